@@ -0,0 +1,390 @@
+use std::{collections::HashSet, time::Duration};
+
+use serde_json::Value;
+use twilight_model::channel::embed::{Embed, EmbedField};
+
+use crate::{
+    backend::LogLine,
+    channels::{self, EventKind},
+    error::Result,
+    format::escape_markdown,
+    queue::{self, Outgoing},
+    verify::now,
+    State,
+};
+
+/// How often to check the ban list for new bans.
+const BAN_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The longest an embed description can be.
+const DESCRIPTION_LIMIT: usize = 4096;
+
+/// The kinds of events sent to admin channels, as named in `admin-events`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminKind {
+    Ban,
+    Moderation,
+    Console,
+    Roles,
+    HostCommand,
+    Wipe,
+    Link,
+    Unlink,
+    Error,
+    Log,
+    Start,
+    Stop,
+}
+
+impl AdminKind {
+    /// Every kind of admin event.
+    pub const ALL: &'static [Self] = &[
+        Self::Ban,
+        Self::Moderation,
+        Self::Console,
+        Self::Roles,
+        Self::HostCommand,
+        Self::Wipe,
+        Self::Link,
+        Self::Unlink,
+        Self::Error,
+        Self::Log,
+        Self::Start,
+        Self::Stop,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ban => "ban",
+            Self::Moderation => "moderation",
+            Self::Console => "console",
+            Self::Roles => "roles",
+            Self::HostCommand => "host-command",
+            Self::Wipe => "wipe",
+            Self::Link => "link",
+            Self::Unlink => "unlink",
+            Self::Error => "error",
+            Self::Log => "log",
+            Self::Start => "start",
+            Self::Stop => "stop",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|k| k.name() == name)
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Self::Ban => "Player banned",
+            Self::Moderation => "Moderation from Discord",
+            Self::Console => "Console command",
+            Self::Roles => "Roles changed",
+            Self::HostCommand => "Host command",
+            Self::Wipe => "Verification store wiped",
+            Self::Link => "Account linked",
+            Self::Unlink => "Account unlinked",
+            Self::Error => "Plugin error",
+            Self::Log => "Plugin log",
+            Self::Start => "Server started",
+            Self::Stop => "Plugin stopping",
+        }
+    }
+
+    fn color(self) -> u32 {
+        match self {
+            Self::Ban | Self::Moderation | Self::Wipe | Self::Error => 0xf04747,
+            Self::Unlink | Self::Stop => 0xfaa61a,
+            Self::Link | Self::Start => 0x43b581,
+            Self::Console | Self::Roles | Self::HostCommand | Self::Log => 0x7289da,
+        }
+    }
+}
+
+/// Something staff may want to know about, sent to admin channels as an embed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdminEvent {
+    pub kind: AdminKind,
+
+    /// Who did it, as markdown.
+    pub actor: Option<String>,
+
+    /// Who it was done to, as markdown.
+    pub target: Option<String>,
+    pub details: String,
+}
+
+impl AdminEvent {
+    pub fn new(kind: AdminKind, details: impl Into<String>) -> Self {
+        Self {
+            kind,
+            actor: None,
+            target: None,
+            details: details.into(),
+        }
+    }
+
+    fn embed(&self, time: u64) -> Embed {
+        let field = |name: &str, value: &Option<String>| {
+            value.clone().map(|value| EmbedField {
+                inline: true,
+                name: name.into(),
+                value,
+            })
+        };
+
+        let description = match self.details.char_indices().nth(DESCRIPTION_LIMIT - 1) {
+            Some((i, _)) => format!("{}…", &self.details[..i]),
+            None => self.details.clone(),
+        };
+
+        Embed {
+            author: None,
+            color: Some(self.kind.color()),
+            description: Some(description).filter(|d| !d.is_empty()),
+            fields: field("Actor", &self.actor)
+                .into_iter()
+                .chain(field("Target", &self.target))
+                .collect(),
+            footer: None,
+            image: None,
+            kind: "rich".into(),
+            provider: None,
+            thumbnail: None,
+            timestamp: Some(timestamp(time)),
+            title: Some(self.kind.title().into()),
+            url: None,
+            video: None,
+        }
+    }
+}
+
+/// Format a UNIX time in seconds as an ISO 8601 timestamp, as Discord expects in embeds.
+pub fn timestamp(time: u64) -> String {
+    // from http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = time / 86400;
+    let seconds = time % 86400;
+
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Whether any channel is sent admin events.
+fn has_channel(state: &State) -> bool {
+    channels::bindings(&state.config(), state.channel_id)
+        .iter()
+        .any(|b| b.receives(EventKind::Admin))
+}
+
+/// Send an event to every admin channel, if its kind is in `admin-events`.
+pub fn log(state: &State, event: AdminEvent) {
+    let config = state.config();
+    if !config.admin_events.iter().any(|k| k == event.kind.name()) {
+        return;
+    }
+
+    let embed = event.embed(now());
+    for binding in channels::bindings(&config, state.channel_id)
+        .iter()
+        .filter(|b| b.receives(EventKind::Admin))
+    {
+        queue::push(
+            state,
+            Outgoing {
+                channel_id: binding.channel_id,
+                author: None,
+                content: String::new(),
+                embeds: vec![embed.clone()],
+                mentions: vec![],
+                batch: false,
+            },
+        );
+    }
+}
+
+/// A ban read from the ban list.
+#[derive(Debug, Clone, PartialEq)]
+struct Ban {
+    id: String,
+    banner: String,
+    reason: String,
+    expires: String,
+}
+
+/// Read the bans in the ban list, which omegga gives as the contents of `BanList.json`.
+fn parse_bans(list: &Value) -> Vec<Ban> {
+    let field = |ban: &Value, key: &str| {
+        ban.get(key)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned()
+    };
+
+    list.get("banList")
+        .and_then(Value::as_object)
+        .map(|bans| {
+            bans.iter()
+                .map(|(id, ban)| Ban {
+                    id: id.clone(),
+                    banner: field(ban, "bannerId"),
+                    reason: field(ban, "reason"),
+                    expires: field(ban, "expires"),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A player's name if they're online, or their UUID. The UUID is also used if the player
+/// can't be looked up, so a ban is never skipped over it.
+async fn describe_player(state: &State, id: &str) -> String {
+    match state.omegga.get_player(id).await {
+        Ok(Some(player)) => escape_markdown(&player.name),
+        _ => format!("`{}`", id),
+    }
+}
+
+/// Log any bans that weren't in the ban list last time it was checked.
+///
+/// Omegga doesn't tell plugins about bans, so this is how they're noticed. The first check
+/// only remembers the bans that are already there.
+pub async fn check_bans(state: &State, known: &mut Option<HashSet<String>>) -> Result<()> {
+    let bans = parse_bans(&state.omegga.get_ban_list().await?);
+
+    if let Some(known) = known.as_ref() {
+        for ban in bans.iter().filter(|b| !known.contains(&b.id)) {
+            let mut details = match ban.reason.as_str() {
+                "" => "No reason given.".to_owned(),
+                reason => format!("Reason: {}", escape_markdown(reason)),
+            };
+            if !ban.expires.is_empty() {
+                details.push_str(&format!("\nExpires: {}", ban.expires));
+            }
+
+            log(
+                state,
+                AdminEvent {
+                    actor: Some(describe_player(state, &ban.banner).await),
+                    target: Some(describe_player(state, &ban.id).await),
+                    ..AdminEvent::new(AdminKind::Ban, details)
+                },
+            );
+        }
+    }
+
+    *known = Some(bans.into_iter().map(|b| b.id).collect());
+    Ok(())
+}
+
+/// Forward the plugin's errors and logs to admin channels, and watch for bans, in the
+/// background.
+pub fn spawn(state: State) {
+    let mut lines = state.omegga.forward_logs();
+    let task_state = state.clone();
+    tokio::spawn(async move {
+        let state = task_state;
+        while let Some(line) = lines.recv().await {
+            let event = match line {
+                LogLine::Log(line) => AdminEvent::new(AdminKind::Log, line),
+                LogLine::Error(line) => AdminEvent::new(AdminKind::Error, line),
+            };
+            log(&state, event);
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut known = None;
+        loop {
+            if !has_channel(&state) {
+                known = None;
+            } else if let Err(e) = check_bans(&state, &mut known).await {
+                state
+                    .omegga
+                    .local_error(format!("Error checking the ban list: {}", e));
+            }
+
+            tokio::time::sleep(BAN_INTERVAL).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{parse_bans, timestamp, AdminEvent, AdminKind, Ban};
+
+    #[test]
+    fn timestamps_are_iso_8601() {
+        assert_eq!(timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(timestamp(1_633_046_461), "2021-10-01T00:01:01Z");
+    }
+
+    #[test]
+    fn embeds_show_the_actor_and_target() {
+        let embed = AdminEvent {
+            actor: Some("host".into()),
+            target: Some("x".into()),
+            ..AdminEvent::new(AdminKind::Unlink, "")
+        }
+        .embed(0);
+
+        assert_eq!(embed.title.as_deref(), Some("Account unlinked"));
+        assert_eq!(embed.description, None);
+        assert_eq!(
+            embed
+                .fields
+                .iter()
+                .map(|f| (f.name.as_str(), f.value.as_str()))
+                .collect::<Vec<_>>(),
+            [("Actor", "host"), ("Target", "x")]
+        );
+    }
+
+    #[test]
+    fn bans_are_parsed() {
+        let list = json!({
+            "banList": {
+                "a": { "bannerId": "b", "reason": "griefing", "expires": "soon" },
+                "c": {},
+            }
+        });
+
+        assert_eq!(
+            parse_bans(&list),
+            [
+                Ban {
+                    id: "a".into(),
+                    banner: "b".into(),
+                    reason: "griefing".into(),
+                    expires: "soon".into(),
+                },
+                Ban {
+                    id: "c".into(),
+                    banner: String::new(),
+                    reason: String::new(),
+                    expires: String::new(),
+                },
+            ]
+        );
+        assert!(parse_bans(&json!(null)).is_empty());
+    }
+}
@@ -0,0 +1,555 @@
+use std::sync::{Arc, Mutex};
+
+use futures::future::BoxFuture;
+use omegga::{resources::Player, Omegga, ResponseError};
+use serde_json::Value;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use twilight_http::Client as HttpClient;
+use twilight_model::{
+    application::{
+        callback::{CallbackData, InteractionResponse},
+        command::Command,
+    },
+    channel::{
+        embed::Embed,
+        message::{AllowedMentions, MessageFlags},
+    },
+    id::{ApplicationId, ChannelId, GuildId, InteractionId, MessageId, RoleId, UserId},
+};
+
+use crate::{error::Result, webhook::Webhook};
+
+// The plugin only talks to the game and Discord through these traits, so they can be swapped
+// out for the fakes in `fake.rs` when testing.
+
+/// What the plugin asks of the game server, through omegga.
+pub trait GameBackend: Send + Sync {
+    fn log(&self, line: String);
+    fn error(&self, line: String);
+    fn warn(&self, line: String);
+
+    /// Run a console command on the server.
+    fn writeln(&self, line: String);
+    fn broadcast(&self, line: String);
+    fn whisper(&self, user: String, line: String);
+
+    fn store_get(&self, key: String) -> BoxFuture<'_, Result<Option<Value>, ResponseError>>;
+    fn store_set(&self, key: String, value: Value);
+    fn store_delete(&self, key: String) -> BoxFuture<'_, ()>;
+    fn store_keys(&self) -> BoxFuture<'_, Result<Vec<String>, ResponseError>>;
+
+    fn get_players(&self) -> BoxFuture<'_, Result<Vec<Player>, ResponseError>>;
+    fn get_player(&self, target: String) -> BoxFuture<'_, Result<Option<Player>, ResponseError>>;
+    fn get_player_roles(
+        &self,
+        target: String,
+    ) -> BoxFuture<'_, Result<Option<Vec<String>>, ResponseError>>;
+
+    /// Get the server's ban list, as in `BanList.json`.
+    fn get_ban_list(&self) -> BoxFuture<'_, Result<Value, ResponseError>>;
+
+    /// Get the server's status, including its brick count.
+    fn get_server_status(&self) -> BoxFuture<'_, Result<Value, ResponseError>>;
+
+    /// Get the minigames running on the server.
+    fn get_minigames(&self) -> BoxFuture<'_, Result<Value, ResponseError>>;
+
+    /// Clear the bricks of a player, by their UUID.
+    fn clear_bricks(&self, target: String);
+}
+
+impl GameBackend for Omegga {
+    fn log(&self, line: String) {
+        Omegga::log(self, line)
+    }
+
+    fn error(&self, line: String) {
+        Omegga::error(self, line)
+    }
+
+    fn warn(&self, line: String) {
+        Omegga::warn(self, line)
+    }
+
+    fn writeln(&self, line: String) {
+        Omegga::writeln(self, line)
+    }
+
+    fn broadcast(&self, line: String) {
+        Omegga::broadcast(self, line)
+    }
+
+    fn whisper(&self, user: String, line: String) {
+        Omegga::whisper(self, user, line)
+    }
+
+    fn store_get(&self, key: String) -> BoxFuture<'_, Result<Option<Value>, ResponseError>> {
+        Box::pin(Omegga::store_get(self, key))
+    }
+
+    fn store_set(&self, key: String, value: Value) {
+        Omegga::store_set(self, key, value)
+    }
+
+    fn store_delete(&self, key: String) -> BoxFuture<'_, ()> {
+        Box::pin(Omegga::store_delete(self, key))
+    }
+
+    fn store_keys(&self) -> BoxFuture<'_, Result<Vec<String>, ResponseError>> {
+        Box::pin(Omegga::store_keys(self))
+    }
+
+    fn get_players(&self) -> BoxFuture<'_, Result<Vec<Player>, ResponseError>> {
+        Box::pin(Omegga::get_players(self))
+    }
+
+    fn get_player(&self, target: String) -> BoxFuture<'_, Result<Option<Player>, ResponseError>> {
+        Box::pin(Omegga::get_player(self, target))
+    }
+
+    fn get_player_roles(
+        &self,
+        target: String,
+    ) -> BoxFuture<'_, Result<Option<Vec<String>>, ResponseError>> {
+        Box::pin(Omegga::get_player_roles(self, target))
+    }
+
+    fn get_ban_list(&self) -> BoxFuture<'_, Result<Value, ResponseError>> {
+        // omegga's own wrapper panics when there's no ban list
+        Box::pin(async move {
+            let list = self.request("getBanList", None).await?;
+            Ok(list.unwrap_or_default())
+        })
+    }
+
+    fn get_server_status(&self) -> BoxFuture<'_, Result<Value, ResponseError>> {
+        Box::pin(async move {
+            let status = self.request("getServerStatus", None).await?;
+            Ok(status.unwrap_or_default())
+        })
+    }
+
+    fn get_minigames(&self) -> BoxFuture<'_, Result<Value, ResponseError>> {
+        Box::pin(async move {
+            let minigames = self.request("getMinigames", None).await?;
+            Ok(minigames.unwrap_or_default())
+        })
+    }
+
+    fn clear_bricks(&self, target: String) {
+        Omegga::clear_bricks(self, target, false)
+    }
+}
+
+/// A line logged by the plugin, forwarded with [`Game::forward_logs`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogLine {
+    Log(String),
+    Error(String),
+}
+
+/// The game server, with the same interface as [`Omegga`].
+#[derive(Clone)]
+pub struct Game {
+    backend: Arc<dyn GameBackend>,
+    forward: Arc<Mutex<Option<UnboundedSender<LogLine>>>>,
+}
+
+impl Game {
+    pub fn new(backend: Arc<dyn GameBackend>) -> Self {
+        Self {
+            backend,
+            forward: Arc::default(),
+        }
+    }
+
+    /// Receive a copy of every line logged from now on, replacing any previous receiver.
+    pub fn forward_logs(&self) -> UnboundedReceiver<LogLine> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        *self.forward.lock().unwrap() = Some(tx);
+        rx
+    }
+
+    fn forward(&self, line: LogLine) {
+        if let Some(tx) = self.forward.lock().unwrap().as_ref() {
+            let _ = tx.send(line);
+        }
+    }
+
+    pub fn log(&self, line: impl Into<String>) {
+        let line = line.into();
+        self.forward(LogLine::Log(line.clone()));
+        self.backend.log(line)
+    }
+
+    pub fn error(&self, line: impl Into<String>) {
+        let line = line.into();
+        self.forward(LogLine::Error(line.clone()));
+        self.backend.error(line)
+    }
+
+    /// Log an error without forwarding it, for errors that forwarding would run into again.
+    pub fn local_error(&self, line: impl Into<String>) {
+        self.backend.error(line.into())
+    }
+
+    pub fn warn(&self, line: impl Into<String>) {
+        self.backend.warn(line.into())
+    }
+
+    pub fn writeln(&self, line: impl Into<String>) {
+        self.backend.writeln(line.into())
+    }
+
+    pub fn broadcast(&self, line: impl Into<String>) {
+        self.backend.broadcast(line.into())
+    }
+
+    pub fn whisper(&self, user: impl Into<String>, line: impl Into<String>) {
+        self.backend.whisper(user.into(), line.into())
+    }
+
+    pub async fn store_get(&self, key: impl Into<String>) -> Result<Option<Value>, ResponseError> {
+        self.backend.store_get(key.into()).await
+    }
+
+    pub fn store_set(&self, key: impl Into<String>, value: Value) {
+        self.backend.store_set(key.into(), value)
+    }
+
+    pub async fn store_delete(&self, key: impl Into<String>) {
+        self.backend.store_delete(key.into()).await
+    }
+
+    pub async fn store_keys(&self) -> Result<Vec<String>, ResponseError> {
+        self.backend.store_keys().await
+    }
+
+    pub async fn get_players(&self) -> Result<Vec<Player>, ResponseError> {
+        self.backend.get_players().await
+    }
+
+    pub async fn get_player(
+        &self,
+        target: impl Into<String>,
+    ) -> Result<Option<Player>, ResponseError> {
+        self.backend.get_player(target.into()).await
+    }
+
+    pub async fn get_player_roles(
+        &self,
+        target: impl Into<String>,
+    ) -> Result<Option<Vec<String>>, ResponseError> {
+        self.backend.get_player_roles(target.into()).await
+    }
+
+    pub async fn get_ban_list(&self) -> Result<Value, ResponseError> {
+        self.backend.get_ban_list().await
+    }
+
+    pub async fn get_server_status(&self) -> Result<Value, ResponseError> {
+        self.backend.get_server_status().await
+    }
+
+    pub async fn get_minigames(&self) -> Result<Value, ResponseError> {
+        self.backend.get_minigames().await
+    }
+
+    pub fn clear_bricks(&self, target: impl Into<String>) {
+        self.backend.clear_bricks(target.into())
+    }
+}
+
+/// A message to send to a Discord channel.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NewMessage {
+    pub content: String,
+    pub embeds: Vec<Embed>,
+
+    /// The message this one replies to, if any.
+    pub reply: Option<MessageId>,
+
+    /// The users the message may mention. Nobody else is pinged.
+    pub mentions: Vec<UserId>,
+}
+
+/// A message to execute through a webhook.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WebhookMessage {
+    pub content: String,
+    pub username: String,
+    pub avatar_url: Option<String>,
+
+    /// The users the message may mention. Nobody else is pinged.
+    pub mentions: Vec<UserId>,
+}
+
+/// What the plugin asks of Discord. Everything read from the gateway goes through the cache
+/// instead, which can be fed events directly.
+pub trait DiscordBackend: Send + Sync {
+    fn send_message(
+        &self,
+        channel: ChannelId,
+        message: NewMessage,
+    ) -> BoxFuture<'_, Result<MessageId>>;
+
+    /// Replace a message's content with embeds.
+    fn update_embeds(
+        &self,
+        channel: ChannelId,
+        message: MessageId,
+        embeds: Vec<Embed>,
+    ) -> BoxFuture<'_, Result<()>>;
+
+    fn execute_webhook(
+        &self,
+        webhook: Webhook,
+        message: WebhookMessage,
+    ) -> BoxFuture<'_, Result<()>>;
+
+    fn rename_channel(&self, channel: ChannelId, name: String) -> BoxFuture<'_, Result<()>>;
+
+    /// Open a direct message channel with a user.
+    fn private_channel(&self, user: UserId) -> BoxFuture<'_, Result<ChannelId>>;
+
+    fn add_role(&self, guild: GuildId, user: UserId, role: RoleId) -> BoxFuture<'_, Result<()>>;
+    fn remove_role(&self, guild: GuildId, user: UserId, role: RoleId) -> BoxFuture<'_, Result<()>>;
+    fn set_nick(
+        &self,
+        guild: GuildId,
+        user: UserId,
+        nick: Option<String>,
+    ) -> BoxFuture<'_, Result<()>>;
+
+    /// Fetch the roles of a guild member.
+    fn member_roles(&self, guild: GuildId, user: UserId) -> BoxFuture<'_, Result<Vec<RoleId>>>;
+
+    /// Fetch the avatar hash of a user.
+    fn user_avatar(&self, user: UserId) -> BoxFuture<'_, Result<Option<String>>>;
+
+    fn set_application_id(&self, application: ApplicationId);
+    fn set_guild_commands(
+        &self,
+        guild: GuildId,
+        commands: Vec<Command>,
+    ) -> BoxFuture<'_, Result<()>>;
+
+    /// Acknowledge an application command, to be replied to later.
+    fn defer_interaction(
+        &self,
+        interaction: InteractionId,
+        token: String,
+        ephemeral: bool,
+    ) -> BoxFuture<'_, Result<()>>;
+
+    /// Reply to a deferred application command.
+    fn update_interaction(&self, token: String, content: String) -> BoxFuture<'_, Result<()>>;
+
+    /// Send another reply to an application command, after the first.
+    fn followup_interaction(
+        &self,
+        token: String,
+        content: String,
+        ephemeral: bool,
+    ) -> BoxFuture<'_, Result<()>>;
+}
+
+/// The mentions allowed in a message sent to Discord. Nobody is pinged except the given users.
+fn allowed_mentions(users: &[UserId]) -> AllowedMentions {
+    AllowedMentions::builder()
+        .user_ids(users.iter().copied())
+        .build()
+}
+
+impl DiscordBackend for HttpClient {
+    fn send_message(
+        &self,
+        channel: ChannelId,
+        message: NewMessage,
+    ) -> BoxFuture<'_, Result<MessageId>> {
+        Box::pin(async move {
+            let mut request = self
+                .create_message(channel)
+                .allowed_mentions(allowed_mentions(&message.mentions));
+
+            if !message.content.is_empty() {
+                request = request.content(&message.content)?;
+            }
+            if !message.embeds.is_empty() {
+                request = request.embeds(&message.embeds)?;
+            }
+            if let Some(reply) = message.reply {
+                request = request.reply(reply);
+            }
+
+            Ok(request.exec().await?.model().await?.id)
+        })
+    }
+
+    fn update_embeds(
+        &self,
+        channel: ChannelId,
+        message: MessageId,
+        embeds: Vec<Embed>,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.update_message(channel, message)
+                .content(None)?
+                .embeds(&embeds)?
+                .exec()
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn execute_webhook(
+        &self,
+        webhook: Webhook,
+        message: WebhookMessage,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let mut request = HttpClient::execute_webhook(self, webhook.id, &webhook.token)
+                .content(&message.content)
+                .username(&message.username)
+                .allowed_mentions(allowed_mentions(&message.mentions));
+
+            if let Some(avatar) = message.avatar_url.as_deref() {
+                request = request.avatar_url(avatar);
+            }
+
+            request.exec().await?;
+            Ok(())
+        })
+    }
+
+    fn rename_channel(&self, channel: ChannelId, name: String) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.update_channel(channel).name(&name)?.exec().await?;
+            Ok(())
+        })
+    }
+
+    fn private_channel(&self, user: UserId) -> BoxFuture<'_, Result<ChannelId>> {
+        Box::pin(async move {
+            Ok(self
+                .create_private_channel(user)
+                .exec()
+                .await?
+                .model()
+                .await?
+                .id)
+        })
+    }
+
+    fn add_role(&self, guild: GuildId, user: UserId, role: RoleId) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.add_guild_member_role(guild, user, role).exec().await?;
+            Ok(())
+        })
+    }
+
+    fn remove_role(&self, guild: GuildId, user: UserId, role: RoleId) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.remove_guild_member_role(guild, user, role)
+                .exec()
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn set_nick(
+        &self,
+        guild: GuildId,
+        user: UserId,
+        nick: Option<String>,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.update_guild_member(guild, user)
+                .nick(nick.as_deref())?
+                .exec()
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn member_roles(&self, guild: GuildId, user: UserId) -> BoxFuture<'_, Result<Vec<RoleId>>> {
+        Box::pin(async move {
+            let member = self.guild_member(guild, user).exec().await?;
+            Ok(member.model().await?.roles)
+        })
+    }
+
+    fn user_avatar(&self, user: UserId) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move { Ok(self.user(user).exec().await?.model().await?.avatar) })
+    }
+
+    fn set_application_id(&self, application: ApplicationId) {
+        HttpClient::set_application_id(self, application);
+    }
+
+    fn set_guild_commands(
+        &self,
+        guild: GuildId,
+        commands: Vec<Command>,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            HttpClient::set_guild_commands(self, guild, &commands)?
+                .exec()
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn defer_interaction(
+        &self,
+        interaction: InteractionId,
+        token: String,
+        ephemeral: bool,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.interaction_callback(
+                interaction,
+                &token,
+                &InteractionResponse::DeferredChannelMessageWithSource(CallbackData {
+                    allowed_mentions: None,
+                    components: None,
+                    content: None,
+                    embeds: vec![],
+                    flags: ephemeral.then_some(MessageFlags::EPHEMERAL),
+                    tts: None,
+                }),
+            )
+            .exec()
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn update_interaction(&self, token: String, content: String) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.update_interaction_original(&token)?
+                .content(Some(&content))?
+                .allowed_mentions(allowed_mentions(&[]))
+                .exec()
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn followup_interaction(
+        &self,
+        token: String,
+        content: String,
+        ephemeral: bool,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.create_followup_message(&token)?
+                .content(&content)
+                .ephemeral(ephemeral)
+                .allowed_mentions(&allowed_mentions(&[]))
+                .exec()
+                .await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,287 @@
+use twilight_model::id::ChannelId;
+
+use crate::{
+    config::Config,
+    format::{format_content, Formatter},
+    queue::{self, Outgoing},
+    State,
+};
+
+/// The options that can be overridden per channel in `channel-formats`.
+pub const CHANNEL_FORMATS: &[&str] = &[
+    "game-message-format",
+    "discord-message-format",
+    "join-message-format",
+    "leave-message-format",
+    "server-start-format",
+];
+
+/// Which way messages go through a bridged channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Both,
+
+    /// Only messages from the game are sent to the channel.
+    ToDiscord,
+
+    /// Only messages in the channel are sent to the game.
+    ToGame,
+}
+
+impl Direction {
+    fn parse(direction: &str) -> Option<Self> {
+        match direction {
+            "both" => Some(Self::Both),
+            "to-discord" => Some(Self::ToDiscord),
+            "to-game" => Some(Self::ToGame),
+            _ => None,
+        }
+    }
+
+    pub fn to_discord(self) -> bool {
+        self != Self::ToGame
+    }
+
+    pub fn to_game(self) -> bool {
+        self != Self::ToDiscord
+    }
+}
+
+/// The kinds of game events a channel can receive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Chat,
+    JoinLeave,
+    Start,
+    Admin,
+}
+
+impl EventKind {
+    fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "chat" => Some(Self::Chat),
+            "join-leave" => Some(Self::JoinLeave),
+            "start" => Some(Self::Start),
+            "admin" => Some(Self::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// A Discord channel bridged with the game.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub channel_id: ChannelId,
+    pub direction: Direction,
+
+    /// The game events sent to the channel.
+    pub events: Vec<EventKind>,
+
+    /// Shown in game before messages from the channel, to tell channels apart.
+    pub prefix: String,
+
+    /// The color of the prefix in game, as hexadecimal.
+    pub color: String,
+
+    /// Formats overriding the ones in the config, by option name.
+    pub formats: Vec<(String, String)>,
+}
+
+impl Binding {
+    /// Parse a `channels` entry, formatted like `CHANNEL ID:DIRECTION:EVENTS:PREFIX:COLOR`
+    /// where the prefix and color are optional.
+    pub fn parse(entry: &str) -> Option<Self> {
+        let mut parts = entry.splitn(5, ':').map(str::trim);
+        let channel_id = ChannelId(parts.next()?.parse().ok().filter(|id| *id != 0)?);
+        let direction = Direction::parse(parts.next()?)?;
+        let events = match parts.next()? {
+            "" => vec![],
+            events => events
+                .split(',')
+                .map(|e| EventKind::parse(e.trim()))
+                .collect::<Option<_>>()?,
+        };
+
+        Some(Self {
+            channel_id,
+            direction,
+            events,
+            prefix: parts.next().unwrap_or_default().to_owned(),
+            color: parts.next().unwrap_or_default().to_owned(),
+            formats: vec![],
+        })
+    }
+
+    /// The primary channel, `channel-id`, when it isn't configured in `channels`.
+    fn primary(channel_id: ChannelId) -> Self {
+        Self {
+            channel_id,
+            direction: Direction::Both,
+            events: vec![EventKind::Chat, EventKind::JoinLeave, EventKind::Start],
+            prefix: String::new(),
+            color: String::new(),
+            formats: vec![],
+        }
+    }
+
+    /// Whether the channel is sent a kind of game event.
+    pub fn receives(&self, kind: EventKind) -> bool {
+        self.direction.to_discord() && self.events.contains(&kind)
+    }
+
+    /// The format to use for an option in this channel.
+    pub fn format(&self, config: &Config, option: &str) -> String {
+        if let Some((_, format)) = self.formats.iter().find(|(o, _)| o == option) {
+            return format.clone();
+        }
+
+        match option {
+            "game-message-format" => config.game_message_format.clone(),
+            "discord-message-format" => config.discord_message_format.clone(),
+            "join-message-format" => config.join_message_format.clone(),
+            "leave-message-format" => config.leave_message_format.clone(),
+            "server-start-format" => config.server_start_format.clone(),
+            _ => String::new(),
+        }
+    }
+
+    /// Put the channel's prefix before a message going to the game.
+    pub fn prefixed(&self, message: String) -> String {
+        match (self.prefix.as_str(), self.color.as_str()) {
+            ("", _) => message,
+            (prefix, "") => format!("{} {}", prefix, message),
+            (prefix, color) => format!("<color=\"{}\">{}</> {}", color, prefix, message),
+        }
+    }
+}
+
+/// Parse a `channel-formats` entry, formatted like `CHANNEL ID:OPTION:FORMAT`.
+pub fn parse_format(entry: &str) -> Option<(ChannelId, String, String)> {
+    let mut parts = entry.splitn(3, ':');
+    let channel_id = ChannelId(parts.next()?.trim().parse().ok()?);
+    let option = parts.next()?.trim();
+    let format = parts.next()?;
+
+    CHANNEL_FORMATS
+        .contains(&option)
+        .then(|| (channel_id, option.to_owned(), format.to_owned()))
+}
+
+/// Every bridged channel. `channel-id` is always bridged both ways with chat, joins, leaves
+/// and server starts, unless it's configured differently in `channels`.
+pub fn bindings(config: &Config, primary: ChannelId) -> Vec<Binding> {
+    let mut bindings = config
+        .channels
+        .iter()
+        .filter_map(|entry| Binding::parse(entry))
+        .collect::<Vec<_>>();
+
+    if !bindings.iter().any(|b| b.channel_id == primary) {
+        bindings.insert(0, Binding::primary(primary));
+    }
+
+    for (channel_id, option, format) in config
+        .channel_formats
+        .iter()
+        .filter_map(|e| parse_format(e))
+    {
+        if let Some(binding) = bindings.iter_mut().find(|b| b.channel_id == channel_id) {
+            binding.formats.push((option, format));
+        }
+    }
+
+    bindings
+}
+
+/// The bridged channel a Discord message was sent in, if any.
+pub fn binding(state: &State, channel_id: ChannelId) -> Option<Binding> {
+    bindings(&state.config(), state.channel_id)
+        .into_iter()
+        .find(|b| b.channel_id == channel_id)
+}
+
+/// Queue a message for every channel receiving a kind of event, formatting `option` with the
+/// channel's format. Channels whose format leaves nothing to send are skipped.
+pub fn relay(
+    state: &State,
+    kind: EventKind,
+    option: &str,
+    formatters: &[Formatter],
+    message: Outgoing,
+) {
+    let config = state.config();
+    for binding in bindings(&config, state.channel_id)
+        .iter()
+        .filter(|b| b.receives(kind))
+    {
+        let content = format_content(binding.format(&config, option), formatters);
+        if content.is_empty() && message.embeds.is_empty() {
+            continue;
+        }
+
+        queue::push(
+            state,
+            Outgoing {
+                channel_id: binding.channel_id,
+                content,
+                ..message.clone()
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use twilight_model::id::ChannelId;
+
+    use super::{bindings, Binding, Direction, EventKind};
+    use crate::config::Config;
+
+    #[test]
+    fn bindings_are_parsed() {
+        assert_eq!(
+            Binding::parse("5:to-discord:join-leave,start"),
+            Some(Binding {
+                channel_id: ChannelId(5),
+                direction: Direction::ToDiscord,
+                events: vec![EventKind::JoinLeave, EventKind::Start],
+                prefix: String::new(),
+                color: String::new(),
+                formats: vec![],
+            })
+        );
+        assert_eq!(
+            Binding::parse("5:both:chat:[Staff]:f80").map(|b| b.prefixed("hi".into())),
+            Some("<color=\"f80\">[Staff]</> hi".into())
+        );
+        assert!(Binding::parse("5:to-game:").is_some());
+        assert!(Binding::parse("5:sideways:chat").is_none());
+        assert!(Binding::parse("5:both:chat,kicks").is_none());
+        assert!(Binding::parse("channel:both:chat").is_none());
+    }
+
+    #[test]
+    fn primary_channel_is_bridged_by_default() {
+        let config = Config {
+            channels: vec!["2:to-discord:join-leave".into(), "1:to-game:".into()],
+            channel_formats: vec!["2:join-message-format:$user is here".into()],
+            ..Default::default()
+        };
+
+        let bindings = bindings(&config, ChannelId(1));
+        assert_eq!(bindings.len(), 2);
+        assert_eq!(bindings[1].direction, Direction::ToGame);
+        assert!(!bindings[1].receives(EventKind::Chat));
+        assert_eq!(
+            bindings[0].format(&config, "join-message-format"),
+            "$user is here"
+        );
+
+        let bindings = super::bindings(&Config::default(), ChannelId(1));
+        assert!(bindings[0].receives(EventKind::Chat));
+        assert_eq!(
+            bindings[0].format(&Config::default(), "join-message-format"),
+            "**$user joined the game.**"
+        );
+    }
+}
@@ -0,0 +1,468 @@
+use twilight_model::{
+    application::command::{
+        BaseCommandOptionData, ChoiceCommandOptionData, Command, CommandOption, CommandType,
+    },
+    id::{GuildId, RoleId, UserId},
+};
+
+use crate::{
+    admin::{self, AdminEvent, AdminKind},
+    console, digest,
+    discord::{self, update_unverified, update_verified},
+    error::Result,
+    format::{describe_duration, escape_markdown, role_text, sanitize},
+    link, moderation, playtime, status,
+    verify::{self, Attempt},
+    whois, State,
+};
+
+/// Someone running a command from Discord.
+#[derive(Debug, Clone)]
+pub struct Invoker {
+    pub id: UserId,
+    pub name: String,
+    pub guild_id: Option<GuildId>,
+    pub roles: Vec<RoleId>,
+}
+
+impl Invoker {
+    /// The invoker's roles, from the cache when their member is cached as it's kept up to date.
+    pub fn member_roles(&self, state: &State) -> Vec<RoleId> {
+        self.guild_id
+            .and_then(|guild_id| state.cache.member(guild_id, self.id))
+            .map(|member| member.roles)
+            .unwrap_or_else(|| self.roles.clone())
+    }
+
+    /// Whether the invoker has one of the `admin-roles`.
+    pub fn is_admin(&self, state: &State) -> bool {
+        self.member_roles(state)
+            .iter()
+            .any(|r| state.config().admin_roles.contains(&r.to_string()))
+    }
+}
+
+/// How commands can be run from Discord.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandMode {
+    /// Only application (slash) commands.
+    Slash,
+
+    /// Only messages starting with `discord-prefix`.
+    Prefix,
+
+    /// Both of the above.
+    Both,
+}
+
+impl CommandMode {
+    pub fn parse(mode: &str) -> Self {
+        match mode {
+            "slash" => Self::Slash,
+            "prefix" => Self::Prefix,
+            _ => Self::Both,
+        }
+    }
+
+    pub fn slash(self) -> bool {
+        self != Self::Prefix
+    }
+
+    pub fn prefix(self) -> bool {
+        self != Self::Slash
+    }
+}
+
+/// How a user should write a command in Discord, like `/verify` or `!verify`.
+pub fn hint(state: &State, command: &str) -> String {
+    if state.command_mode().slash() {
+        format!("/{}", command)
+    } else {
+        format!("{}{}", state.config().discord_prefix, command)
+    }
+}
+
+/// Define an application command.
+pub fn command(name: &str, description: &str, options: Vec<CommandOption>) -> Command {
+    Command {
+        application_id: None,
+        guild_id: None,
+        name: name.into(),
+        default_permission: None,
+        description: description.into(),
+        id: None,
+        kind: CommandType::ChatInput,
+        options,
+    }
+}
+
+/// The application commands to register with Discord.
+pub fn definitions(state: &State) -> Vec<Command> {
+    let mut commands = vec![
+        command("players", "List the players online in game.", vec![]),
+        command("status", "Show the status of the game server.", vec![]),
+        command(
+            "whois",
+            "Look up a player, or the player a Discord user is linked to.",
+            vec![CommandOption::String(ChoiceCommandOptionData {
+                choices: vec![],
+                description: "A player's name or UUID, or a Discord user's mention or ID.".into(),
+                name: "player".into(),
+                required: true,
+            })],
+        ),
+    ];
+
+    commands.extend(playtime::definitions());
+    commands.push(command(
+        "digest",
+        "Preview the activity digest so far.",
+        vec![],
+    ));
+
+    if state.config().verification {
+        commands.push(command(
+            "verify",
+            "Link your Discord account to your Brickadia player.",
+            vec![CommandOption::String(ChoiceCommandOptionData {
+                choices: vec![],
+                description:
+                    "The code from running /discord verify in game. Leave blank to resync.".into(),
+                name: "code".into(),
+                required: false,
+            })],
+        ));
+        commands.push(command(
+            "unverify",
+            "Unlink your Discord account from your Brickadia player.",
+            vec![
+                CommandOption::User(BaseCommandOptionData {
+                    description: "Admins only: the Discord user to unlink.".into(),
+                    name: "user".into(),
+                    required: false,
+                }),
+                CommandOption::String(ChoiceCommandOptionData {
+                    choices: vec![],
+                    description: "Admins only: the name or UUID of the player to unlink.".into(),
+                    name: "player".into(),
+                    required: false,
+                }),
+            ],
+        ));
+    }
+
+    if moderation::enabled(&state.config()) {
+        commands.extend(moderation::definitions());
+    }
+
+    if !state.config().console_owners.is_empty() {
+        commands.push(command(
+            "console",
+            "Owners only: run a command in the server console.",
+            vec![CommandOption::String(ChoiceCommandOptionData {
+                choices: vec![],
+                description: "The console command to run.".into(),
+                name: "command".into(),
+                required: true,
+            })],
+        ));
+    }
+
+    commands
+}
+
+/// Whether a command's reply should only be shown to whoever ran it, when possible.
+pub fn is_ephemeral(command: &str) -> bool {
+    command == "verify" || command == "console"
+}
+
+/// Run a command, returning the reply to send, if any.
+pub async fn run(
+    state: &State,
+    invoker: &Invoker,
+    command: &str,
+    args: &str,
+) -> Result<Option<String>> {
+    state.omegga.log(format!(
+        "{} is trying to run {} with {}",
+        invoker.name, command, args
+    ));
+
+    match command {
+        "players" => players(state).await.map(Some),
+        "status" => status(state).await.map(Some),
+        "whois" => whois::run(state, args.trim()).await.map(Some),
+        "digest" => Ok(Some(digest::preview(state))),
+        command if playtime::COMMANDS.contains(&command) => {
+            playtime::run(state, invoker, command, args.trim())
+                .await
+                .map(Some)
+        }
+        "verify" if state.config().verification => {
+            verify(state, invoker, args.trim()).await.map(Some)
+        }
+        "unverify" if state.config().verification => {
+            unverify(state, invoker, args.trim()).await.map(Some)
+        }
+        "console" if !state.config().console_owners.is_empty() => {
+            console::run(state, invoker, args.trim()).await.map(Some)
+        }
+        command if moderation::COMMANDS.contains(&command) => {
+            moderation::run(state, invoker, command, args.trim())
+                .await
+                .map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The online players as markdown lines, each prefixed by their role text.
+pub async fn player_lines(state: &State) -> Result<Vec<String>> {
+    let mut lines = vec![];
+    for player in state.omegga.get_players().await? {
+        let roles = state
+            .omegga
+            .get_player_roles(&player.name)
+            .await?
+            .unwrap_or_default();
+        let role_text = role_text(&roles, &state.config().game_roles);
+
+        lines.push(format!(
+            "{}{}{}",
+            role_text,
+            if !role_text.is_empty() { " " } else { "" },
+            escape_markdown(&player.name)
+        ));
+    }
+
+    Ok(lines)
+}
+
+async fn players(state: &State) -> Result<String> {
+    let players = player_lines(state).await?;
+    if players.is_empty() {
+        return Ok(String::from("**There are no players online.**"));
+    }
+
+    let mut response = format!(
+        "**There {} {} player{} online.**\n",
+        if players.len() == 1 { "is" } else { "are" },
+        players.len(),
+        if players.len() == 1 { "" } else { "s" }
+    );
+
+    for line in players.iter() {
+        response.push_str(line);
+        response.push('\n');
+    }
+
+    Ok(response)
+}
+
+async fn status(state: &State) -> Result<String> {
+    let info = status::gather(state).await?;
+    Ok(format!(
+        "**The server is online** on {}, and has been up for {}.\n\
+        **Players:** {}\n\
+        **Bricks:** {}\n\
+        **Minigames:** {}\n\
+        **Latency:** {} to the server, {} to Discord",
+        escape_markdown(&info.describe_map()),
+        describe_duration(info.uptime()),
+        info.describe_players(),
+        info.describe_bricks(),
+        escape_markdown(&info.describe_minigames()),
+        info.describe_latency(),
+        info.describe_bot_latency(),
+    ))
+}
+
+async fn verify(state: &State, invoker: &Invoker, code: &str) -> Result<String> {
+    if code.is_empty() {
+        let linked = link::linked_player(state, invoker.id)
+            .await?
+            .unwrap_or_default();
+
+        return match state.omegga.get_player(&linked).await? {
+            Some(player) => {
+                // update on discord
+                update_verified(state, invoker, &player.name).await?;
+                Ok(String::from("**Synced verification with game.**"))
+            }
+            None => Ok(String::from("**You are not verified!** Start the verification process by running `/discord verify` in-game.")),
+        };
+    }
+
+    let key = match state.verifications.attempt(invoker.id, code) {
+        Attempt::Success(key) => key,
+        Attempt::Expired(key) => {
+            verify::persist(state);
+
+            // let the player know too, if they're still around
+            if let Some(player) = state.omegga.get_player(&key).await? {
+                state.omegga.whisper(
+                    &player.name,
+                    "<color=\"a00\">Your verification code expired.</> Run <code>/discord verify</> for a new one.",
+                );
+            }
+
+            return Ok(String::from(
+                "**That code has expired!** Run `/discord verify` in-game for a new one.",
+            ));
+        }
+        Attempt::Invalid { remaining } => {
+            return Ok(format!(
+                "**There is no pending verification with that code!** You have {} attempt{} left.",
+                remaining,
+                if remaining == 1 { "" } else { "s" }
+            ))
+        }
+        Attempt::Locked(seconds) => {
+            return Ok(format!(
+                "**Too many failed attempts!** Try again in {}.",
+                describe_duration(seconds)
+            ))
+        }
+    };
+    verify::persist(state);
+
+    // the player may have left since asking for the code, so go by their last known name
+    let online = state.omegga.get_player(&key).await?;
+    let name = match online.as_ref() {
+        Some(player) => player.name.clone(),
+        None => whois::player_name(state, &key)
+            .await?
+            .unwrap_or_else(|| key.clone()),
+    };
+
+    // add to the database, revoking the verification of whoever the player was linked to before
+    if let Some(previous) = link::link(state, &key, invoker.id).await? {
+        update_unverified(state, previous).await?;
+    }
+
+    // update on discord
+    update_verified(state, invoker, &name).await?;
+    admin::log(
+        state,
+        AdminEvent {
+            actor: Some(format!("<@{}>", invoker.id)),
+            target: Some(escape_markdown(&name)),
+            ..AdminEvent::new(AdminKind::Link, "Verified from Discord.")
+        },
+    );
+
+    // confirm in-game, if they're still around
+    if online.is_some() {
+        state.omegga.whisper(
+            &name,
+            format!(
+                "<color=\"0a0\"><b>Success!</></> You've been verified as <b>{}</> in Discord.",
+                sanitize(&invoker.name)
+            ),
+        );
+    }
+
+    // confirm to the user that they've been verified in discord
+    Ok(format!(
+        "**Success!** You've been verified as **{}** in Brickadia.",
+        escape_markdown(&name)
+    ))
+}
+
+/// Parse a Discord user from a mention like `<@123>`, or a plain ID.
+pub fn parse_user(text: &str) -> Option<UserId> {
+    let id = text
+        .strip_prefix("<@")
+        .and_then(|t| t.strip_suffix('>'))
+        .map(|t| t.trim_start_matches('!'))
+        .unwrap_or(text);
+
+    id.parse().ok().map(UserId)
+}
+
+async fn unverify(state: &State, invoker: &Invoker, target: &str) -> Result<String> {
+    if target.is_empty() {
+        return Ok(match link::unlink_user(state, invoker.id).await? {
+            Some(player) => {
+                update_unverified(state, invoker.id).await?;
+                let name = match state.omegga.get_player(&player).await? {
+                    Some(player) => {
+                        state.omegga.whisper(
+                            &player.name,
+                            format!(
+                                "<color=\"a00\">Your Discord account <b>{}</> has been unlinked.</>",
+                                sanitize(&invoker.name)
+                            ),
+                        );
+                        escape_markdown(&player.name)
+                    }
+                    None => format!("`{}`", player),
+                };
+                admin::log(
+                    state,
+                    AdminEvent {
+                        actor: Some(format!("<@{}>", invoker.id)),
+                        target: Some(name),
+                        ..AdminEvent::new(AdminKind::Unlink, "Unlinked from Discord.")
+                    },
+                );
+                String::from("**Your Discord account has been unlinked from Brickadia.**")
+            }
+            None => String::from("**You are not verified!**"),
+        });
+    }
+
+    if !invoker.is_admin(state) {
+        return Ok(String::from(
+            "**You don't have permission to unverify other users!**",
+        ));
+    }
+
+    // the target is either a discord user, or the name or UUID of a player
+    let (user, player) = match parse_user(target) {
+        Some(user) => (Some(user), link::unlink_user(state, user).await?),
+        None => {
+            let player = match state.omegga.get_player(target).await? {
+                Some(player) => player.id,
+                None => target.to_owned(),
+            };
+            (link::unlink_player(state, &player).await?, Some(player))
+        }
+    };
+
+    let (user, player) = match (user, player) {
+        (Some(user), Some(player)) => (user, player),
+        _ => return Ok(String::from("**That user is not verified!**")),
+    };
+
+    update_unverified(state, user).await?;
+
+    // let both sides know
+    let _ = discord::direct_message(
+        state,
+        user,
+        "**Your Discord account has been unlinked from Brickadia by an admin.**",
+    )
+    .await;
+
+    let name = match state.omegga.get_player(&player).await? {
+        Some(player) => {
+            state.omegga.whisper(
+                &player.name,
+                "<color=\"a00\">Your Discord account has been unlinked by an admin.</>",
+            );
+            escape_markdown(&player.name)
+        }
+        None => format!("`{}`", player),
+    };
+    admin::log(
+        state,
+        AdminEvent {
+            actor: Some(format!("<@{}>", invoker.id)),
+            target: Some(format!("{} (<@{}>)", name, user)),
+            ..AdminEvent::new(AdminKind::Unlink, "Unlinked from Discord by an admin.")
+        },
+    );
+
+    Ok(format!("**Unlinked <@{}> from {}.**", user, name))
+}
@@ -0,0 +1,754 @@
+use std::{
+    fs::File,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use twilight_model::id::ChannelId;
+
+use crate::{
+    admin::AdminKind,
+    channels::{self, Binding, CHANNEL_FORMATS},
+    commands::{self, CommandMode},
+    digest,
+    error::{Error, Result},
+    moderation, roles, status,
+    webhook::Webhook,
+    State,
+};
+
+/// Where the config is read from.
+pub const CONFIG_PATH: &str = "config.json";
+
+/// How often to check if the config file has changed.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The plugin config, read from `config.json`. Missing options take their defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub token: String,
+
+    #[serde(rename = "channel-id")]
+    pub channel_id: String,
+
+    pub channels: Vec<String>,
+
+    #[serde(rename = "channel-formats")]
+    pub channel_formats: Vec<String>,
+
+    #[serde(rename = "admin-events")]
+    pub admin_events: Vec<String>,
+
+    #[serde(rename = "discord-link")]
+    pub discord_link: String,
+
+    #[serde(rename = "channel-name-online-format")]
+    pub channel_name_online_format: String,
+
+    #[serde(rename = "discord-prefix")]
+    pub discord_prefix: String,
+
+    #[serde(rename = "command-mode")]
+    pub command_mode: String,
+
+    #[serde(rename = "stat-channel-id")]
+    pub stat_channel_id: String,
+
+    #[serde(rename = "max-players")]
+    pub max_players: u64,
+
+    #[serde(rename = "status-channel-id")]
+    pub status_channel_id: String,
+
+    pub digest: String,
+
+    #[serde(rename = "digest-time")]
+    pub digest_time: String,
+
+    #[serde(rename = "digest-day")]
+    pub digest_day: String,
+
+    #[serde(rename = "digest-timezone")]
+    pub digest_timezone: String,
+
+    #[serde(rename = "game-message-format")]
+    pub game_message_format: String,
+
+    #[serde(rename = "discord-message-format")]
+    pub discord_message_format: String,
+
+    #[serde(rename = "join-message-format")]
+    pub join_message_format: String,
+
+    #[serde(rename = "leave-message-format")]
+    pub leave_message_format: String,
+
+    #[serde(rename = "server-start-format")]
+    pub server_start_format: String,
+
+    #[serde(rename = "attachment-format")]
+    pub attachment_format: String,
+
+    #[serde(rename = "sticker-format")]
+    pub sticker_format: String,
+
+    #[serde(rename = "reply-format")]
+    pub reply_format: String,
+
+    #[serde(rename = "embed-format")]
+    pub embed_format: String,
+
+    #[serde(rename = "game-roles")]
+    pub game_roles: Vec<String>,
+
+    #[serde(rename = "discord-roles")]
+    pub discord_roles: Vec<String>,
+
+    #[serde(rename = "trusted-roles")]
+    pub trusted_roles: Vec<String>,
+
+    #[serde(rename = "admin-roles")]
+    pub admin_roles: Vec<String>,
+
+    #[serde(rename = "moderation-roles")]
+    pub moderation_roles: Vec<String>,
+
+    #[serde(rename = "console-owners")]
+    pub console_owners: Vec<String>,
+
+    #[serde(rename = "console-denylist")]
+    pub console_denylist: Vec<String>,
+
+    #[serde(rename = "discord-emojis")]
+    pub discord_emojis: Vec<String>,
+
+    #[serde(rename = "resolve-mentions")]
+    pub resolve_mentions: bool,
+
+    #[serde(rename = "mention-player-names")]
+    pub mention_player_names: bool,
+
+    pub verification: bool,
+
+    #[serde(rename = "verified-role")]
+    pub verified_role: String,
+
+    #[serde(rename = "verified-nickname")]
+    pub verified_nickname: bool,
+
+    #[serde(rename = "verify-code-ttl")]
+    pub verify_code_ttl: u64,
+
+    #[serde(rename = "verify-max-attempts")]
+    pub verify_max_attempts: u32,
+
+    #[serde(rename = "verify-cooldown")]
+    pub verify_cooldown: u64,
+
+    #[serde(rename = "verify-persist")]
+    pub verify_persist: bool,
+
+    #[serde(rename = "role-sync")]
+    pub role_sync: Vec<String>,
+
+    #[serde(rename = "role-sync-reverse")]
+    pub role_sync_reverse: bool,
+
+    #[serde(rename = "role-sync-interval")]
+    pub role_sync_interval: u64,
+
+    #[serde(rename = "role-sync-dry-run")]
+    pub role_sync_dry_run: bool,
+
+    #[serde(rename = "queue-size")]
+    pub queue_size: usize,
+
+    #[serde(rename = "queue-drop-policy")]
+    pub queue_drop_policy: String,
+
+    #[serde(rename = "webhook-url")]
+    pub webhook_url: String,
+
+    #[serde(rename = "webhook-create")]
+    pub webhook_create: bool,
+
+    #[serde(rename = "webhook-username-format")]
+    pub webhook_username_format: String,
+
+    #[serde(rename = "webhook-avatar-format")]
+    pub webhook_avatar_format: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            token: String::new(),
+            channel_id: String::new(),
+            channels: vec![],
+            channel_formats: vec![],
+            admin_events: AdminKind::ALL
+                .iter()
+                .filter(|k| **k != AdminKind::Log)
+                .map(|k| k.name().into())
+                .collect(),
+            discord_link: "https://discord.com/".into(),
+            channel_name_online_format: String::new(),
+            discord_prefix: "!".into(),
+            command_mode: "both".into(),
+            stat_channel_id: String::new(),
+            max_players: 0,
+            status_channel_id: String::new(),
+            digest: "off".into(),
+            digest_time: "00:00".into(),
+            digest_day: "monday".into(),
+            digest_timezone: "UTC".into(),
+            game_message_format: "<color=\"$color\"><b>$user</b></>: $message".into(),
+            discord_message_format: "**$user**: $message".into(),
+            join_message_format: "**$user joined the game.**".into(),
+            leave_message_format: "**$user left the game.**".into(),
+            server_start_format: "**The server has started.**".into(),
+            attachment_format: "<link=\"$url\">[$name, $size]</>".into(),
+            sticker_format: "[sticker: $name]".into(),
+            reply_format: "<color=\"aaaaaa\">[reply to $user: $message]</>".into(),
+            embed_format: String::new(),
+            game_roles: vec![],
+            discord_roles: vec!["@everyone:<emoji>egg</>".into()],
+            trusted_roles: vec![],
+            admin_roles: vec![],
+            moderation_roles: vec![],
+            console_owners: vec![],
+            console_denylist: vec!["exit".into(), "quit".into()],
+            discord_emojis: vec![],
+            resolve_mentions: false,
+            mention_player_names: false,
+            verification: false,
+            verified_role: String::new(),
+            verified_nickname: false,
+            verify_code_ttl: 600,
+            verify_max_attempts: 5,
+            verify_cooldown: 300,
+            verify_persist: true,
+            role_sync: vec![],
+            role_sync_reverse: false,
+            role_sync_interval: 300,
+            role_sync_dry_run: false,
+            queue_size: 100,
+            queue_drop_policy: "oldest".into(),
+            webhook_url: String::new(),
+            webhook_create: false,
+            webhook_username_format: "$role $user".into(),
+            webhook_avatar_format: String::new(),
+        }
+    }
+}
+
+fn is_snowflake(id: &str) -> bool {
+    matches!(id.parse::<u64>(), Ok(id) if id != 0)
+}
+
+/// The `$keys` in a template that aren't one of the given formatters.
+///
+/// Keys are matched by prefix like when formatting, so `$users` is `$user` followed by `s`.
+fn unknown_keys(template: &str, keys: &[&str]) -> Vec<String> {
+    template
+        .split('$')
+        .skip(1)
+        .map(|rest| {
+            rest.chars()
+                .take_while(|c| c.is_ascii_alphabetic())
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty() && !keys.iter().any(|k| word.starts_with(k)))
+        .collect()
+}
+
+/// The formatters a format option has.
+fn format_keys(option: &str) -> &'static [&'static str] {
+    match option {
+        "game-message-format" => &["role", "user", "message", "color"],
+        "discord-message-format" => &["role", "user", "message"],
+        "join-message-format" | "leave-message-format" => &[
+            "role",
+            "user",
+            "players",
+            "max",
+            "map",
+            "uptime",
+            "bricks",
+            "minigames",
+            "latency",
+            "ping",
+        ],
+        "server-start-format" => &[
+            "players",
+            "max",
+            "map",
+            "uptime",
+            "bricks",
+            "minigames",
+            "latency",
+            "ping",
+        ],
+        "attachment-format" => &["name", "url", "size"],
+        "sticker-format" => &["name"],
+        "reply-format" => &["user", "message"],
+        "embed-format" => &["title", "url"],
+        "channel-name-online-format" => status::KEYS,
+        "webhook-username-format" => &["role", "user"],
+        "webhook-avatar-format" => &["role", "user", "id"],
+        _ => &[],
+    }
+}
+
+/// Describe any unknown formatters in a template for `option`, set in the option `source`.
+fn check_template(problems: &mut Vec<String>, source: &str, option: &str, template: &str) {
+    let keys = format_keys(option);
+    let source = if source == option {
+        option.to_owned()
+    } else {
+        format!("{} for {}", source, option)
+    };
+
+    for key in unknown_keys(template, keys) {
+        problems.push(format!(
+            "{} has an unknown formatter ${} (expected one of {})",
+            source,
+            key,
+            keys.iter()
+                .map(|k| format!("${}", k))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+}
+
+impl Config {
+    /// Read and validate the config file.
+    pub fn read(path: &str) -> Result<Self> {
+        let config = serde_json::from_reader::<_, Self>(File::open(path)?)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the config for invalid values, describing every problem found.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = vec![];
+
+        if !is_snowflake(&self.channel_id) {
+            problems.push(format!(
+                "channel-id {:?} must be a channel ID",
+                self.channel_id
+            ));
+        }
+
+        for (option, id) in [
+            ("status-channel-id", &self.status_channel_id),
+            ("stat-channel-id", &self.stat_channel_id),
+            ("verified-role", &self.verified_role),
+        ] {
+            if !id.is_empty() && !is_snowflake(id) {
+                problems.push(format!("{} {:?} must be blank or an ID", option, id));
+            }
+        }
+
+        for (option, ids) in [
+            ("trusted-roles", &self.trusted_roles),
+            ("admin-roles", &self.admin_roles),
+        ] {
+            for id in ids.iter().filter(|id| !is_snowflake(id)) {
+                problems.push(format!("{} entry {:?} must be a role ID", option, id));
+            }
+        }
+
+        for (option, entries) in [
+            ("game-roles", &self.game_roles),
+            ("discord-roles", &self.discord_roles),
+        ] {
+            for entry in entries {
+                if !matches!(entry.split_once(':'), Some((role, _)) if !role.is_empty()) {
+                    problems.push(format!(
+                        "{} entry {:?} must look like ROLENAME:TEXT",
+                        option, entry
+                    ));
+                }
+            }
+        }
+
+        for id in self.console_owners.iter().filter(|id| !is_snowflake(id)) {
+            problems.push(format!("console-owners entry {:?} must be a user ID", id));
+        }
+
+        for entry in self.moderation_roles.iter() {
+            if moderation::parse_role(entry).is_none() {
+                problems.push(format!(
+                    "moderation-roles entry {:?} must look like COMMAND:ROLE ID, with a command \
+                     from *, {}",
+                    entry,
+                    moderation::COMMANDS.join(", ")
+                ));
+            }
+        }
+
+        for entry in self.role_sync.iter() {
+            if roles::parse_mappings(std::slice::from_ref(entry)).is_empty() {
+                problems.push(format!(
+                    "role-sync entry {:?} must look like ROLE ID:BRICKADIA ROLE",
+                    entry
+                ));
+            }
+        }
+
+        let templates = [
+            ("game-message-format", &self.game_message_format),
+            ("discord-message-format", &self.discord_message_format),
+            ("join-message-format", &self.join_message_format),
+            ("leave-message-format", &self.leave_message_format),
+            ("server-start-format", &self.server_start_format),
+            ("attachment-format", &self.attachment_format),
+            ("sticker-format", &self.sticker_format),
+            ("reply-format", &self.reply_format),
+            ("embed-format", &self.embed_format),
+            (
+                "channel-name-online-format",
+                &self.channel_name_online_format,
+            ),
+            ("webhook-username-format", &self.webhook_username_format),
+            ("webhook-avatar-format", &self.webhook_avatar_format),
+        ];
+        for (option, template) in templates {
+            check_template(&mut problems, option, option, template);
+        }
+
+        for entry in self.channels.iter() {
+            if Binding::parse(entry).is_none() {
+                problems.push(format!(
+                    "channels entry {:?} must look like CHANNEL ID:DIRECTION:EVENTS:PREFIX:COLOR, \
+                     with a direction of both, to-discord or to-game, and events from chat, \
+                     join-leave, start and admin",
+                    entry
+                ));
+            }
+        }
+
+        let channels = self.channel_id.parse().ok().map(ChannelId);
+        let bindings = channels
+            .map(|id| channels::bindings(self, id))
+            .unwrap_or_default();
+        for entry in self.channel_formats.iter() {
+            match channels::parse_format(entry) {
+                Some((id, _, _)) if !bindings.iter().any(|b| b.channel_id == id) => {
+                    problems.push(format!(
+                        "channel-formats entry {:?} is for a channel that isn't bridged",
+                        entry
+                    ))
+                }
+                Some((_, option, format)) => {
+                    check_template(&mut problems, "channel-formats", &option, &format)
+                }
+                None => problems.push(format!(
+                    "channel-formats entry {:?} must look like CHANNEL ID:OPTION:FORMAT, with an \
+                     option from {}",
+                    entry,
+                    CHANNEL_FORMATS.join(", ")
+                )),
+            }
+        }
+
+        for entry in self.admin_events.iter() {
+            if AdminKind::parse(entry).is_none() {
+                problems.push(format!(
+                    "admin-events entry {:?} must be one of {}",
+                    entry,
+                    AdminKind::ALL
+                        .iter()
+                        .map(|k| k.name())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+        }
+
+        if !["prefix", "slash", "both"].contains(&self.command_mode.as_str()) {
+            problems.push(format!(
+                "command-mode {:?} must be prefix, slash or both",
+                self.command_mode
+            ));
+        }
+
+        if !["off", "daily", "weekly"].contains(&self.digest.as_str()) {
+            problems.push(format!(
+                "digest {:?} must be off, daily or weekly",
+                self.digest
+            ));
+        }
+
+        if digest::parse_time(&self.digest_time).is_none() {
+            problems.push(format!(
+                "digest-time {:?} must be a 24 hour time like 18:00",
+                self.digest_time
+            ));
+        }
+
+        if digest::parse_weekday(&self.digest_day).is_none() {
+            problems.push(format!(
+                "digest-day {:?} must be a day of the week like monday",
+                self.digest_day
+            ));
+        }
+
+        if digest::parse_timezone(&self.digest_timezone).is_none() {
+            problems.push(format!(
+                "digest-timezone {:?} must be UTC or an offset from it like UTC+2 or -05:30",
+                self.digest_timezone
+            ));
+        }
+
+        if !["oldest", "newest"].contains(&self.queue_drop_policy.as_str()) {
+            problems.push(format!(
+                "queue-drop-policy {:?} must be oldest or newest",
+                self.queue_drop_policy
+            ));
+        }
+
+        if !self.webhook_url.is_empty() && Webhook::parse(&self.webhook_url).is_none() {
+            problems.push("webhook-url must be a Discord webhook URL".into());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Config(problems.join("; ")))
+        }
+    }
+
+    /// The options that changed from another config which only take effect after a restart.
+    pub fn restart_required(&self, other: &Self) -> Vec<&'static str> {
+        let mut options = vec![];
+        let mut check = |option, changed| {
+            if changed {
+                options.push(option);
+            }
+        };
+
+        check("token", self.token != other.token);
+        check("channel-id", self.channel_id != other.channel_id);
+        check(
+            "status-channel-id",
+            self.status_channel_id != other.status_channel_id,
+        );
+        check(
+            "stat-channel-id",
+            self.stat_channel_id != other.stat_channel_id,
+        );
+        check(
+            "channel-name-online-format",
+            self.channel_name_online_format.is_empty()
+                != other.channel_name_online_format.is_empty(),
+        );
+        // members are only watched if roles were synced at startup
+        check(
+            "role-sync",
+            self.role_sync.is_empty() != other.role_sync.is_empty(),
+        );
+        check("queue-size", self.queue_size != other.queue_size);
+        check(
+            "queue-drop-policy",
+            self.queue_drop_policy != other.queue_drop_policy,
+        );
+        check("webhook-url", self.webhook_url != other.webhook_url);
+        check(
+            "webhook-create",
+            self.webhook_create != other.webhook_create,
+        );
+
+        options
+    }
+}
+
+/// The current config, which can be swapped out while the plugin is running.
+#[derive(Clone)]
+pub struct SharedConfig(Arc<RwLock<Arc<Config>>>);
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn replace(&self, config: Config) -> Arc<Config> {
+        std::mem::replace(&mut *self.0.write().unwrap(), Arc::new(config))
+    }
+}
+
+/// Swap in a new config, if it's valid. Everything reading the config picks it up from here on,
+/// and the Discord connection is kept.
+pub async fn reload(state: &State, config: Config) {
+    if let Err(e) = config.validate() {
+        state
+            .omegga
+            .error(format!("Not reloading the config: {}", e));
+        return;
+    }
+
+    let old = state.config.replace(config);
+    let config = state.config();
+    if *old == *config {
+        return;
+    }
+
+    let restart = old.restart_required(&config);
+    if !restart.is_empty() {
+        state.omegga.warn(format!(
+            "Changes to {} will take effect after a restart.",
+            restart.join(", ")
+        ));
+    }
+
+    state.verifications.set_limits(
+        config.verify_code_ttl,
+        config.verify_max_attempts,
+        config.verify_cooldown,
+    );
+    state.status.request_update();
+    state.channel_name.request_update();
+
+    // the commands depend on the config, like whether verification is on
+    let definitions_changed = CommandMode::parse(&old.command_mode).slash()
+        != state.command_mode().slash()
+        || old.verification != config.verification
+        || moderation::enabled(&old) != moderation::enabled(&config)
+        || old.console_owners.is_empty() != config.console_owners.is_empty();
+    if definitions_changed && state.command_mode().slash() {
+        if let Some(guild_id) = crate::discord::guild_id(state) {
+            if let Err(e) = state
+                .discord
+                .set_guild_commands(guild_id, commands::definitions(state))
+                .await
+            {
+                state
+                    .omegga
+                    .error(format!("Error registering commands: {}", e));
+            }
+        }
+    }
+
+    state.omegga.log("Reloaded the config.");
+}
+
+/// Reload the config from the parameters omegga sends with `init`, if it sent any.
+pub async fn reload_from_params(state: &State, params: Option<Value>) {
+    let value = match params {
+        Some(Value::Array(values)) => values.into_iter().next(),
+        value => value,
+    };
+
+    // omegga may send other things with init, so only take what looks like our config
+    match value {
+        Some(value) if value.get("channel-id").is_some() => match serde_json::from_value(value) {
+            Ok(config) => reload(state, config).await,
+            Err(e) => state
+                .omegga
+                .error(format!("Not reloading the config: {}", e)),
+        },
+        _ => (),
+    }
+}
+
+fn modified() -> Option<SystemTime> {
+    std::fs::metadata(CONFIG_PATH).ok()?.modified().ok()
+}
+
+/// Reload the config in the background whenever the file changes.
+pub fn spawn_watcher(state: State) {
+    tokio::spawn(async move {
+        let mut last = modified();
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let current = modified();
+            if current == last {
+                continue;
+            }
+            last = current;
+
+            match Config::read(CONFIG_PATH) {
+                Ok(config) => reload(&state, config).await,
+                Err(e) => state
+                    .omegga
+                    .error(format!("Not reloading the config: {}", e)),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::{unknown_keys, Config};
+    use crate::error::Error;
+
+    fn valid() -> Config {
+        Config {
+            channel_id: "100".into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn defaults_match_doc() {
+        let doc = serde_json::from_str::<Value>(include_str!("../doc.json")).unwrap();
+        let defaults = doc["config"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.clone(), v["default"].clone()))
+            .collect::<serde_json::Map<_, _>>();
+
+        assert_eq!(
+            serde_json::from_value::<Config>(Value::Object(defaults)).unwrap(),
+            Config::default()
+        );
+        assert_eq!(
+            serde_json::from_value::<Config>(json!({})).unwrap(),
+            Config::default()
+        );
+    }
+
+    #[test]
+    fn unknown_formatters_are_found() {
+        assert!(unknown_keys("**$user**: $message, $5", &["user", "message"]).is_empty());
+        assert!(unknown_keys("$users", &["user"]).is_empty());
+        assert_eq!(unknown_keys("$usr $map", &["user"]), ["usr", "map"]);
+    }
+
+    #[test]
+    fn invalid_configs_describe_every_problem() {
+        assert!(valid().validate().is_ok());
+        assert!(Config::default().validate().is_err());
+
+        let config = Config {
+            channel_id: "12a".into(),
+            verified_role: "role".into(),
+            game_roles: vec!["Admin".into()],
+            role_sync: vec!["Admin:Admin".into()],
+            join_message_format: "$name joined".into(),
+            ..valid()
+        };
+        match config.validate() {
+            Err(Error::Config(problems)) => {
+                assert_eq!(problems.split("; ").count(), 5, "{}", problems)
+            }
+            result => panic!("{:?}", result),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::{sync::Mutex, time::Duration};
+
+use twilight_model::id::UserId;
+
+use crate::{
+    admin::{self, AdminEvent, AdminKind},
+    commands::Invoker,
+    config::Config,
+    error::Result,
+    State,
+};
+
+/// How long to capture console output for after running a command.
+const CAPTURE_WINDOW: Duration = Duration::from_secs(2);
+
+/// The most console lines kept from one command.
+const MAX_LINES: usize = 200;
+
+/// Console lines captured while a command from Discord runs.
+///
+/// Only one command is captured at a time, so their output isn't mixed up.
+#[derive(Default)]
+pub struct Capture {
+    lines: Mutex<Option<Vec<String>>>,
+    running: tokio::sync::Mutex<()>,
+}
+
+impl Capture {
+    /// Keep a console line, if a command is being captured.
+    pub fn push(&self, line: &str) {
+        if let Some(lines) = self.lines.lock().unwrap().as_mut() {
+            if lines.len() < MAX_LINES {
+                lines.push(strip_prefix(line).to_owned());
+            }
+        }
+    }
+
+    fn start(&self) {
+        *self.lines.lock().unwrap() = Some(vec![]);
+    }
+
+    fn finish(&self) -> Vec<String> {
+        self.lines.lock().unwrap().take().unwrap_or_default()
+    }
+}
+
+/// Remove the `[time][frame]` prefix from a line of the server log.
+fn strip_prefix(line: &str) -> &str {
+    let mut rest = line;
+    for _ in 0..2 {
+        match rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+            Some((_, r)) => rest = r,
+            None => break,
+        }
+    }
+    rest
+}
+
+/// Whether a Discord user is one of the `console-owners`.
+pub fn is_owner(config: &Config, user: UserId) -> bool {
+    config.console_owners.contains(&user.to_string())
+}
+
+/// The `console-denylist` entry a command is blocked by, if any. Entries block every command
+/// starting with them, ignoring case.
+pub fn denied_by<'a>(config: &'a Config, command: &str) -> Option<&'a str> {
+    let command = command.trim().to_lowercase();
+    config
+        .console_denylist
+        .iter()
+        .map(|e| e.trim())
+        .find(|e| !e.is_empty() && command.starts_with(&e.to_lowercase()))
+}
+
+/// Show console output in a code block, so it's shown as-is.
+fn code_block(lines: &[String]) -> String {
+    // a line can't end the code block early
+    let output = lines.join("\n").replace("```", "`\u{200b}``");
+    format!("```\n{}\n```", output)
+}
+
+/// Run a console command for an owner, returning the output.
+pub async fn run(state: &State, invoker: &Invoker, command: &str) -> Result<String> {
+    let config = state.config();
+    let audit = |outcome: &str| {
+        state.omegga.log(format!(
+            "{} ({}) ran console command {:?}: {}",
+            invoker.name, invoker.id, command, outcome
+        ));
+        admin::log(
+            state,
+            AdminEvent {
+                actor: Some(format!("<@{}>", invoker.id)),
+                ..AdminEvent::new(
+                    AdminKind::Console,
+                    format!("{}\n{}", code_block(&[command.to_owned()]), outcome),
+                )
+            },
+        );
+    };
+
+    if !is_owner(&config, invoker.id) {
+        audit("refused, as they're not an owner");
+        return Ok(String::from(
+            "**Only the server's owners can run console commands!**",
+        ));
+    }
+
+    if command.is_empty() {
+        return Ok(String::from("**Please give a command to run.**"));
+    }
+
+    // each line would run as its own command, getting past the denylist
+    if command.contains(['\n', '\r']) {
+        audit("refused, as it has more than one line");
+        return Ok(String::from("**Please run one command at a time.**"));
+    }
+
+    if let Some(entry) = denied_by(&config, command) {
+        audit(&format!("blocked by the denylist entry {:?}", entry));
+        return Ok(String::from(
+            "**That command is blocked by `console-denylist`.**",
+        ));
+    }
+
+    audit("ran");
+    let lines = {
+        let _running = state.console.running.lock().await;
+        state.console.start();
+        state.omegga.writeln(command);
+        tokio::time::sleep(CAPTURE_WINDOW).await;
+        state.console.finish()
+    };
+
+    if lines.is_empty() {
+        return Ok(String::from("**Ran the command.** There was no output."));
+    }
+
+    Ok(format!("**Ran the command.**\n{}", code_block(&lines)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{denied_by, strip_prefix};
+    use crate::config::Config;
+
+    #[test]
+    fn log_prefixes_are_stripped() {
+        assert_eq!(
+            strip_prefix("[2021.10.01-00.00.00:000][123]LogChat: hi"),
+            "LogChat: hi"
+        );
+        assert_eq!(strip_prefix("no prefix [here]"), "no prefix [here]");
+    }
+
+    #[test]
+    fn denylist_blocks_by_prefix() {
+        let config = Config {
+            console_denylist: vec!["Exit".into(), "Chat.Command /Ban".into()],
+            ..Default::default()
+        };
+
+        assert_eq!(denied_by(&config, "exit"), Some("Exit"));
+        assert_eq!(
+            denied_by(&config, " chat.command /ban x"),
+            Some("Chat.Command /Ban")
+        );
+        assert_eq!(denied_by(&config, "Chat.Command /Kick x"), None);
+    }
+}
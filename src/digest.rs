@@ -0,0 +1,478 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use twilight_model::channel::embed::Embed;
+
+use crate::{
+    admin,
+    config::Config,
+    format::escape_markdown,
+    playtime::describe_playtime,
+    queue::{self, Outgoing},
+    verify::now,
+    State,
+};
+
+/// The store key the activity since the last digest is persisted under.
+const STORE_KEY: &str = "digest";
+
+/// How often to check if a digest is due, saving the activity so far.
+const TICK: Duration = Duration::from_secs(60);
+
+/// How many players to show in each top list.
+const TOP_COUNT: usize = 5;
+
+const WEEKDAYS: &[&str] = &[
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+/// Parse a 24 hour time like `18:00`, as minutes into the day.
+pub fn parse_time(text: &str) -> Option<u64> {
+    let (hours, minutes) = text.split_once(':')?;
+    if minutes.len() != 2 {
+        return None;
+    }
+
+    match (hours.parse::<u64>().ok()?, minutes.parse::<u64>().ok()?) {
+        (hours, minutes) if hours < 24 && minutes < 60 => Some(hours * 60 + minutes),
+        _ => None,
+    }
+}
+
+/// Parse the name of a day of the week, as days after Monday.
+pub fn parse_weekday(text: &str) -> Option<u64> {
+    let text = text.to_lowercase();
+    WEEKDAYS
+        .iter()
+        .position(|d| *d == text || (text.len() >= 3 && d.starts_with(&text)))
+        .map(|d| d as u64)
+}
+
+/// Parse a time zone, as `UTC` or an offset like `UTC+2`, `+02:00` or `-5:30`, into seconds
+/// east of UTC.
+pub fn parse_timezone(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let offset = text
+        .strip_prefix("UTC")
+        .or_else(|| text.strip_prefix("GMT"))
+        .unwrap_or(text);
+    if offset.is_empty() {
+        return Some(0);
+    }
+
+    let (sign, offset) = match (offset.strip_prefix('+'), offset.strip_prefix('-')) {
+        (Some(rest), _) => (1, rest),
+        (_, Some(rest)) => (-1, rest),
+        _ => return None,
+    };
+    let (hours, minutes) = offset.split_once(':').unwrap_or((offset, "0"));
+    if !hours
+        .chars()
+        .chain(minutes.chars())
+        .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    match (hours.parse::<i64>().ok()?, minutes.parse::<i64>().ok()?) {
+        (hours, minutes) if hours <= 14 && minutes < 60 => {
+            Some(sign * (hours * 3600 + minutes * 60))
+        }
+        _ => None,
+    }
+}
+
+/// When digests are posted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Schedule {
+    weekly: bool,
+
+    /// Minutes into the day.
+    time: u64,
+
+    /// Days after Monday, for weekly digests.
+    weekday: u64,
+
+    /// Seconds east of UTC.
+    offset: i64,
+}
+
+impl Schedule {
+    /// The schedule set by `digest`, `digest-time`, `digest-day` and `digest-timezone`, unless
+    /// digests are off.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let weekly = match config.digest.as_str() {
+            "daily" => false,
+            "weekly" => true,
+            _ => return None,
+        };
+
+        Some(Self {
+            weekly,
+            time: parse_time(&config.digest_time)?,
+            weekday: parse_weekday(&config.digest_day)?,
+            offset: parse_timezone(&config.digest_timezone)?,
+        })
+    }
+
+    /// The first UNIX time a digest is due after `time`.
+    pub fn next_after(&self, time: u64) -> u64 {
+        let local = time as i64 + self.offset;
+        let day = local.div_euclid(86400);
+
+        // the epoch was a thursday
+        (day..)
+            .map(|day| (day, day * 86400 + self.time as i64 * 60))
+            .find(|(day, due)| {
+                *due > local && (!self.weekly || (day + 3).rem_euclid(7) as u64 == self.weekday)
+            })
+            .map(|(_, due)| (due - self.offset) as u64)
+            .unwrap_or(time)
+    }
+
+    fn title(&self) -> &'static str {
+        if self.weekly {
+            "Weekly digest"
+        } else {
+            "Daily digest"
+        }
+    }
+}
+
+/// The activity since the last digest.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct Counters {
+    /// The UNIX time in seconds the activity was counted from.
+    started: u64,
+
+    /// The playtime in seconds of each player when they were first online, by UUID.
+    playtime: HashMap<String, u64>,
+
+    /// The most players online at once, and when.
+    peak: usize,
+    peak_at: u64,
+
+    /// Chat messages relayed from the game to Discord, and from Discord to the game.
+    to_discord: u64,
+    to_game: u64,
+
+    /// The number of messages relayed from each chatter, by name.
+    chatters: HashMap<String, u64>,
+}
+
+impl Counters {
+    fn chat(&mut self, name: &str) {
+        *self.chatters.entry(name.to_owned()).or_default() += 1;
+    }
+}
+
+/// The activity counted for the next digest.
+#[derive(Debug, Default)]
+pub struct Digest {
+    counters: Mutex<Counters>,
+    dirty: AtomicBool,
+}
+
+impl Digest {
+    /// Record a player being online, with their playtime and the number of players online.
+    pub fn online(&self, player: &str, playtime: u64, players: usize, time: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        counters
+            .playtime
+            .entry(player.to_owned())
+            .or_insert(playtime);
+        if players > counters.peak {
+            counters.peak = players;
+            counters.peak_at = time;
+        }
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Record a chat message relayed from the game to Discord.
+    pub fn chat_to_discord(&self, name: &str) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.to_discord += 1;
+        counters.chat(name);
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Record a chat message relayed from Discord to the game.
+    pub fn chat_to_game(&self, name: &str) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.to_game += 1;
+        counters.chat(name);
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    fn snapshot(&self) -> Counters {
+        self.counters.lock().unwrap().clone()
+    }
+}
+
+/// Describe the activity counted so far as markdown.
+fn describe(state: &State, counters: &Counters, time: u64) -> String {
+    let new = counters
+        .playtime
+        .keys()
+        .filter_map(|id| state.history.get(id))
+        .filter(|r| r.first_seen >= counters.started)
+        .count();
+
+    let mut chatters = counters.chatters.iter().collect::<Vec<_>>();
+    chatters.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+    let mut playtime = counters
+        .playtime
+        .iter()
+        .filter_map(|(id, start)| {
+            let record = state.history.get(id)?;
+            Some((
+                record.name.clone(),
+                record.playtime(time).saturating_sub(*start),
+            ))
+        })
+        .filter(|(_, played)| *played > 0)
+        .collect::<Vec<_>>();
+    playtime.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let list = |items: Vec<String>| {
+        if items.is_empty() {
+            "Nobody".to_owned()
+        } else {
+            items.join(", ")
+        }
+    };
+
+    let mut lines = vec![
+        format!("Activity since <t:{}:f>.", counters.started),
+        format!(
+            "**Players:** {} unique, {} new",
+            counters.playtime.len(),
+            new
+        ),
+    ];
+    if counters.peak > 0 {
+        lines.push(format!(
+            "**Peak:** {} online at <t:{}:f>",
+            counters.peak, counters.peak_at
+        ));
+    }
+    lines.push(format!(
+        "**Chat:** {} message{} to Discord, {} to the game",
+        counters.to_discord,
+        if counters.to_discord == 1 { "" } else { "s" },
+        counters.to_game
+    ));
+    lines.push(format!(
+        "**Top chatters:** {}",
+        list(
+            chatters
+                .into_iter()
+                .take(TOP_COUNT)
+                .map(|(name, n)| format!("{} ({})", escape_markdown(name), n))
+                .collect()
+        )
+    ));
+    lines.push(format!(
+        "**Top playtime:** {}",
+        list(
+            playtime
+                .into_iter()
+                .take(TOP_COUNT)
+                .map(|(name, played)| {
+                    format!("{} ({})", escape_markdown(&name), describe_playtime(played))
+                })
+                .collect()
+        )
+    ));
+
+    lines.join("\n")
+}
+
+/// Preview the next digest as markdown, for the `digest` command.
+pub fn preview(state: &State) -> String {
+    format!(
+        "**Digest preview**\n{}",
+        describe(state, &state.digest.snapshot(), now())
+    )
+}
+
+/// Start counting activity again from now, including the players online.
+async fn reset(state: &State) {
+    let time = now();
+    let mut counters = Counters {
+        started: time,
+        ..Default::default()
+    };
+
+    match state.omegga.get_players().await {
+        Ok(players) => {
+            for player in players.iter() {
+                let playtime = state
+                    .history
+                    .get(&player.id)
+                    .map_or(0, |r| r.playtime(time));
+                counters.playtime.insert(player.id.clone(), playtime);
+            }
+            counters.peak = players.len();
+            counters.peak_at = time;
+        }
+        Err(e) => state
+            .omegga
+            .error(format!("Error getting players for the digest: {}", e)),
+    }
+
+    *state.digest.counters.lock().unwrap() = counters;
+    state.digest.dirty.store(true, Ordering::SeqCst);
+}
+
+/// Post the digest to the bridge channel, and start counting again.
+async fn post(state: &State, schedule: Schedule) {
+    let time = now();
+    let embed = Embed {
+        author: None,
+        color: Some(0x7289da),
+        description: Some(describe(state, &state.digest.snapshot(), time)),
+        fields: vec![],
+        footer: None,
+        image: None,
+        kind: "rich".into(),
+        provider: None,
+        thumbnail: None,
+        timestamp: Some(admin::timestamp(time)),
+        title: Some(schedule.title().into()),
+        url: None,
+        video: None,
+    };
+
+    queue::push(
+        state,
+        Outgoing {
+            channel_id: state.channel_id,
+            author: None,
+            content: String::new(),
+            embeds: vec![embed],
+            mentions: vec![],
+            batch: false,
+        },
+    );
+
+    reset(state).await;
+}
+
+/// Post the digest if it's due.
+pub async fn tick(state: &State) {
+    if let Some(schedule) = Schedule::from_config(&state.config()) {
+        let started = state.digest.counters.lock().unwrap().started;
+        if now() >= schedule.next_after(started) {
+            post(state, schedule).await;
+        }
+    }
+
+    persist(state);
+}
+
+/// Load the activity since the last digest from the store, starting afresh if there's none
+/// that can be read.
+pub async fn restore(state: &State) {
+    match state.omegga.store_get(STORE_KEY).await {
+        Ok(Some(value)) => match serde_json::from_value(value) {
+            Ok(counters) => *state.digest.counters.lock().unwrap() = counters,
+            Err(e) => {
+                state
+                    .omegga
+                    .error(format!("Error reading the stored digest: {}", e));
+                reset(state).await;
+            }
+        },
+        Ok(None) => reset(state).await,
+        Err(e) => {
+            state
+                .omegga
+                .error(format!("Error restoring the digest: {}", e));
+            reset(state).await;
+        }
+    }
+}
+
+/// Save the activity since the last digest to the store, if it's changed.
+pub fn persist(state: &State) {
+    if !state.digest.dirty.swap(false, Ordering::SeqCst) {
+        return;
+    }
+
+    if let Ok(value) = serde_json::to_value(state.digest.snapshot()) {
+        state.omegga.store_set(STORE_KEY, value);
+    }
+}
+
+/// Post digests when they're due in the background.
+pub fn spawn(state: State) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(TICK).await;
+            tick(&state).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_time, parse_timezone, parse_weekday, Schedule};
+
+    #[test]
+    fn schedule_options_are_parsed() {
+        assert_eq!(parse_time("18:30"), Some(18 * 60 + 30));
+        assert_eq!(parse_time("0:00"), Some(0));
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("6pm"), None);
+
+        assert_eq!(parse_weekday("Monday"), Some(0));
+        assert_eq!(parse_weekday("sun"), Some(6));
+        assert_eq!(parse_weekday("s"), None);
+
+        assert_eq!(parse_timezone("UTC"), Some(0));
+        assert_eq!(parse_timezone("UTC+2"), Some(7200));
+        assert_eq!(parse_timezone("-05:30"), Some(-19800));
+        assert_eq!(parse_timezone("EST"), None);
+        assert_eq!(parse_timezone("UTC\u{2212}5"), None);
+        assert_eq!(parse_timezone("UTC+-5"), None);
+    }
+
+    #[test]
+    fn digests_are_due_at_the_next_local_time() {
+        // 2021-10-01 was a friday
+        let friday = 1_633_046_400;
+        let daily = Schedule {
+            weekly: false,
+            time: 18 * 60,
+            weekday: 0,
+            offset: 3600,
+        };
+
+        // 18:00 at UTC+1 is 17:00 UTC
+        assert_eq!(daily.next_after(friday), friday + 17 * 3600);
+        assert_eq!(daily.next_after(friday + 17 * 3600), friday + 41 * 3600);
+
+        let weekly = Schedule {
+            weekly: true,
+            offset: 0,
+            ..daily
+        };
+        assert_eq!(weekly.next_after(friday), friday + 3 * 86400 + 18 * 3600);
+    }
+}
@@ -1,216 +1,524 @@
-use anyhow::Result;
-use dashmap::mapref::entry::Entry;
 use futures::StreamExt;
-use omegga::resources::Player;
-use serde_json::Value;
+use std::collections::HashMap;
 use twilight_gateway::{shard::Events, Event};
+
 use twilight_model::{
-    channel::Message, gateway::payload::MessageCreate, id::RoleId, user::CurrentUser,
+    application::interaction::{
+        application_command::CommandDataOption, ApplicationCommand, Interaction,
+    },
+    channel::{message::Mention, Message},
+    gateway::payload::MessageCreate,
+    id::{ChannelId, GuildId, RoleId, UserId},
 };
 
 use crate::{
-    format::{format_content, format_to_game, role_text, Formatter},
-    State,
+    backend::{NewMessage, WebhookMessage},
+    channels,
+    commands::{self, Invoker},
+    config::Config,
+    error::Result,
+    format::{
+        describe_size, escape_url, format_content, format_to_game, mention_nodes, mentioned_names,
+        role_text, sanitize, shorten, split_message, Formatter, Mentions,
+    },
+    link,
+    markdown::Node,
+    queue::Outgoing,
+    roles,
+    verify::now,
+    webhook, whois, State,
 };
 
-pub async fn reply(state: &State, message: &Message, content: &str) -> Result<()> {
+/// The most characters of a replied-to message quoted in game.
+const REPLY_QUOTE_LIMIT: usize = 40;
+
+/// Render a Discord message for the game: its content, after a quote of the message it
+/// replies to, followed by its attachments, stickers and embeds in their configured formats.
+fn message_to_game(
+    config: &Config,
+    message: &Message,
+    trusted: bool,
+    mentions: &Mentions,
+) -> String {
+    let render = |template: &str, formatters: Vec<(&'static str, String)>| {
+        let formatters = formatters
+            .into_iter()
+            .map(|(key, value)| Formatter { key, value })
+            .collect::<Vec<_>>();
+        format_content(template.to_owned(), &formatters)
+    };
+
+    let mut parts = vec![];
+    if let Some(replied) = message.referenced_message.as_ref() {
+        let user = replied
+            .member
+            .as_ref()
+            .and_then(|m| m.nick.as_ref())
+            .unwrap_or(&replied.author.name);
+        parts.push(render(
+            &config.reply_format,
+            vec![
+                ("user", sanitize(user)),
+                (
+                    "message",
+                    sanitize(&shorten(&replied.content, REPLY_QUOTE_LIMIT)),
+                ),
+            ],
+        ));
+    }
+
+    parts.push(format_to_game(
+        message.content.to_owned(),
+        trusted,
+        mentions,
+    ));
+
+    for attachment in message.attachments.iter() {
+        parts.push(render(
+            &config.attachment_format,
+            vec![
+                ("name", sanitize(&attachment.filename)),
+                ("url", escape_url(&attachment.url)),
+                ("size", describe_size(attachment.size)),
+            ],
+        ));
+    }
+
+    for sticker in message.sticker_items.iter() {
+        parts.push(render(
+            &config.sticker_format,
+            vec![("name", sanitize(&sticker.name))],
+        ));
+    }
+
+    for embed in message.embeds.iter() {
+        if let Some(title) = embed.title.as_ref() {
+            parts.push(render(
+                &config.embed_format,
+                vec![
+                    ("title", sanitize(title)),
+                    (
+                        "url",
+                        embed.url.as_deref().map(escape_url).unwrap_or_default(),
+                    ),
+                ],
+            ));
+        }
+    }
+
+    parts.retain(|part| !part.trim().is_empty());
+    parts.join(" ")
+}
+
+/// Look up the names of the users, roles and channels mentioned in a Discord message, so it
+/// can be shown in game. Users are named after their nickname, preferring the mentions sent
+/// with the message in `known` to the cache, or their player name if they're verified and
+/// `mention-player-names` is enabled.
+pub async fn mention_names(
+    state: &State,
+    guild: Option<GuildId>,
+    source: &str,
+    known: &[Mention],
+) -> Result<Mentions> {
+    let mut mentions = Mentions {
+        time: now(),
+        ..Mentions::default()
+    };
+
+    for node in mention_nodes(source) {
+        match node {
+            Node::UserMention(id) => {
+                let user = match id.parse() {
+                    Ok(user) => UserId(user),
+                    Err(_) => continue,
+                };
+
+                let mut name = None;
+                if state.config().mention_player_names {
+                    if let Some(player) = link::linked_player(state, user).await? {
+                        name = whois::player_name(state, &player).await?;
+                    }
+                }
+
+                let name = name
+                    .or_else(|| {
+                        known.iter().find(|m| m.id == user).map(|m| {
+                            m.member
+                                .as_ref()
+                                .and_then(|member| member.nick.clone())
+                                .unwrap_or_else(|| m.name.clone())
+                        })
+                    })
+                    .or_else(|| {
+                        guild
+                            .and_then(|guild| state.cache.member(guild, user))
+                            .and_then(|member| member.nick)
+                    })
+                    .or_else(|| state.cache.user(user).map(|u| u.name));
+                if let Some(name) = name {
+                    mentions.users.insert(id, name);
+                }
+            }
+            Node::RoleMention(id) => {
+                if let Some(role) = id.parse().ok().and_then(|r| state.cache.role(RoleId(r))) {
+                    mentions.roles.insert(id, role.name);
+                }
+            }
+            Node::ChannelMention(id) => {
+                if let Some(channel) = id
+                    .parse()
+                    .ok()
+                    .and_then(|c| state.cache.guild_channel(ChannelId(c)))
+                {
+                    mentions.channels.insert(id, channel.name().to_owned());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(mentions)
+}
+
+/// Resolve players mentioned like `@Name` in a game message to their verified Discord users,
+/// keyed by their lowercase name. This is empty unless `resolve-mentions` is enabled.
+pub async fn resolve_mentions(state: &State, message: &str) -> Result<HashMap<String, String>> {
+    let mut mentions = HashMap::new();
+    if !state.config().resolve_mentions {
+        return Ok(mentions);
+    }
+
+    for name in mentioned_names(message) {
+        let player = match state.omegga.get_player(&name).await? {
+            Some(p) => p,
+            None => continue,
+        };
+
+        if let Some(user) = link::linked_user(state, &player.id).await? {
+            mentions.insert(name.to_lowercase(), user.to_string());
+        }
+    }
+
+    Ok(mentions)
+}
+
+/// Relay a message from the game to the Discord channel.
+///
+/// When a webhook is configured, messages from players are executed through it with the
+/// player's name and avatar. Otherwise, they are sent as plain messages from the bot.
+pub async fn relay(state: &State, message: &Outgoing) -> Result<()> {
+    // the webhook is in the primary channel, so other channels get plain messages
+    let webhook = state
+        .webhook
+        .as_ref()
+        .filter(|_| message.channel_id == state.channel_id);
+
+    let (webhook, author) = match (webhook, message.author.as_ref()) {
+        (Some(webhook), Some(author)) => (webhook, author),
+        _ => {
+            state
+                .discord
+                .send_message(
+                    message.channel_id,
+                    NewMessage {
+                        content: message.content.clone(),
+                        embeds: message.embeds.clone(),
+                        mentions: message.mentions.clone(),
+                        ..Default::default()
+                    },
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // resolve the player's ID if we weren't given it
+    let id = match author.id.clone() {
+        Some(id) => Some(id),
+        None => state.omegga.get_player(&author.name).await?.map(|p| p.id),
+    };
+
+    let avatar = match id {
+        Some(id) => Some(webhook::avatar_url(state, &id, &author.formatters).await),
+        None => None,
+    };
+
+    // usernames aren't markdown, so use the player's name as-is
+    let username = format_content(
+        state.config().webhook_username_format.clone(),
+        &[
+            &[Formatter {
+                key: "user",
+                value: author.name.clone(),
+            }],
+            author.formatters.as_slice(),
+        ]
+        .concat(),
+    );
+    let username = match username.trim() {
+        "" => author.name.clone(),
+        name => name.to_owned(),
+    };
+
     state
-        .http
-        .create_message(message.channel_id)
-        .content(content)?
-        .reply(message.id)
-        .exec()
-        .await?;
+        .discord
+        .execute_webhook(
+            webhook.clone(),
+            WebhookMessage {
+                content: message.content.clone(),
+                username,
+                avatar_url: avatar,
+                mentions: message.mentions.clone(),
+            },
+        )
+        .await
+}
+
+/// Reply to a message, over several messages if it's too long for one.
+pub async fn reply(state: &State, message: &Message, content: &str) -> Result<()> {
+    for (i, piece) in split_message(content).into_iter().enumerate() {
+        state
+            .discord
+            .send_message(
+                message.channel_id,
+                NewMessage {
+                    content: piece,
+                    reply: (i == 0).then_some(message.id),
+                    ..Default::default()
+                },
+            )
+            .await?;
+    }
     Ok(())
 }
 
-pub async fn update_verified(state: &State, message: &Message, player: &Player) -> Result<()> {
-    if !state.config.verified_role.is_empty() {
+pub async fn update_verified(state: &State, invoker: &Invoker, name: &str) -> Result<()> {
+    let guild_id = match invoker.guild_id {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+
+    if let Ok(role) = state.config().verified_role.parse() {
         let _ = state
-            .http
-            .add_guild_member_role(
-                message.guild_id.unwrap(),
-                message.author.id,
-                RoleId(state.config.verified_role.parse().unwrap()),
-            )
-            .exec()
+            .discord
+            .add_role(guild_id, invoker.id, RoleId(role))
+            .await;
+    }
+
+    if state.config().verified_nickname {
+        let _ = state
+            .discord
+            .set_nick(guild_id, invoker.id, Some(name.to_owned()))
             .await;
     }
 
-    if state.config.verified_nickname {
+    Ok(())
+}
+
+/// Revoke what [`update_verified`] gave a Discord user: the verified role, and their
+/// nickname if it was set by the plugin.
+pub async fn update_unverified(state: &State, user: UserId) -> Result<()> {
+    let guild_id = match guild_id(state) {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+
+    if let Ok(role) = state.config().verified_role.parse() {
         let _ = state
-            .http
-            .update_guild_member(message.guild_id.unwrap(), message.author.id)
-            .nick(Some(player.name.as_str()))?
-            .exec()
+            .discord
+            .remove_role(guild_id, user, RoleId(role))
             .await;
     }
 
+    if state.config().verified_nickname {
+        let _ = state.discord.set_nick(guild_id, user, None).await;
+    }
+
+    Ok(())
+}
+
+/// The guild the bridge channel is in, once it's been cached.
+pub fn guild_id(state: &State) -> Option<GuildId> {
+    state
+        .cache
+        .guild_channel(state.channel_id)
+        .and_then(|c| c.guild_id())
+}
+
+/// Send a direct message to a Discord user.
+pub async fn direct_message(state: &State, user: UserId, content: &str) -> Result<()> {
+    let channel = state.discord.private_channel(user).await?;
+    state
+        .discord
+        .send_message(
+            channel,
+            NewMessage {
+                content: content.to_owned(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    Ok(())
+}
+
+/// Respond to an application command, replying once the command has run.
+async fn handle_command(state: &State, command: Box<ApplicationCommand>) -> Result<()> {
+    let user = match command.member.as_ref().and_then(|m| m.user.as_ref()) {
+        Some(user) => user,
+        None => match command.user.as_ref() {
+            Some(user) => user,
+            None => return Ok(()),
+        },
+    };
+
+    let invoker = Invoker {
+        id: user.id,
+        name: user.name.clone(),
+        guild_id: command.guild_id,
+        roles: command
+            .member
+            .as_ref()
+            .map(|m| m.roles.clone())
+            .unwrap_or_default(),
+    };
+
+    // slash command options are passed along as if they were typed after the command
+    let args = command
+        .data
+        .options
+        .iter()
+        .filter_map(|o| match o {
+            CommandDataOption::String { value, .. } => Some(value.to_owned()),
+            CommandDataOption::Integer { value, .. } => Some(value.to_string()),
+            CommandDataOption::Boolean { value, .. } => Some(value.to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    // commands can take a while to talk to the server, so defer the reply first
+    let ephemeral = commands::is_ephemeral(&command.data.name);
+    state
+        .discord
+        .defer_interaction(command.id, command.token.clone(), ephemeral)
+        .await?;
+
+    // unverify takes a user or a player, and both would be read as one name
+    let content = if command.data.name == "unverify" && command.data.options.len() > 1 {
+        "**Please give either a user or a player, not both.**".to_owned()
+    } else {
+        match commands::run(state, &invoker, &command.data.name, &args).await {
+            Ok(Some(response)) => response,
+            Ok(None) => "**Unknown command.**".to_owned(),
+            Err(e) => {
+                state
+                    .omegga
+                    .log(format!("Error running {}: {}", command.data.name, e));
+                "**Something went wrong running that command.**".to_owned()
+            }
+        }
+    };
+
+    let mut pieces = split_message(&content).into_iter();
+    state
+        .discord
+        .update_interaction(command.token.clone(), pieces.next().unwrap_or_default())
+        .await?;
+    for piece in pieces {
+        state
+            .discord
+            .followup_interaction(command.token.clone(), piece, ephemeral)
+            .await?;
+    }
     Ok(())
 }
 
 async fn handle_message(
     state: &State,
-    current_user: &CurrentUser,
+    current_user: Option<UserId>,
     message: Box<MessageCreate>,
 ) -> Result<()> {
     // reject the bot
-    if message.author.id == current_user.id {
+    if Some(message.author.id) == current_user {
         return Ok(());
     }
 
-    // only accept messages in the current channel
-    if message.channel_id != state.channel_id {
+    // reject messages sent through our webhook
+    if let (Some(webhook), Some(webhook_id)) = (state.webhook.as_ref(), message.webhook_id) {
+        if webhook.id == webhook_id {
+            return Ok(());
+        }
+    }
+
+    // only accept messages in bridged channels
+    let binding = match channels::binding(state, message.channel_id) {
+        Some(binding) => binding,
+        None => return Ok(()),
+    };
+
+    // channels that only go to Discord don't take messages or commands
+    if !binding.direction.to_game() {
         return Ok(());
     }
 
     // parse commands if the message starts with the prefix
-    let prefix = &state.config.discord_prefix;
-    if message.content.starts_with(prefix) {
+    let prefix = &state.config().discord_prefix;
+    if state.command_mode().prefix() && message.content.starts_with(prefix) {
         let (cmd, args) = message
             .content
             .split_once(' ')
             .map(|(c, a)| (&c[prefix.len()..], a))
             .unwrap_or((&message.content[prefix.len()..], &message.content[0..0]));
 
-        state.omegga.log(format!(
-            "{} is trying to run {} with {}",
-            message.author.name, cmd, args
-        ));
-
-        match cmd {
-            "players" => {
-                let players = state.omegga.get_players().await?;
-                if players.is_empty() {
-                    reply(&state, &message.0, "**There are no players online.**").await?;
-                } else {
-                    let mut response = format!(
-                        "**There {} {} player{} online.**\n",
-                        if players.len() == 1 { "is" } else { "are" },
-                        players.len(),
-                        if players.len() == 1 { "" } else { "s" }
-                    );
-
-                    for player in players.iter() {
-                        let roles = state
-                            .omegga
-                            .get_player_roles(&player.name)
-                            .await?
-                            .unwrap_or_else(Vec::new);
-                        let role_text = role_text(&roles, &state.config.game_roles);
-
-                        response.push_str(
-                            format!(
-                                "{}{}{}\n",
-                                role_text,
-                                if !role_text.is_empty() { " " } else { "" },
-                                player.name
-                            )
-                            .as_str(),
-                        );
-                    }
-
-                    reply(&state, &message.0, response.as_str()).await?;
-                }
-            }
-            "verify" => {
-                if !state.config.verification {
-                    return Ok(());
-                }
-
-                match args {
-                    "" => {
-                        if let Some(player) = state
-                            .omegga
-                            .get_player(
-                                state
-                                    .omegga
-                                    .store_get(format!("d2g_{}", message.author.id).as_str())
-                                    .await?
-                                    .unwrap_or_default()
-                                    .as_str()
-                                    .unwrap_or_default(),
-                            )
-                            .await?
-                        {
-                            // update on discord
-                            update_verified(&state, &message.0, &player).await?;
-                            reply(&state, &message.0, "**Synced verification with game.**").await?;
-                        } else {
-                            reply(&state, &message.0, "**You are not verified!** Start the verification process by running `/discord verify` in-game.").await?;
-                        }
-                    }
-                    code => {
-                        let key = match state.verify_buffer.iter().find(|r| r.value() == code) {
-                            Some(r) => r.key().to_owned(),
-                            None => {
-                                reply(
-                                    &state,
-                                    &message.0,
-                                    format!("**There is no pending verification with that code!**")
-                                        .as_str(),
-                                )
-                                .await?;
-                                return Ok(());
-                            }
-                        };
-
-                        if let Entry::Occupied(entry) = state.verify_buffer.entry(key) {
-                            // fetch the in-game player
-                            let player = state
-                                .omegga
-                                .get_player(entry.key().to_owned())
-                                .await?
-                                .unwrap();
-
-                            // add to the database
-                            state.omegga.store_set(
-                                format!("g2d_{}", entry.key()),
-                                Value::String(message.author.id.to_string()),
-                            );
-
-                            state.omegga.store_set(
-                                format!("d2g_{}", message.author.id),
-                                Value::String(entry.key().to_string()),
-                            );
-
-                            // remove from the dashmap
-                            entry.remove();
-
-                            // confirm to the user that they've been verified in discord
-                            reply(
-                                &state,
-                                &message.0,
-                                format!(
-                                    "**Success!** You've been verified as **{}** in Brickadia.",
-                                    player.name
-                                )
-                                .as_str(),
-                            )
-                            .await?;
-
-                            // update on discord
-                            update_verified(&state, &message.0, &player).await?;
-
-                            // confirm in-game
-                            state.omegga.whisper(player.name, format!("<color=\"0a0\"><b>Success!</></> You've been verified as <b>{}</> in Discord.", message.author.name));
-                        }
-                    }
-                }
-            }
-            _ => (),
+        let invoker = Invoker {
+            id: message.author.id,
+            name: message.author.name.clone(),
+            guild_id: message.guild_id,
+            roles: message
+                .member
+                .as_ref()
+                .map(|m| m.roles.clone())
+                .unwrap_or_default(),
+        };
+
+        // commands aren't chat, and can hold things like verification codes
+        if let Some(response) = commands::run(state, &invoker, cmd, args).await? {
+            reply(state, &message.0, &response).await?;
+            return Ok(());
         }
     }
 
-    // get user info
-    let member = message.member.as_ref().unwrap();
-    let mut roles = member
-        .roles
+    // get user info, skipping roles that aren't cached yet
+    let member_roles = message
+        .member
+        .as_ref()
+        .map(|m| m.roles.as_slice())
+        .unwrap_or_default();
+    let mut roles = member_roles
         .iter()
-        .map(|id| state.cache.role(*id).unwrap())
+        .filter_map(|id| state.cache.role(*id))
         .collect::<Vec<_>>();
 
-    roles.sort_by(|a, b| b.position.cmp(&a.position));
+    roles.sort_by_key(|r| std::cmp::Reverse(r.position));
+
+    // members with a trusted role may use chat codes in their messages
+    let trusted = member_roles
+        .iter()
+        .any(|id| state.config().trusted_roles.contains(&id.to_string()));
+
+    let name = message
+        .member
+        .as_ref()
+        .and_then(|m| m.nick.as_ref())
+        .unwrap_or(&message.author.name);
+
+    // skip messages with nothing to show, like embeds when embed-format is blank
+    let mentions =
+        mention_names(state, message.guild_id, &message.content, &message.mentions).await?;
+    let content = message_to_game(&state.config(), &message.0, trusted, &mentions);
+    if content.is_empty() {
+        return Ok(());
+    }
 
     let role_color = roles
         .iter()
@@ -224,20 +532,20 @@ async fn handle_message(
             key: "role",
             value: role_text(
                 &roles.iter().map(|r| r.name.to_owned()).collect::<Vec<_>>(),
-                &state.config.discord_roles,
+                &state.config().discord_roles,
             ),
         },
         Formatter {
             key: "user",
-            value: member
-                .nick
-                .as_ref()
-                .unwrap_or(&message.author.name)
-                .to_owned(),
+            value: if trusted {
+                name.to_owned()
+            } else {
+                sanitize(name)
+            },
         },
         Formatter {
             key: "message",
-            value: format_to_game(message.content.to_owned()),
+            value: content,
         },
         Formatter {
             key: "color",
@@ -245,36 +553,99 @@ async fn handle_message(
         },
     ];
 
-    state.omegga.broadcast(format_content(
-        state.config.game_message_format.clone(),
+    state.omegga.broadcast(binding.prefixed(format_content(
+        binding.format(&state.config(), "game-message-format"),
         &formatters,
-    ));
+    )));
 
     state
         .omegga
         .log(format_content("<$user> $message".into(), &formatters));
+    state.digest.chat_to_game(name);
 
     Ok(())
 }
 
-pub async fn listener(state: State, mut events: Events) -> Result<()> {
-    let current_user = state.http.current_user().exec().await?.model().await?;
+/// Register the plugin's application commands in a guild.
+async fn register_commands(state: &State, guild_id: GuildId) -> Result<()> {
+    state
+        .discord
+        .set_guild_commands(guild_id, commands::definitions(state))
+        .await
+}
 
-    while let Some(event) = events.next().await {
-        state.cache.update(&event);
+/// Handle an event from Discord's gateway. `current_user` is the bot's user, once it's ready.
+pub async fn handle_event(state: &State, current_user: &mut Option<UserId>, event: Event) {
+    state.cache.update(&event);
 
-        match event {
-            Event::Ready(_) => {
-                state.omegga.log("Discord client is ready.");
+    match event {
+        Event::Ready(ready) => {
+            *current_user = Some(ready.user.id);
+            state.discord.set_application_id(ready.application.id);
+            state.omegga.log("Discord client is ready.");
+        }
+        // register commands in the guild the channel is in
+        Event::GuildCreate(guild)
+            if state.command_mode().slash()
+                && guild.channels.iter().any(|c| c.id() == state.channel_id) =>
+        {
+            if let Err(e) = register_commands(state, guild.id).await {
+                state
+                    .omegga
+                    .error(format!("Error registering commands: {}", e));
             }
-            Event::MessageCreate(message) => {
-                match handle_message(&state, &current_user, message).await {
-                    Ok(_) => (),
-                    Err(e) => state.omegga.log(format!("Error handling message: {}", e)),
+        }
+        Event::InteractionCreate(interaction) if state.command_mode().slash() => {
+            if let Interaction::ApplicationCommand(command) = interaction.0 {
+                if let Err(e) = handle_command(state, command).await {
+                    state.omegga.log(format!("Error handling command: {}", e));
                 }
             }
-            _ => (),
         }
+        // keep synced roles up to date as they change in discord
+        Event::MemberUpdate(member) if Some(member.guild_id) == guild_id(state) => {
+            if let Err(e) = roles::sync_user(state, member.user.id, member.roles).await {
+                state.omegga.error(format!("Error syncing roles: {}", e));
+            }
+        }
+        // post the status message again if it's deleted
+        Event::MessageDelete(message) if Some(message.id) == state.status.message_id() => {
+            state.status.request_update();
+        }
+        Event::MessageCreate(message) => {
+            if let Err(e) = handle_message(state, *current_user, message).await {
+                state.omegga.log(format!("Error handling message: {}", e));
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Whether an event runs a command, from a slash command or a message with the prefix.
+fn is_command(state: &State, event: &Event) -> bool {
+    match event {
+        Event::InteractionCreate(_) => true,
+        Event::MessageCreate(message) => {
+            state.command_mode().prefix()
+                && message.content.starts_with(&state.config().discord_prefix)
+        }
+        _ => false,
+    }
+}
+
+pub async fn listener(state: State, mut events: Events) -> Result<()> {
+    let mut current_user = None;
+
+    while let Some(event) = events.next().await {
+        // commands can take a while, like console commands waiting on output, so they're
+        // handled in their own task to keep relaying chat meanwhile
+        if is_command(&state, &event) {
+            let (state, mut current_user) = (state.clone(), current_user);
+            tokio::spawn(async move { handle_event(&state, &mut current_user, event).await });
+            continue;
+        }
+
+        handle_event(&state, &mut current_user, event).await;
     }
 
     Ok(())
@@ -0,0 +1,106 @@
+use std::error::Error as StdError;
+
+use omegga::ResponseError;
+use thiserror::Error;
+use twilight_http::{error::ErrorType, response::DeserializeBodyError};
+
+/// Anything that can go wrong in the plugin.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The plugin config is missing something or has an invalid value.
+    #[error("invalid config: {0}")]
+    Config(String),
+
+    /// A message from omegga didn't have the parameters we expected.
+    #[error("invalid parameters for {method}: {reason}")]
+    Params { method: String, reason: String },
+
+    /// A player we needed to find in game isn't online.
+    #[error("player {0} isn't online")]
+    PlayerNotFound(String),
+
+    /// A request to omegga failed.
+    #[error("omegga request failed: {0:?}")]
+    Omegga(#[from] ResponseError),
+
+    /// A request to Discord failed.
+    #[error("Discord request failed: {0}")]
+    Discord(Box<twilight_http::Error>),
+
+    /// A request to Discord couldn't be made, like a message that was too long.
+    #[error("invalid Discord request: {0}")]
+    Request(Box<dyn StdError + Send + Sync>),
+
+    /// Discord responded with something we couldn't read.
+    #[error("invalid Discord response: {0}")]
+    Response(#[from] DeserializeBodyError),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn params(method: &str, reason: impl ToString) -> Self {
+        Self::Params {
+            method: method.to_owned(),
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Whether Discord responded with the given HTTP status.
+    pub fn is_status(&self, code: u16) -> bool {
+        matches!(self, Self::Discord(e) if matches!(e.kind(), ErrorType::Response { status, .. } if status.raw() == code))
+    }
+
+    /// Whether the error might go away if what caused it is tried again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Discord(e) => match e.kind() {
+                ErrorType::Response { status, .. } => {
+                    status.raw() == 429 || status.is_server_error()
+                }
+                ErrorType::ChunkingResponse
+                | ErrorType::RequestCanceled
+                | ErrorType::RequestError
+                | ErrorType::RequestTimedOut
+                | ErrorType::ServiceUnavailable { .. } => true,
+                _ => false,
+            },
+            Self::Omegga(ResponseError::Recv(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl From<twilight_http::Error> for Error {
+    fn from(error: twilight_http::Error) -> Self {
+        Self::Discord(Box::new(error))
+    }
+}
+
+/// Errors from building Discord requests, which all become [`Error::Request`].
+macro_rules! request_errors {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for Error {
+                fn from(error: $error) -> Self {
+                    Self::Request(Box::new(error))
+                }
+            }
+        )*
+    };
+}
+
+request_errors!(
+    twilight_http::request::channel::message::create_message::CreateMessageError,
+    twilight_http::request::channel::message::update_message::UpdateMessageError,
+    twilight_http::request::channel::update_channel::UpdateChannelError,
+    twilight_http::request::guild::member::update_guild_member::UpdateGuildMemberError,
+    twilight_http::request::application::InteractionError,
+    twilight_http::request::application::interaction::update_original_response::UpdateOriginalResponseError,
+);
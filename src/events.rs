@@ -0,0 +1,235 @@
+use futures::future::BoxFuture;
+use omegga::resources::Player;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value;
+
+use crate::{
+    error::{Error, Result},
+    State,
+};
+
+/// A notification from omegga about something that happened in game.
+#[derive(Debug, Clone)]
+pub enum GameEvent {
+    /// The server started on a map.
+    Start {
+        map: String,
+    },
+
+    /// The server changed to another map.
+    MapChange {
+        map: String,
+    },
+
+    /// A player sent a chat message.
+    Chat {
+        user: String,
+        message: String,
+    },
+
+    Join(Player),
+    Leave(Player),
+
+    /// A player ran a chat command like `!discord`.
+    ChatCommand {
+        command: String,
+        // parsed the same as slash commands, though `!discord` only needs the name
+        #[allow(dead_code)]
+        user: String,
+        #[allow(dead_code)]
+        args: Vec<String>,
+    },
+
+    /// A player ran a slash command like `/discord`.
+    Command {
+        command: String,
+        user: String,
+        args: Vec<String>,
+    },
+
+    /// A line was written to the server console.
+    Line(String),
+
+    /// Any other notification.
+    Other {
+        // kept for debugging, since nothing handles these
+        #[allow(dead_code)]
+        method: String,
+        #[allow(dead_code)]
+        params: Option<Value>,
+    },
+}
+
+/// Read the parameters of a notification from omegga.
+fn params<T: DeserializeOwned>(method: &str, params: Option<Value>) -> Result<T> {
+    let params = params.ok_or_else(|| Error::params(method, "missing parameters"))?;
+    serde_json::from_value(params).map_err(|e| Error::params(method, e))
+}
+
+/// Read the first of a notification's parameters.
+fn first_param<T: DeserializeOwned>(method: &str, value: Option<Value>) -> Result<T> {
+    params::<Vec<T>>(method, value)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::params(method, "no parameters"))
+}
+
+/// Read a command's user and arguments.
+fn command_params(method: &str, value: Option<Value>) -> Result<(String, Vec<String>)> {
+    let mut params = params::<Vec<String>>(method, value)?.into_iter();
+    let user = params
+        .next()
+        .ok_or_else(|| Error::params(method, "no user"))?;
+    Ok((user, params.collect()))
+}
+
+impl GameEvent {
+    /// Parse a notification from omegga.
+    pub fn parse(method: &str, value: Option<Value>) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct MapObject {
+            map: String,
+        }
+
+        Ok(match method {
+            "start" => Self::Start {
+                map: first_param::<MapObject>(method, value)?.map,
+            },
+            "mapchange" => Self::MapChange {
+                map: first_param::<MapObject>(method, value)?.map,
+            },
+            "chat" => {
+                let mut params = params::<Vec<String>>(method, value)?.into_iter();
+                Self::Chat {
+                    user: params
+                        .next()
+                        .ok_or_else(|| Error::params(method, "no user"))?,
+                    message: params.next().unwrap_or_default(),
+                }
+            }
+            "join" => Self::Join(first_param(method, value)?),
+            "leave" => Self::Leave(first_param(method, value)?),
+            "line" => Self::Line(first_param(method, value)?),
+            _ => {
+                if let Some(command) = method.strip_prefix("chatcmd:") {
+                    let (user, args) = command_params(method, value)?;
+                    Self::ChatCommand {
+                        command: command.to_owned(),
+                        user,
+                        args,
+                    }
+                } else if let Some(command) = method.strip_prefix("cmd:") {
+                    let (user, args) = command_params(method, value)?;
+                    Self::Command {
+                        command: command.to_owned(),
+                        user,
+                        args,
+                    }
+                } else {
+                    Self::Other {
+                        method: method.to_owned(),
+                        params: value,
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Something that reacts to events from the game, like relaying them to Discord.
+pub trait Handler: Send + Sync {
+    /// Handle an event. Every handler sees every event, and ignores the ones it doesn't need.
+    fn handle<'a>(&'a self, state: &'a State, event: &'a GameEvent) -> BoxFuture<'a, Result<()>>;
+}
+
+/// The handlers that game events are dispatched to, in the order they were registered.
+#[derive(Default)]
+pub struct Dispatcher {
+    handlers: Vec<(&'static str, Box<dyn Handler>)>,
+}
+
+impl Dispatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(mut self, name: &'static str, handler: impl Handler + 'static) -> Self {
+        self.handlers.push((name, Box::new(handler)));
+        self
+    }
+
+    /// Parse a notification from omegga and dispatch it. A bad notification is logged and
+    /// skipped, never taking the plugin down.
+    pub async fn notify(&self, state: &State, method: &str, params: Option<Value>) {
+        match GameEvent::parse(method, params) {
+            Ok(event) => self.dispatch(state, &event).await,
+            Err(e) => state
+                .omegga
+                .error(format!("Error handling {}: {}", method, e)),
+        }
+    }
+
+    /// Dispatch an event to every handler. A handler failing is logged, and doesn't stop the others.
+    pub async fn dispatch(&self, state: &State, event: &GameEvent) {
+        for (name, handler) in self.handlers.iter() {
+            if let Err(e) = handler.handle(state, event).await {
+                state
+                    .omegga
+                    .error(format!("Error in {} handling {:?}: {}", name, event, e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::GameEvent;
+    use crate::error::Error;
+
+    #[test]
+    fn notifications_are_parsed() {
+        assert!(matches!(
+            GameEvent::parse("start", Some(json!([{"map": "Plate"}]))),
+            Ok(GameEvent::Start { map }) if map == "Plate"
+        ));
+        assert!(matches!(
+            GameEvent::parse("chat", Some(json!(["x", "hi"]))),
+            Ok(GameEvent::Chat { user, message }) if user == "x" && message == "hi"
+        ));
+        assert!(matches!(
+            GameEvent::parse("cmd:discord", Some(json!(["x", "unverify", "y"]))),
+            Ok(GameEvent::Command { command, user, args })
+                if command == "discord" && user == "x" && args == ["unverify", "y"]
+        ));
+        assert!(matches!(
+            GameEvent::parse("chatcmd:discord", Some(json!(["x"]))),
+            Ok(GameEvent::ChatCommand { command, args, .. }) if command == "discord" && args.is_empty()
+        ));
+        assert!(matches!(
+            GameEvent::parse("plugin:players:raw", None),
+            Ok(GameEvent::Other { .. })
+        ));
+    }
+
+    #[test]
+    fn malformed_notifications_are_errors() {
+        assert!(matches!(
+            GameEvent::parse("chat", None),
+            Err(Error::Params { .. })
+        ));
+        assert!(matches!(
+            GameEvent::parse("chat", Some(json!({"user": "x"}))),
+            Err(Error::Params { .. })
+        ));
+        assert!(matches!(
+            GameEvent::parse("join", Some(json!([]))),
+            Err(Error::Params { .. })
+        ));
+        assert!(matches!(
+            GameEvent::parse("cmd:discord", Some(json!([]))),
+            Err(Error::Params { .. })
+        ));
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use futures::future::{self, BoxFuture};
+use omegga::{resources::Player, ResponseError};
+use serde_json::{json, Value};
+use twilight_model::{
+    application::command::Command,
+    channel::embed::Embed,
+    id::{ApplicationId, ChannelId, GuildId, InteractionId, MessageId, RoleId, UserId},
+};
+
+use crate::{
+    backend::{DiscordBackend, GameBackend, NewMessage, WebhookMessage},
+    error::Result,
+    webhook::Webhook,
+};
+
+/// Something the plugin did in game.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameOutput {
+    Log(String),
+    Error(String),
+    Warn(String),
+    Command(String),
+    Broadcast(String),
+    Whisper { user: String, line: String },
+    ClearBricks(String),
+}
+
+/// An in-memory game server, with a store and players that can be joined by tests.
+#[derive(Default)]
+pub struct FakeGame {
+    output: Mutex<Vec<GameOutput>>,
+    store: Mutex<HashMap<String, Value>>,
+    players: Mutex<Vec<(Player, Vec<String>)>>,
+    bans: Mutex<serde_json::Map<String, Value>>,
+    bricks: AtomicU64,
+    minigames: Mutex<Vec<String>>,
+}
+
+impl FakeGame {
+    /// Add an online player with the given roles.
+    pub fn join(&self, name: &str, id: &str, roles: &[&str]) -> Player {
+        self.add_player(name, id, roles, false)
+    }
+
+    /// Add the host as an online player.
+    pub fn join_host(&self, name: &str, id: &str) -> Player {
+        self.add_player(name, id, &[], true)
+    }
+
+    /// Remove an online player, by their UUID.
+    pub fn leave(&self, id: &str) {
+        self.players.lock().unwrap().retain(|(p, _)| p.id != id);
+    }
+
+    fn add_player(&self, name: &str, id: &str, roles: &[&str], host: bool) -> Player {
+        let player = Player {
+            name: name.into(),
+            id: id.into(),
+            controller: format!("PlayerController_{}", name),
+            state: format!("PlayerState_{}", name),
+            host: Some(host),
+        };
+
+        let roles = roles.iter().map(|r| r.to_string()).collect();
+        self.players.lock().unwrap().push((player.clone(), roles));
+        player
+    }
+
+    /// Ban a player, like an admin would in game.
+    pub fn ban(&self, id: &str, banner: &str, reason: &str) {
+        self.bans.lock().unwrap().insert(
+            id.into(),
+            json!({
+                "bannerId": banner,
+                "created": "2021-01-01T00:00:00.000Z",
+                "expires": "2021-01-02T00:00:00.000Z",
+                "reason": reason,
+            }),
+        );
+    }
+
+    /// Set how many bricks are on the server.
+    pub fn set_bricks(&self, bricks: u64) {
+        self.bricks.store(bricks, Ordering::SeqCst);
+    }
+
+    /// Start a minigame on the server.
+    pub fn add_minigame(&self, name: &str) {
+        self.minigames.lock().unwrap().push(name.into());
+    }
+
+    /// Take everything the plugin has done in game so far.
+    pub fn take_output(&self) -> Vec<GameOutput> {
+        std::mem::take(&mut *self.output.lock().unwrap())
+    }
+
+    pub fn stored(&self, key: &str) -> Option<Value> {
+        self.store.lock().unwrap().get(key).cloned()
+    }
+
+    fn push(&self, output: GameOutput) {
+        self.output.lock().unwrap().push(output);
+    }
+
+    fn find(&self, target: &str) -> Option<(Player, Vec<String>)> {
+        self.players
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(p, _)| p.id == target || p.name.eq_ignore_ascii_case(target))
+            .cloned()
+    }
+}
+
+impl GameBackend for FakeGame {
+    fn log(&self, line: String) {
+        self.push(GameOutput::Log(line));
+    }
+
+    fn error(&self, line: String) {
+        self.push(GameOutput::Error(line));
+    }
+
+    fn warn(&self, line: String) {
+        self.push(GameOutput::Warn(line));
+    }
+
+    fn writeln(&self, line: String) {
+        self.push(GameOutput::Command(line));
+    }
+
+    fn broadcast(&self, line: String) {
+        self.push(GameOutput::Broadcast(line));
+    }
+
+    fn whisper(&self, user: String, line: String) {
+        self.push(GameOutput::Whisper { user, line });
+    }
+
+    fn store_get(&self, key: String) -> BoxFuture<'_, Result<Option<Value>, ResponseError>> {
+        Box::pin(future::ready(Ok(self.stored(&key))))
+    }
+
+    fn store_set(&self, key: String, value: Value) {
+        self.store.lock().unwrap().insert(key, value);
+    }
+
+    fn store_delete(&self, key: String) -> BoxFuture<'_, ()> {
+        self.store.lock().unwrap().remove(&key);
+        Box::pin(future::ready(()))
+    }
+
+    fn store_keys(&self) -> BoxFuture<'_, Result<Vec<String>, ResponseError>> {
+        let keys = self.store.lock().unwrap().keys().cloned().collect();
+        Box::pin(future::ready(Ok(keys)))
+    }
+
+    fn get_players(&self) -> BoxFuture<'_, Result<Vec<Player>, ResponseError>> {
+        let players = self.players.lock().unwrap();
+        Box::pin(future::ready(Ok(players
+            .iter()
+            .map(|(p, _)| p.clone())
+            .collect())))
+    }
+
+    fn get_player(&self, target: String) -> BoxFuture<'_, Result<Option<Player>, ResponseError>> {
+        Box::pin(future::ready(Ok(self.find(&target).map(|(p, _)| p))))
+    }
+
+    fn get_player_roles(
+        &self,
+        target: String,
+    ) -> BoxFuture<'_, Result<Option<Vec<String>>, ResponseError>> {
+        Box::pin(future::ready(Ok(self.find(&target).map(|(_, r)| r))))
+    }
+
+    fn get_ban_list(&self) -> BoxFuture<'_, Result<Value, ResponseError>> {
+        let bans = self.bans.lock().unwrap().clone();
+        Box::pin(future::ready(Ok(json!({ "banList": bans }))))
+    }
+
+    fn get_server_status(&self) -> BoxFuture<'_, Result<Value, ResponseError>> {
+        let bricks = self.bricks.load(Ordering::SeqCst);
+        Box::pin(future::ready(Ok(json!({
+            "serverName": "Test Server",
+            "bricks": bricks,
+            "components": 0,
+        }))))
+    }
+
+    fn get_minigames(&self) -> BoxFuture<'_, Result<Value, ResponseError>> {
+        // the global minigame is always there, as in omegga
+        let minigames = std::iter::once("GLOBAL".to_owned())
+            .chain(self.minigames.lock().unwrap().iter().cloned())
+            .enumerate()
+            .map(|(i, name)| {
+                json!({
+                    "index": i as i64 - 1,
+                    "name": name,
+                    "ruleset": if i == 0 { "GLOBAL" } else { "Minigame" },
+                    "members": [],
+                })
+            })
+            .collect::<Vec<_>>();
+        Box::pin(future::ready(Ok(Value::Array(minigames))))
+    }
+
+    fn clear_bricks(&self, target: String) {
+        self.push(GameOutput::ClearBricks(target));
+    }
+}
+
+/// A request the plugin made to Discord.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiscordRequest {
+    Message {
+        channel: ChannelId,
+        message: NewMessage,
+    },
+    UpdateEmbeds {
+        channel: ChannelId,
+        message: MessageId,
+        embeds: Vec<Embed>,
+    },
+    Webhook(WebhookMessage),
+    Rename {
+        channel: ChannelId,
+        name: String,
+    },
+    AddRole {
+        user: UserId,
+        role: RoleId,
+    },
+    RemoveRole {
+        user: UserId,
+        role: RoleId,
+    },
+    Nick {
+        user: UserId,
+        nick: Option<String>,
+    },
+    Commands(Vec<String>),
+    Defer {
+        ephemeral: bool,
+    },
+    Respond(String),
+    Followup(String),
+}
+
+/// An in-memory Discord, recording every request made to it.
+#[derive(Default)]
+pub struct FakeDiscord {
+    requests: Mutex<Vec<DiscordRequest>>,
+    next_id: AtomicU64,
+}
+
+impl FakeDiscord {
+    /// Take every request made so far.
+    pub fn take_requests(&self) -> Vec<DiscordRequest> {
+        std::mem::take(&mut *self.requests.lock().unwrap())
+    }
+
+    fn push(&self, request: DiscordRequest) -> BoxFuture<'_, Result<()>> {
+        self.requests.lock().unwrap().push(request);
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+impl DiscordBackend for FakeDiscord {
+    fn send_message(
+        &self,
+        channel: ChannelId,
+        message: NewMessage,
+    ) -> BoxFuture<'_, Result<MessageId>> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push(DiscordRequest::Message { channel, message });
+        let id = MessageId(self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        Box::pin(future::ready(Ok(id)))
+    }
+
+    fn update_embeds(
+        &self,
+        channel: ChannelId,
+        message: MessageId,
+        embeds: Vec<Embed>,
+    ) -> BoxFuture<'_, Result<()>> {
+        self.push(DiscordRequest::UpdateEmbeds {
+            channel,
+            message,
+            embeds,
+        })
+    }
+
+    fn execute_webhook(
+        &self,
+        _webhook: Webhook,
+        message: WebhookMessage,
+    ) -> BoxFuture<'_, Result<()>> {
+        self.push(DiscordRequest::Webhook(message))
+    }
+
+    fn rename_channel(&self, channel: ChannelId, name: String) -> BoxFuture<'_, Result<()>> {
+        self.push(DiscordRequest::Rename { channel, name })
+    }
+
+    // direct messages go to a channel with the same ID as the user
+    fn private_channel(&self, user: UserId) -> BoxFuture<'_, Result<ChannelId>> {
+        Box::pin(future::ready(Ok(ChannelId(user.0))))
+    }
+
+    fn add_role(&self, _guild: GuildId, user: UserId, role: RoleId) -> BoxFuture<'_, Result<()>> {
+        self.push(DiscordRequest::AddRole { user, role })
+    }
+
+    fn remove_role(
+        &self,
+        _guild: GuildId,
+        user: UserId,
+        role: RoleId,
+    ) -> BoxFuture<'_, Result<()>> {
+        self.push(DiscordRequest::RemoveRole { user, role })
+    }
+
+    fn set_nick(
+        &self,
+        _guild: GuildId,
+        user: UserId,
+        nick: Option<String>,
+    ) -> BoxFuture<'_, Result<()>> {
+        self.push(DiscordRequest::Nick { user, nick })
+    }
+
+    fn member_roles(&self, _guild: GuildId, _user: UserId) -> BoxFuture<'_, Result<Vec<RoleId>>> {
+        Box::pin(future::ready(Ok(vec![])))
+    }
+
+    fn user_avatar(&self, _user: UserId) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(future::ready(Ok(None)))
+    }
+
+    fn set_application_id(&self, _application: ApplicationId) {}
+
+    fn set_guild_commands(
+        &self,
+        _guild: GuildId,
+        commands: Vec<Command>,
+    ) -> BoxFuture<'_, Result<()>> {
+        self.push(DiscordRequest::Commands(
+            commands.into_iter().map(|c| c.name).collect(),
+        ))
+    }
+
+    fn defer_interaction(
+        &self,
+        _interaction: InteractionId,
+        _token: String,
+        ephemeral: bool,
+    ) -> BoxFuture<'_, Result<()>> {
+        self.push(DiscordRequest::Defer { ephemeral })
+    }
+
+    fn update_interaction(&self, _token: String, content: String) -> BoxFuture<'_, Result<()>> {
+        self.push(DiscordRequest::Respond(content))
+    }
+
+    fn followup_interaction(
+        &self,
+        _token: String,
+        content: String,
+        _ephemeral: bool,
+    ) -> BoxFuture<'_, Result<()>> {
+        self.push(DiscordRequest::Followup(content))
+    }
+}
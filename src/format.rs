@@ -1,33 +1,52 @@
+use std::collections::HashMap;
+
 use lazy_static::lazy_static;
 use regex::Regex;
 
+use crate::markdown::{self, Node};
+
 #[derive(Debug, Clone)]
 pub struct Formatter {
     pub key: &'static str,
     pub value: String,
 }
 
-impl Formatter {
-    pub fn format(&self, source: String) -> String {
-        source.replace(&format!("${}", self.key), self.value.as_str())
-    }
-}
-
 /// Compose a Vec<T> from a bunch of other Vec<T>s in one big Vec<Vec<T>>.
 pub fn compose_vec<T>(vecs: Vec<Vec<T>>) -> Vec<T> {
     let mut vec = vec![];
     for v in vecs.into_iter() {
-        vec.extend(v.into_iter());
+        vec.extend(v);
     }
     vec
 }
 
 /// Format some text with the formatters provided.
+///
+/// This is done in a single pass, so formatter values (which may come from users) are
+/// never formatted themselves.
 pub fn format_content(text: String, formatters: &[Formatter]) -> String {
-    let mut buf = text;
-    for formatter in formatters.iter() {
-        buf = formatter.format(buf);
+    let mut buf = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+
+    while let Some(i) = rest.find('$') {
+        buf.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+
+        // prefer the longest matching key, so `$username` isn't read as `$user`
+        match formatters
+            .iter()
+            .filter(|f| rest.starts_with(f.key))
+            .min_by_key(|f| std::cmp::Reverse(f.key.len()))
+        {
+            Some(formatter) => {
+                buf.push_str(&formatter.value);
+                rest = &rest[formatter.key.len()..];
+            }
+            None => buf.push('$'),
+        }
     }
+
+    buf.push_str(rest);
     buf
 }
 
@@ -51,31 +70,929 @@ pub fn role_text(roles: &[String], list: &[String]) -> String {
     String::new()
 }
 
+/// Describe a duration in seconds, like `5 minutes`.
+pub fn describe_duration(seconds: u64) -> String {
+    let (n, unit) = match seconds {
+        s if s >= 3600 => ((s + 1799) / 3600, "hour"),
+        s if s >= 60 => ((s + 29) / 60, "minute"),
+        s => (s, "second"),
+    };
+
+    format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" })
+}
+
+/// Describe a file size in bytes, like `1.5 MB`.
+pub fn describe_size(bytes: u64) -> String {
+    const UNITS: [&str; 3] = ["KB", "MB", "GB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Shorten some text to at most `limit` characters, ending it with `…` if it was cut.
+pub fn shorten(text: &str, limit: usize) -> String {
+    match text.char_indices().nth(limit) {
+        Some(_) => {
+            let end = text.char_indices().nth(limit - 1).map_or(0, |(i, _)| i);
+            format!("{}…", text[..end].trim_end())
+        }
+        None => text.to_owned(),
+    }
+}
+
+/// Escape a URL so it can be used in a `<link="...">` chat code.
+pub fn escape_url(url: &str) -> String {
+    url.replace('"', "%22")
+        .replace('<', "%3C")
+        .replace('>', "%3E")
+}
+
+/// Escape Brickadia's chat codes in some text, so it is shown as-is in game.
+pub fn sanitize(text: &str) -> String {
+    let mut buf = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => buf.push_str("&amp;"),
+            ';' => buf.push_str("&scl;"),
+            '<' => buf.push_str("&lt;"),
+            '>' => buf.push_str("&gt;"),
+            '\u{2067}' => (),
+            c => buf.push(c),
+        }
+    }
+    buf
+}
+
+/// The names of the users, roles and channels mentioned in a Discord message, by ID.
+#[derive(Debug, Clone, Default)]
+pub struct Mentions {
+    pub users: HashMap<String, String>,
+    pub roles: HashMap<String, String>,
+    pub channels: HashMap<String, String>,
+
+    /// The UNIX time in seconds relative timestamps are described from.
+    pub time: u64,
+}
+
+/// The user, role and channel mentions in a Discord message, in order.
+pub fn mention_nodes(source: &str) -> Vec<Node> {
+    fn collect(nodes: Vec<Node>, out: &mut Vec<Node>) {
+        for node in nodes {
+            match node {
+                Node::UserMention(_) | Node::RoleMention(_) | Node::ChannelMention(_) => {
+                    out.push(node)
+                }
+                Node::Bold(children)
+                | Node::Italic(children)
+                | Node::Underline(children)
+                | Node::Strikethrough(children)
+                | Node::Spoiler(children)
+                | Node::Quote(children)
+                | Node::Header { children, .. }
+                | Node::ListItem { children, .. }
+                | Node::Link {
+                    label: children, ..
+                } => collect(children, out),
+                _ => (),
+            }
+        }
+    }
+
+    let mut out = vec![];
+    collect(markdown::parse(source), &mut out);
+    out
+}
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+/// The year, month from 1 and day of the month of a day counted from the UNIX epoch.
+fn civil_date(days: i64) -> (i64, usize, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month as usize, day)
+}
+
+/// Describe a Discord timestamp in one of its styles, in UTC or relative to `now`.
+pub fn describe_timestamp(time: i64, style: char, now: u64) -> String {
+    let (days, seconds) = (time.div_euclid(86_400), time.rem_euclid(86_400));
+    let (year, month, day) = civil_date(days);
+    let date = format!("{} {} {}", day, MONTHS[month - 1], year);
+    let clock = format!("{:02}:{:02}", seconds / 3600, seconds / 60 % 60);
+
+    match style {
+        't' => format!("{} UTC", clock),
+        'T' => format!("{}:{:02} UTC", clock, seconds % 60),
+        'd' => format!("{:02}/{:02}/{}", day, month, year),
+        'D' => date,
+        'F' => format!(
+            "{}, {} {} UTC",
+            WEEKDAYS[(days + 4).rem_euclid(7) as usize],
+            date,
+            clock
+        ),
+        'R' => match time.saturating_sub(now as i64) {
+            diff if diff > 0 => format!("in {}", describe_duration(diff as u64)),
+            diff => format!("{} ago", describe_duration(diff.unsigned_abs())),
+        },
+        _ => format!("{} {} UTC", date, clock),
+    }
+}
+
 /// Convert a Discord MD formatted message to Brickadia's chat codes.
-pub fn format_to_game(source: String) -> String {
+///
+/// Unless `trusted` is set, any chat codes written in the message itself are escaped, so
+/// only the codes generated from its markdown are rendered. Mentions are shown by their names
+/// in `mentions` where they're known.
+pub fn format_to_game(source: String, trusted: bool, mentions: &Mentions) -> String {
+    let mut out = String::new();
+    render_game(&markdown::parse(&source), !trusted, mentions, &mut out);
+    out
+}
+
+/// Render parsed Discord markdown nodes as Brickadia chat codes.
+fn render_game(nodes: &[Node], escape: bool, mentions: &Mentions, out: &mut String) {
+    let text = |text: &str| match escape {
+        true => sanitize(text),
+        false => text.to_owned(),
+    };
+
+    for node in nodes.iter() {
+        match node {
+            Node::Text(t) => out.push_str(&text(t)),
+            Node::LineBreak => out.push('\n'),
+            Node::Bold(children) => wrap_game(out, escape, mentions, "<b>", children),
+            Node::Italic(children) => wrap_game(out, escape, mentions, "<i>", children),
+            Node::Underline(children) => wrap_game(out, escape, mentions, "<u>", children),
+            Node::Header { children, .. } => wrap_game(out, escape, mentions, "<b>", children),
+            Node::Strikethrough(children) => {
+                out.push_str("~~");
+                render_game(children, escape, mentions, out);
+                out.push_str("~~");
+            }
+            Node::Spoiler(children) => {
+                out.push_str("[spoiler: ");
+                render_game(children, escape, mentions, out);
+                out.push(']');
+            }
+            Node::Code(code) | Node::CodeBlock { code, .. } => {
+                out.push_str("<code>");
+                out.push_str(&text(code));
+                out.push_str("</>");
+            }
+            Node::Link { url, label } => {
+                out.push_str(&format!("<link=\"{}\">", escape_url(url)));
+                render_game(label, escape, mentions, out);
+                out.push_str("</>");
+            }
+            Node::Emoji { name, .. } => match name.strip_prefix("br_") {
+                Some(name) => out.push_str(&format!("<emoji>{}</>", text(name))),
+                None => out.push_str(&format!(":{}:", text(name))),
+            },
+            Node::UserMention(id) => out.push_str(&format!(
+                "<link=\"https://discord.com/users/{}\">@{}</>",
+                id,
+                mentions
+                    .users
+                    .get(id)
+                    .map_or_else(|| "mentioned".into(), |name| sanitize(name))
+            )),
+            Node::RoleMention(id) => out.push_str(&format!(
+                "<b>@{}</>",
+                mentions
+                    .roles
+                    .get(id)
+                    .map_or_else(|| "deleted-role".into(), |name| sanitize(name))
+            )),
+            Node::ChannelMention(id) => out.push_str(&format!(
+                "<b>#{}</>",
+                mentions
+                    .channels
+                    .get(id)
+                    .map_or_else(|| "deleted-channel".into(), |name| sanitize(name))
+            )),
+            Node::Timestamp { time, style } => {
+                out.push_str(&describe_timestamp(*time, *style, mentions.time))
+            }
+            Node::Quote(children) => {
+                let mut quote = String::new();
+                render_game(children, escape, mentions, &mut quote);
+                out.push_str("> ");
+                out.push_str(&quote.replace('\n', "\n> "));
+            }
+            Node::ListItem { marker, children } => {
+                match marker {
+                    Some(n) => out.push_str(&format!("{}. ", n)),
+                    None => out.push_str("• "),
+                }
+                render_game(children, escape, mentions, out);
+            }
+        }
+    }
+}
+
+fn wrap_game(out: &mut String, escape: bool, mentions: &Mentions, tag: &str, children: &[Node]) {
+    out.push_str(tag);
+    render_game(children, escape, mentions, out);
+    out.push_str("</>");
+}
+
+/// The longest a Discord message can be.
+pub const MESSAGE_LIMIT: usize = 2000;
+
+/// Split text into pieces short enough for Discord messages, between lines where possible.
+///
+/// A code block split across pieces is closed and reopened, so each piece renders on its own.
+pub fn split_message(content: &str) -> Vec<String> {
+    const FENCE: &str = "```";
+
+    // room kept for closing a code block at the end of a piece
+    const RESERVED: usize = FENCE.len() + 1;
+
+    let mut pieces = vec![];
+    let mut piece = String::new();
+    let mut in_code = false;
+
+    for line in content.split('\n') {
+        for part in split_chars(line, MESSAGE_LIMIT - 2 * RESERVED) {
+            if !piece.is_empty() && piece.len() + 1 + part.len() + RESERVED > MESSAGE_LIMIT {
+                if in_code {
+                    piece.push('\n');
+                    piece.push_str(FENCE);
+                }
+                pieces.push(std::mem::take(&mut piece));
+                if in_code {
+                    piece.push_str(FENCE);
+                }
+            }
+
+            if !piece.is_empty() {
+                piece.push('\n');
+            }
+            piece.push_str(part);
+        }
+
+        if line.matches(FENCE).count() % 2 == 1 {
+            in_code = !in_code;
+        }
+    }
+
+    if !piece.is_empty() || pieces.is_empty() {
+        pieces.push(piece);
+    }
+    pieces
+}
+
+/// Split text into parts of at most `max` bytes, between characters.
+fn split_chars(text: &str, max: usize) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut rest = text;
+    while rest.len() > max {
+        let mut i = max;
+        while !rest.is_char_boundary(i) {
+            i -= 1;
+        }
+        parts.push(&rest[..i]);
+        rest = &rest[i..];
+    }
+
+    parts.push(rest);
+    parts
+}
+
+/// Characters that have meaning in Discord's markdown.
+const MARKDOWN_CHARS: &str = "\\*_~`|<>[]#";
+
+/// Escape Discord markdown in some text, so it is shown as-is in Discord.
+pub fn escape_markdown(text: &str) -> String {
+    let mut buf = String::with_capacity(text.len());
+    for c in text.chars() {
+        if MARKDOWN_CHARS.contains(c) {
+            buf.push('\\');
+        }
+        buf.push(c);
+    }
+    buf
+}
+
+/// Decode the entities Brickadia uses to escape its chat codes.
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&scl;", ";")
+        .replace("&amp;", "&")
+}
+
+/// A node of a parsed Brickadia chat message.
+#[derive(Debug)]
+enum ChatNode {
+    Text(String),
+    Tag {
+        name: String,
+        value: Option<String>,
+        children: Vec<ChatNode>,
+    },
+}
+
+/// Parse a message with Brickadia's chat codes into a tree of tags.
+fn parse_chat(source: &str) -> Vec<ChatNode> {
     lazy_static! {
-        static ref REGEXES: Vec<(Regex, &'static str)> = vec![
-            (
-                Regex::new("<:br_([A-Za-z_]+):\\d+>").unwrap(),
-                "<emoji>$1</>"
+        static ref TAG: Regex = Regex::new("<(/?)([a-z]*)(?:=\"?([^\">]*)\"?)?>").unwrap();
+    }
+
+    // a stack of open tags, with the root at the bottom
+    let mut stack: Vec<(String, Option<String>, Vec<ChatNode>)> =
+        vec![(String::new(), None, vec![])];
+    let mut last = 0;
+
+    for captures in TAG.captures_iter(source) {
+        let tag = captures.get(0).unwrap();
+        if tag.start() > last {
+            let text = ChatNode::Text(decode_entities(&source[last..tag.start()]));
+            stack.last_mut().unwrap().2.push(text);
+        }
+        last = tag.end();
+
+        let name = captures.get(2).map(|m| m.as_str()).unwrap_or_default();
+        if !captures[1].is_empty() {
+            // any closing tag closes the innermost open tag
+            if stack.len() > 1 {
+                let (name, value, children) = stack.pop().unwrap();
+                stack.last_mut().unwrap().2.push(ChatNode::Tag {
+                    name,
+                    value,
+                    children,
+                });
+            }
+        } else if name == "br" {
+            stack
+                .last_mut()
+                .unwrap()
+                .2
+                .push(ChatNode::Text("\n".into()));
+        } else {
+            let value = captures.get(3).map(|m| m.as_str().to_owned());
+            stack.push((name.to_owned(), value, vec![]));
+        }
+    }
+
+    if last < source.len() {
+        let text = ChatNode::Text(decode_entities(&source[last..]));
+        stack.last_mut().unwrap().2.push(text);
+    }
+
+    // close anything left open
+    while stack.len() > 1 {
+        let (name, value, children) = stack.pop().unwrap();
+        stack.last_mut().unwrap().2.push(ChatNode::Tag {
+            name,
+            value,
+            children,
+        });
+    }
+
+    stack.pop().unwrap().2
+}
+
+/// Find the names of players mentioned like `@Name` in a message from the game.
+pub fn mentioned_names(source: &str) -> Vec<String> {
+    let text = decode_entities(source);
+    let mut names = vec![];
+    let mut rest = text.as_str();
+
+    while let Some(i) = rest.find('@') {
+        rest = &rest[i + 1..];
+        let len = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+        if len > 0
+            && !names
+                .iter()
+                .any(|n: &String| n.eq_ignore_ascii_case(&rest[..len]))
+        {
+            names.push(rest[..len].to_owned());
+        }
+        rest = &rest[len..];
+    }
+
+    names
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+/// Convert a message with Brickadia's chat codes to Discord markdown.
+///
+/// `emojis` is a list of `NAME:EMOJI` entries used to show `<emoji>NAME</>` in Discord,
+/// and `mentions` maps lowercase player names to the Discord user IDs to mention for `@Name`.
+pub fn format_to_discord(
+    source: &str,
+    emojis: &[String],
+    mentions: &HashMap<String, String>,
+) -> String {
+    let mut out = String::new();
+    render_discord(&parse_chat(source), emojis, mentions, &mut out);
+    out
+}
+
+fn render_discord(
+    nodes: &[ChatNode],
+    emojis: &[String],
+    mentions: &HashMap<String, String>,
+    out: &mut String,
+) {
+    for node in nodes.iter() {
+        let (name, value, children) = match node {
+            ChatNode::Text(text) => {
+                render_discord_text(text, mentions, out);
+                continue;
+            }
+            ChatNode::Tag {
+                name,
+                value,
+                children,
+            } => (name.as_str(), value, children),
+        };
+
+        let mut inner = String::new();
+        match name {
+            "code" => {
+                inner = plain_text(children).replace('`', "'");
+                if !inner.trim().is_empty() {
+                    out.push_str(&format!("`{}`", inner));
+                }
+                continue;
+            }
+            "emoji" => {
+                let emoji = plain_text(children);
+                match emojis
+                    .iter()
+                    .filter_map(|e| e.split_once(':'))
+                    .find(|(n, _)| *n == emoji)
+                {
+                    Some((_, text)) => out.push_str(text),
+                    None => out.push_str(&format!(":{}:", escape_markdown(&emoji))),
+                }
+                continue;
+            }
+            _ => render_discord(children, emojis, mentions, &mut inner),
+        }
+
+        if inner.trim().is_empty() {
+            out.push_str(&inner);
+            continue;
+        }
+
+        match (name, value) {
+            ("b", _) => out.push_str(&format!("**{}**", inner)),
+            ("i", _) => out.push_str(&format!("*{}*", inner)),
+            ("u", _) => out.push_str(&format!("__{}__", inner)),
+            ("link", Some(url)) if url.starts_with("http://") || url.starts_with("https://") => {
+                out.push_str(&format!("[{}](<{}>)", inner, url.replace(')', "%29")))
+            }
+            _ => out.push_str(&inner),
+        }
+    }
+}
+
+fn render_discord_text(text: &str, mentions: &HashMap<String, String>, out: &mut String) {
+    let mut rest = text;
+
+    while let Some(i) = rest.find('@') {
+        out.push_str(&escape_markdown(&rest[..i]));
+        rest = &rest[i + 1..];
+
+        let len = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+        match mentions.get(&rest[..len].to_lowercase()) {
+            Some(id) => {
+                out.push_str(&format!("<@{}>", id));
+                rest = &rest[len..];
+            }
+            None => out.push('@'),
+        }
+    }
+
+    out.push_str(&escape_markdown(rest));
+}
+
+/// Get the text inside some chat nodes, ignoring their tags.
+fn plain_text(nodes: &[ChatNode]) -> String {
+    nodes
+        .iter()
+        .map(|n| match n {
+            ChatNode::Text(text) => text.to_owned(),
+            ChatNode::Tag { children, .. } => plain_text(children),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{
+        describe_size, describe_timestamp, format_content, format_to_discord, format_to_game,
+        mentioned_names, shorten, split_message, Formatter, Mentions, MESSAGE_LIMIT,
+    };
+
+    fn assert_game(source: &str, expected: &str) {
+        assert_eq!(
+            format_to_game(source.to_owned(), false, &Mentions::default()),
+            expected,
+            "source: {:?}",
+            source
+        );
+    }
+
+    #[test]
+    fn formatter_values_are_not_formatted() {
+        let formatters = [
+            Formatter {
+                key: "user",
+                value: "$message".to_owned(),
+            },
+            Formatter {
+                key: "message",
+                value: "costs $5".to_owned(),
+            },
+        ];
+
+        assert_eq!(
+            format_content("$user: $message".to_owned(), &formatters),
+            "$message: costs $5"
+        );
+    }
+
+    #[test]
+    fn sizes_and_shortening() {
+        assert_eq!(describe_size(512), "512 B");
+        assert_eq!(describe_size(1536), "1.5 KB");
+        assert_eq!(describe_size(3 * 1024 * 1024), "3.0 MB");
+        assert_eq!(shorten("short", 10), "short");
+        assert_eq!(shorten("a bit too long", 6), "a bit…");
+        assert_eq!(shorten("ééééé", 4), "ééé…");
+    }
+
+    #[test]
+    fn plain_text() {
+        assert_game("hello world", "hello world");
+        assert_game("", "");
+        assert_game("2 * 3 * 4 = 24", "2 * 3 * 4 = 24");
+        assert_game("a_b and c_d", "a_b and c_d");
+        assert_game("snake_case_variable", "snake_case_variable");
+        assert_game("~~", "~~");
+        assert_game("**", "**");
+        assert_game(
+            "* a list item at the start of a line",
+            "\u{2022} a list item at the start of a line",
+        );
+        assert_game("a * b", "a * b");
+    }
+
+    #[test]
+    fn emphasis() {
+        assert_game("**bold**", "<b>bold</>");
+        assert_game("*italic*", "<i>italic</>");
+        assert_game("_italic_", "<i>italic</>");
+        assert_game("__underline__", "<u>underline</>");
+        assert_game("***bold italic***", "<b><i>bold italic</></>");
+        assert_game("__*underline italic*__", "<u><i>underline italic</></>");
+        assert_game(
+            "**bold *and italic* text**",
+            "<b>bold <i>and italic</> text</>",
+        );
+        assert_game(
+            "*italic **and bold** text*",
+            "<i>italic <b>and bold</> text</>",
+        );
+    }
+
+    #[test]
+    fn multiple_spans() {
+        assert_game("**one** and **two**", "<b>one</> and <b>two</>");
+        assert_game("*one* and *two*", "<i>one</> and <i>two</>");
+        assert_game("_one_ and _two_", "<i>one</> and <i>two</>");
+        assert_game(
+            "i **really** like __this__ *map*",
+            "i <b>really</> like <u>this</> <i>map</>",
+        );
+    }
+
+    #[test]
+    fn unclosed_and_spaced_delimiters() {
+        assert_game("**not closed", "**not closed");
+        assert_game(
+            "* a list item, not italic *",
+            "\u{2022} a list item, not italic *",
+        );
+        assert_game("this is * not italic*", "this is * not italic*");
+        assert_game("a *b", "a *b");
+        assert_game("_not closed", "_not closed");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_game("\\*not italic\\*", "*not italic*");
+        assert_game("\\*\\*not bold\\*\\*", "**not bold**");
+        assert_game("\\_not italic\\_", "_not italic_");
+        assert_game("C:\\Users\\me", "C:\\Users\\me");
+    }
+
+    #[test]
+    fn code() {
+        assert_game("`code`", "<code>code</>");
+        assert_game("`*not italic*`", "<code>*not italic*</>");
+        assert_game("`a` and `b`", "<code>a</> and <code>b</>");
+        assert_game("``has ` tick``", "<code>has ` tick</>");
+        assert_game("`` `ticked` ``", "<code>`ticked`</>");
+        assert_game(
+            "**bold `**` still bold**",
+            "<b>bold <code>**</> still bold</>",
+        );
+        assert_game("`unclosed", "`unclosed");
+    }
+
+    #[test]
+    fn code_blocks() {
+        assert_game("```\nlet x = *y*;\n```", "<code>let x = *y*&scl;</>");
+        assert_game("```rust\nfn main() {}\n```", "<code>fn main() {}</>");
+        assert_game("```inline```", "<code>inline</>");
+        assert_game("look:\n```\na\nb\n```\nnice", "look:\n<code>a\nb</>\nnice");
+    }
+
+    #[test]
+    fn links() {
+        assert_game(
+            "https://example.com/some_path_with_underscores",
+            "<link=\"https://example.com/some_path_with_underscores\">https://example.com/some_path_with_underscores</>",
+        );
+        assert_game(
+            "check https://example.com.",
+            "check <link=\"https://example.com\">https://example.com</>.",
+        );
+        assert_game(
+            "(https://example.com/a)",
+            "(<link=\"https://example.com/a\">https://example.com/a</>)",
+        );
+        assert_game(
+            "<https://example.com/no_embed>",
+            "<link=\"https://example.com/no_embed\">https://example.com/no_embed</>",
+        );
+        assert_game(
+            "[the wiki](https://wiki.brickadia.com/Main_Page)",
+            "<link=\"https://wiki.brickadia.com/Main_Page\">the wiki</>",
+        );
+        assert_game(
+            "_see <https://a.com/x_y>_",
+            "<i>see <link=\"https://a.com/x_y\">https://a.com/x_y</></>",
+        );
+        assert_game("[not a link](nope)", "[not a link](nope)");
+        assert_game("http://", "http://");
+    }
+
+    #[test]
+    fn strikethrough_and_spoilers() {
+        assert_game("~~gone~~", "~~gone~~");
+        assert_game("~~**gone**~~", "~~<b>gone</>~~");
+        assert_game("||secret||", "[spoiler: secret]");
+        assert_game(
+            "the killer is ||*nobody*||",
+            "the killer is [spoiler: <i>nobody</>]",
+        );
+        assert_game("a || b", "a || b");
+    }
+
+    #[test]
+    fn emoji_and_mentions() {
+        assert_game("<:br_egg:123456>", "<emoji>egg</>");
+        assert_game("<a:br_blegg:123456>", "<emoji>blegg</>");
+        assert_game("<:pepega:123456>", ":pepega:");
+        assert_game(
+            "hi <@!1234>",
+            "hi <link=\"https://discord.com/users/1234\">@mentioned</>",
+        );
+        assert_game(
+            "hi <@1234>",
+            "hi <link=\"https://discord.com/users/1234\">@mentioned</>",
+        );
+        assert_game(
+            "<@&1234> in <#5678>",
+            "<b>@deleted-role</> in <b>#deleted-channel</>",
+        );
+        assert_game(
+            "<@&> <#general> <t:12:Q>",
+            "&lt;@&amp;&gt; &lt;#general&gt; &lt;t:12:Q&gt;",
+        );
+        assert_game("<:br_snake_case:1>", "<emoji>snake_case</>");
+        assert_game("1 < 2 > 0", "1 &lt; 2 &gt; 0");
+    }
+
+    #[test]
+    fn mentions_are_named() {
+        let mut mentions = Mentions {
+            time: 1_618_953_630,
+            ..Mentions::default()
+        };
+        mentions.users.insert("1".into(), "<bob>".into());
+        mentions.roles.insert("2".into(), "Admin".into());
+        mentions.channels.insert("3".into(), "general".into());
+
+        assert_eq!(
+            format_to_game(
+                "**<@!1>** ask <@&2> in <#3> <t:1618953330:R>".to_owned(),
+                false,
+                &mentions
             ),
-            (
-                Regex::new("<@!(\\d+)>").unwrap(),
-                "<link=\"https://discord.com/users/$1\">@mentioned</>"
+            "<b><link=\"https://discord.com/users/1\">@&lt;bob&gt;</></> ask <b>@Admin</> in \
+             <b>#general</> 5 minutes ago"
+        );
+    }
+
+    #[test]
+    fn timestamps_are_described() {
+        let time = 1_618_953_630;
+        assert_eq!(describe_timestamp(time, 't', 0), "21:20 UTC");
+        assert_eq!(describe_timestamp(time, 'T', 0), "21:20:30 UTC");
+        assert_eq!(describe_timestamp(time, 'd', 0), "20/04/2021");
+        assert_eq!(describe_timestamp(0, 'd', 0), "01/01/1970");
+        assert_eq!(describe_timestamp(time, 'D', 0), "20 April 2021");
+        assert_eq!(describe_timestamp(time, 'f', 0), "20 April 2021 21:20 UTC");
+        assert_eq!(
+            describe_timestamp(time, 'F', 0),
+            "Tuesday, 20 April 2021 21:20 UTC"
+        );
+        assert_eq!(
+            describe_timestamp(0, 'F', 0),
+            "Thursday, 1 January 1970 00:00 UTC"
+        );
+        assert_eq!(describe_timestamp(-86_400, 'D', 0), "31 December 1969");
+        assert_eq!(
+            describe_timestamp(time + 7200, 'R', time as u64),
+            "in 2 hours"
+        );
+        assert!(describe_timestamp(i64::MIN, 'R', time as u64).ends_with(" hours ago"));
+        assert!(describe_timestamp(i64::MAX, 'R', 0).starts_with("in "));
+        assert!(describe_timestamp(i64::MIN, 'F', 0).ends_with(" UTC"));
+    }
+
+    #[test]
+    fn escaped_chat_codes() {
+        assert_game(
+            "<color=\"f00\"><size=\"200\">LOUD</></>",
+            "&lt;color=\"f00\"&gt;&lt;size=\"200\"&gt;LOUD&lt;/&gt;&lt;/&gt;",
+        );
+        assert_game(
+            "<link=\"evil\">free bricks</>",
+            "&lt;link=\"evil\"&gt;free bricks&lt;/&gt;",
+        );
+        assert_game("**<b>bold</>**", "<b>&lt;b&gt;bold&lt;/&gt;</>");
+        assert_game("`<code>`", "<code>&lt;code&gt;</>");
+        assert_game("fish & chips; please", "fish &amp; chips&scl; please");
+    }
+
+    #[test]
+    fn trusted_chat_codes() {
+        assert_eq!(
+            format_to_game(
+                "<color=\"f00\">**red**</>".to_owned(),
+                true,
+                &Mentions::default()
             ),
-            (Regex::new("`(.+)`").unwrap(), "<code>$1</>"),
-            (Regex::new("\\*\\*(.+)\\*\\*").unwrap(), "<b>$1</>"),
-            (Regex::new("\\*(.+)\\*").unwrap(), "<i>$1</>"),
-            (Regex::new("__(.+)__").unwrap(), "<u>$1</>"),
-            (Regex::new("_(.+)_").unwrap(), "<i>$1</>"),
-        ];
+            "<color=\"f00\"><b>red</></>"
+        );
+    }
+
+    #[test]
+    fn block_quotes() {
+        assert_game("> quoted", "> quoted");
+        assert_game("> **quoted**\nreply", "> <b>quoted</>\nreply");
+        assert_game(">>> one\ntwo", "> one\n> two");
+        assert_game(">not a quote", "&gt;not a quote");
+    }
+
+    #[test]
+    fn headers_and_lists() {
+        assert_game("# Title", "<b>Title</>");
+        assert_game("### Small *title*", "<b>Small <i>title</></>");
+        assert_game("#hashtag", "#hashtag");
+        assert_game("- one\n- two", "\u{2022} one\n\u{2022} two");
+        assert_game("1. first\n2. second", "1. first\n2. second");
+        assert_game("not # a header", "not # a header");
     }
 
-    let mut source = source;
+    fn assert_discord(source: &str, expected: &str) {
+        let emojis = vec!["egg:<:br_egg:1234>".to_owned()];
+        let mut mentions = HashMap::new();
+        mentions.insert("voximity".to_owned(), "5678".to_owned());
 
-    for (regex, replacement) in REGEXES.iter() {
-        source = regex.replace_all(&source, *replacement).to_string();
+        assert_eq!(
+            format_to_discord(source, &emojis, &mentions),
+            expected,
+            "source: {:?}",
+            source
+        );
     }
 
-    source
+    #[test]
+    fn discord_plain_text() {
+        assert_discord("hello world", "hello world");
+        assert_discord("**not bold**", "\\*\\*not bold\\*\\*");
+        assert_discord(
+            "||spoiler|| ~~strike~~",
+            "\\|\\|spoiler\\|\\| \\~\\~strike\\~\\~",
+        );
+        assert_discord("snake_case", "snake\\_case");
+        assert_discord("&lt;@&amp;1234&gt;", "\\<@&1234\\>");
+        assert_discord("a&scl; b", "a; b");
+    }
+
+    #[test]
+    fn discord_chat_codes() {
+        assert_discord("<b>bold</>", "**bold**");
+        assert_discord("<i>italic</i>", "*italic*");
+        assert_discord("<u>under</>", "__under__");
+        assert_discord("<b><i>both</></>", "***both***");
+        assert_discord("<code>a*b</>", "`a*b`");
+        assert_discord("<color=\"f00\">red</>", "red");
+        assert_discord("<size=\"40\"><b>big</></>", "**big**");
+        assert_discord(
+            "<link=\"https://brickadia.com\">site</>",
+            "[site](<https://brickadia.com>)",
+        );
+        assert_discord("<link=\"javascript:x\">nope</>", "nope");
+        assert_discord("<b></>", "");
+        assert_discord("<b>unclosed", "**unclosed**");
+        assert_discord("one<br>two", "one\ntwo");
+    }
+
+    #[test]
+    fn discord_emoji() {
+        assert_discord("<emoji>egg</>", "<:br_egg:1234>");
+        assert_discord("<emoji>blegg</>", ":blegg:");
+    }
+
+    #[test]
+    fn discord_mentions() {
+        assert_discord("hi @voximity!", "hi <@5678>!");
+        assert_discord("hi @VOXIMITY", "hi <@5678>");
+        assert_discord("@everyone @here", "@everyone @here");
+        assert_discord("mail me@example.com", "mail me@example.com");
+        assert_eq!(
+            mentioned_names("@a and @b_c and @A"),
+            vec!["a".to_owned(), "b_c".to_owned()]
+        );
+    }
+
+    #[test]
+    fn long_messages_are_split_between_lines() {
+        assert_eq!(split_message("short"), ["short"]);
+
+        let line = "x".repeat(900);
+        let pieces = split_message(&format!("```\n{}\n{}\n{}\n```", line, line, line));
+        assert_eq!(pieces.len(), 2);
+        assert!(pieces.iter().all(|p| p.len() <= MESSAGE_LIMIT));
+        assert_eq!(pieces[0], format!("```\n{}\n{}\n```", line, line));
+        assert_eq!(pieces[1], format!("```\n{}\n```", line));
+
+        let pieces = split_message(&"é".repeat(MESSAGE_LIMIT));
+        assert_eq!(pieces.concat(), "é".repeat(MESSAGE_LIMIT));
+        assert!(pieces.iter().all(|p| p.len() <= MESSAGE_LIMIT));
+    }
 }
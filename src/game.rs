@@ -0,0 +1,600 @@
+use futures::future::BoxFuture;
+use omegga::resources::Player;
+use twilight_model::id::UserId;
+
+use crate::{
+    admin::{self, AdminEvent, AdminKind},
+    channels::{self, EventKind},
+    commands, discord,
+    error::{Error, Result},
+    events::{Dispatcher, GameEvent, Handler},
+    format::{
+        compose_vec, describe_duration, escape_markdown, format_to_discord, role_text, sanitize,
+        Formatter,
+    },
+    history, link, playtime,
+    queue::{Author, Outgoing},
+    roles, status,
+    verify::{self, now},
+    whois, State,
+};
+
+/// The subcommands of `/discord` handled by [`Verification`].
+const VERIFY_COMMANDS: &[&str] = &["verify", "relink", "unverify"];
+
+/// The subcommands of `/discord` logged as their own admin events rather than host commands.
+const LOGGED_COMMANDS: &[&str] = &["wipe", "unverify"];
+
+/// Every feature that reacts to game events, in the order they run.
+pub fn dispatcher() -> Dispatcher {
+    Dispatcher::new()
+        .register("server info", ServerInfo)
+        .register("history", LastSeen)
+        .register("digest", DigestCounter)
+        .register("relay", Relay)
+        .register("role sync", RoleSync)
+        .register("verification", Verification)
+        .register("discord command", DiscordCommand)
+        .register("admin log", AdminLog)
+        .register("console capture", ConsoleCapture)
+}
+
+/// Find an online player by their name or UUID.
+async fn find_player(state: &State, player: &str) -> Result<Player> {
+    state
+        .omegga
+        .get_player(player)
+        .await?
+        .ok_or_else(|| Error::PlayerNotFound(player.to_owned()))
+}
+
+async fn user_formatters(state: &State, user: String) -> Result<Vec<Formatter>> {
+    let roles = state
+        .omegga
+        .get_player_roles(&user)
+        .await?
+        .unwrap_or_else(Vec::new);
+
+    Ok(vec![
+        Formatter {
+            key: "role",
+            value: role_text(&roles, &state.config().game_roles),
+        },
+        Formatter {
+            key: "user",
+            value: escape_markdown(&user),
+        },
+    ])
+}
+
+/// Keeps the status message and channel name up to date with the server.
+pub struct ServerInfo;
+
+impl Handler for ServerInfo {
+    fn handle<'a>(&'a self, state: &'a State, event: &'a GameEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match event {
+                GameEvent::Start { map } => {
+                    state.status.server_started(map.clone());
+                    status::persist(state);
+                }
+                GameEvent::MapChange { map } => {
+                    state.status.map_changed(map.clone());
+                    status::persist(state);
+                }
+                GameEvent::Join(_) | GameEvent::Leave(_) => (),
+                _ => return Ok(()),
+            }
+
+            state.status.request_update();
+            state.channel_name.request_update();
+            Ok(())
+        })
+    }
+}
+
+/// Remembers players and their playtime as they join and leave, for looking them up later.
+pub struct LastSeen;
+
+impl Handler for LastSeen {
+    fn handle<'a>(&'a self, state: &'a State, event: &'a GameEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match event {
+                GameEvent::Join(player) => state.history.join(player, now()),
+                GameEvent::Leave(player) => state.history.leave(player, now()),
+
+                // nobody is online after the server starts, so sessions left open by it
+                // stopping are over
+                GameEvent::Start { .. } => state.history.close_sessions(&[]),
+                _ => return Ok(()),
+            }
+
+            history::persist(state);
+            Ok(())
+        })
+    }
+}
+
+/// Counts players and chat for the digest.
+pub struct DigestCounter;
+
+impl Handler for DigestCounter {
+    fn handle<'a>(&'a self, state: &'a State, event: &'a GameEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match event {
+                GameEvent::Join(player) => {
+                    let time = now();
+                    let players = state.omegga.get_players().await?.len();
+                    let playtime = state
+                        .history
+                        .get(&player.id)
+                        .map_or(0, |r| r.playtime(time));
+                    state.digest.online(&player.id, playtime, players, time);
+                }
+                GameEvent::Chat { user, .. }
+                    if channels::bindings(&state.config(), state.channel_id)
+                        .iter()
+                        .any(|b| b.receives(EventKind::Chat)) =>
+                {
+                    state.digest.chat_to_discord(user)
+                }
+                _ => (),
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// Relays the server starting, chat, and players joining and leaving to Discord.
+pub struct Relay;
+
+impl Handler for Relay {
+    fn handle<'a>(&'a self, state: &'a State, event: &'a GameEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match event {
+                GameEvent::Start { map } => {
+                    relay_start(state, map).await;
+                    Ok(())
+                }
+                GameEvent::Chat { user, message } => relay_chat(state, user, message).await,
+                GameEvent::Join(player) => relay_join_leave(state, player, true).await,
+                GameEvent::Leave(player) => relay_join_leave(state, player, false).await,
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+async fn relay_start(state: &State, map: &str) {
+    let formatters = compose_vec(vec![
+        vec![Formatter {
+            key: "map",
+            value: map.to_owned(),
+        }],
+        status::formatters_for(state, EventKind::Start, "server-start-format").await,
+    ]);
+
+    channels::relay(
+        state,
+        EventKind::Start,
+        "server-start-format",
+        &formatters,
+        Outgoing {
+            channel_id: state.channel_id,
+            author: None,
+            content: String::new(),
+            embeds: vec![],
+            mentions: vec![],
+            batch: false,
+        },
+    );
+}
+
+async fn relay_chat(state: &State, user: &str, message: &str) -> Result<()> {
+    let mentions = discord::resolve_mentions(state, message).await?;
+    let author = user_formatters(state, user.to_owned()).await?;
+    let formatters = compose_vec(vec![
+        author.clone(),
+        vec![Formatter {
+            key: "message",
+            value: format_to_discord(message, &state.config().discord_emojis, &mentions),
+        }],
+    ]);
+
+    let mentions = mentions
+        .values()
+        .filter_map(|id| id.parse().ok().map(UserId))
+        .collect::<Vec<_>>();
+
+    channels::relay(
+        state,
+        EventKind::Chat,
+        "discord-message-format",
+        &formatters,
+        Outgoing {
+            channel_id: state.channel_id,
+            author: Some(Author {
+                name: user.to_owned(),
+                id: None,
+                formatters: author,
+            }),
+            content: String::new(),
+            embeds: vec![],
+            mentions,
+            batch: true,
+        },
+    );
+
+    Ok(())
+}
+
+async fn relay_join_leave(state: &State, player: &Player, joined: bool) -> Result<()> {
+    let option = if joined {
+        "join-message-format"
+    } else {
+        "leave-message-format"
+    };
+    let formatters = user_formatters(state, player.name.clone()).await?;
+    let info = status::formatters_for(state, EventKind::JoinLeave, option).await;
+
+    channels::relay(
+        state,
+        EventKind::JoinLeave,
+        option,
+        &compose_vec(vec![formatters.clone(), info]),
+        Outgoing {
+            channel_id: state.channel_id,
+            author: Some(Author {
+                name: player.name.clone(),
+                id: Some(player.id.clone()),
+                formatters: formatters.clone(),
+            }),
+            content: String::new(),
+            embeds: vec![],
+            mentions: vec![],
+            batch: false,
+        },
+    );
+
+    Ok(())
+}
+
+/// Sends the server starting and commands run by the host to admin channels.
+pub struct AdminLog;
+
+impl Handler for AdminLog {
+    fn handle<'a>(&'a self, state: &'a State, event: &'a GameEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match event {
+                GameEvent::Start { map } => admin::log(
+                    state,
+                    AdminEvent::new(
+                        AdminKind::Start,
+                        format!("Started on {}.", escape_markdown(map)),
+                    ),
+                ),
+                GameEvent::Command {
+                    command,
+                    user,
+                    args,
+                } if !(command == "discord"
+                    && args
+                        .first()
+                        .is_some_and(|a| LOGGED_COMMANDS.contains(&a.as_str()))) =>
+                {
+                    let player = find_player(state, user).await?;
+                    if player.host.unwrap_or(false) {
+                        admin::log(
+                            state,
+                            AdminEvent {
+                                actor: Some(escape_markdown(&player.name)),
+                                ..AdminEvent::new(
+                                    AdminKind::HostCommand,
+                                    format!("`/{} {}`", command, args.join(" ")),
+                                )
+                            },
+                        );
+                    }
+                }
+                _ => (),
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// Captures console output for commands run from Discord.
+pub struct ConsoleCapture;
+
+impl Handler for ConsoleCapture {
+    fn handle<'a>(&'a self, state: &'a State, event: &'a GameEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if let GameEvent::Line(line) = event {
+                state.console.push(line);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// Syncs mapped roles for players as they join.
+pub struct RoleSync;
+
+impl Handler for RoleSync {
+    fn handle<'a>(&'a self, state: &'a State, event: &'a GameEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if let GameEvent::Join(player) = event {
+                // syncing roles can wait on discord, so do it in the background
+                let state = state.clone();
+                let player = player.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = roles::sync_player(&state, &player, None).await {
+                        state
+                            .omegga
+                            .error(format!("Error syncing roles for {}: {}", player.name, e));
+                    }
+                });
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// Handles `/discord verify`, `/discord relink` and `/discord unverify`.
+pub struct Verification;
+
+impl Handler for Verification {
+    fn handle<'a>(&'a self, state: &'a State, event: &'a GameEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if !state.config().verification {
+                return Ok(());
+            }
+
+            match event {
+                GameEvent::Leave(player) if state.verifications.pending(&player.id).is_some() => {
+                    // remove their pending verification if the player leaves
+                    state.verifications.remove(&player.id);
+                    verify::persist(state);
+                    Ok(())
+                }
+                GameEvent::Command {
+                    command,
+                    user,
+                    args,
+                } if command == "discord" => match args.first().map(String::as_str) {
+                    Some("verify") => verify(state, user, false).await,
+                    Some("relink") => verify(state, user, true).await,
+                    Some("unverify") => unverify(state, user, &args[1..].join(" ")).await,
+                    _ => Ok(()),
+                },
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+/// Handles `!discord` and the rest of `/discord`.
+pub struct DiscordCommand;
+
+impl Handler for DiscordCommand {
+    fn handle<'a>(&'a self, state: &'a State, event: &'a GameEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match event {
+                GameEvent::ChatCommand { command, .. } if command == "discord" => {
+                    state.omegga.broadcast(format!(
+                        "Join this server's <link=\"{}\">Discord</>.",
+                        state.config().discord_link
+                    ));
+                    Ok(())
+                }
+                GameEvent::Command {
+                    command,
+                    user,
+                    args,
+                } if command == "discord" => match args.first().map(String::as_str) {
+                    None => {
+                        state
+                            .omegga
+                            .whisper(user, "<color=\"a00\">Please specify a command to run.</>");
+                        Ok(())
+                    }
+                    Some("wipe") => wipe(state, user).await,
+                    Some("status") => status(state, user).await,
+                    Some("whois") => whois::run_in_game(state, user, &args[1..].join(" ")).await,
+                    Some(s) if playtime::COMMANDS.contains(&s) => {
+                        playtime::run_in_game(state, user, s, &args[1..].join(" ")).await
+                    }
+                    Some(s) if VERIFY_COMMANDS.contains(&s) => Ok(()),
+                    Some(_) => {
+                        state.omegga.whisper(
+                            user,
+                            format!(
+                                "Join this server's <link=\"{}\">Discord</>.",
+                                state.config().discord_link
+                            ),
+                        );
+                        Ok(())
+                    }
+                },
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+async fn status(state: &State, user: &str) -> Result<()> {
+    let info = status::gather(state).await?;
+    for line in [
+        format!(
+            "<b>Map:</> {}, up for {}",
+            sanitize(&info.describe_map()),
+            describe_duration(info.uptime())
+        ),
+        format!(
+            "<b>Players:</> {}, <b>Bricks:</> {}",
+            info.describe_players(),
+            info.describe_bricks()
+        ),
+        format!("<b>Minigames:</> {}", sanitize(&info.describe_minigames())),
+        format!(
+            "<b>Latency:</> {} to the plugin, {} to Discord",
+            info.describe_latency(),
+            info.describe_bot_latency()
+        ),
+    ] {
+        state.omegga.whisper(user, line);
+    }
+
+    Ok(())
+}
+
+async fn wipe(state: &State, user: &str) -> Result<()> {
+    let player = find_player(state, user).await?;
+    if player.host.unwrap_or(false) {
+        link::unlink_all(state).await?;
+        state.omegga.broadcast("Verification store has been wiped.");
+        admin::log(
+            state,
+            AdminEvent {
+                actor: Some(escape_markdown(&player.name)),
+                ..AdminEvent::new(AdminKind::Wipe, "Every link was removed.")
+            },
+        );
+    }
+
+    Ok(())
+}
+
+async fn verify(state: &State, user: &str, relink: bool) -> Result<()> {
+    let player = find_player(state, user).await?;
+
+    // check if the user is already verified, unless they want to link another account
+    if !relink && link::linked_user(state, &player.id).await?.is_some() {
+        state.omegga.whisper(user, "<color=\"a00\">You are already verified!</> Run <code>/discord relink</> to link a different Discord account, or <code>/discord unverify</> to unlink it.");
+        return Ok(());
+    }
+
+    let (pending, expired) = match state.verifications.pending(&player.id) {
+        Some(pending) if !pending.is_expired() => {
+            state.omegga.whisper(user, format!(
+                "<color=\"a00\">You have already initiated the verification process! Send <code>{} {}</> in the game channel within {}.</>",
+                commands::hint(state, "verify"),
+                pending.code,
+                describe_duration(pending.remaining())
+            ));
+            return Ok(());
+        }
+        Some(_) => (state.verifications.start(&player.id), true),
+        None => (state.verifications.start(&player.id), false),
+    };
+    verify::persist(state);
+
+    state.omegga.whisper(
+        user,
+        format!(
+            "{}To verify, send <code>{} {}</> in the game channel within {}.",
+            if expired {
+                "Your previous code expired. "
+            } else {
+                ""
+            },
+            commands::hint(state, "verify"),
+            pending.code,
+            describe_duration(state.config().verify_code_ttl)
+        ),
+    );
+
+    Ok(())
+}
+
+async fn unverify(state: &State, user: &str, target: &str) -> Result<()> {
+    let player = find_player(state, user).await?;
+
+    // the host can unverify anyone by name or UUID
+    let admin = !target.is_empty();
+    if admin && !player.host.unwrap_or(false) {
+        state.omegga.whisper(
+            user,
+            "<color=\"a00\">Only the host can unverify other players.</>",
+        );
+        return Ok(());
+    }
+
+    let target = if admin {
+        match state.omegga.get_player(target).await? {
+            Some(p) => p.id,
+            None => target.to_owned(),
+        }
+    } else {
+        player.id.clone()
+    };
+
+    let discord_user = match link::unlink_player(state, &target).await? {
+        Some(u) => u,
+        None => {
+            state.omegga.whisper(
+                user,
+                if admin {
+                    "<color=\"a00\">That player is not verified!</>"
+                } else {
+                    "<color=\"a00\">You are not verified!</>"
+                },
+            );
+            return Ok(());
+        }
+    };
+
+    // let the discord user know, in the background as it waits on discord
+    let notice = if admin {
+        "**Your Discord account has been unlinked from Brickadia by the host.**".to_owned()
+    } else {
+        format!(
+            "**Your Discord account has been unlinked from {} in Brickadia.**",
+            escape_markdown(&player.name)
+        )
+    };
+    let task_state = state.clone();
+    tokio::spawn(async move {
+        let state = task_state;
+        if let Err(e) = discord::update_unverified(&state, discord_user).await {
+            state
+                .omegga
+                .error(format!("Error revoking verification: {}", e));
+        }
+
+        if let Err(e) = discord::direct_message(&state, discord_user, &notice).await {
+            state
+                .omegga
+                .log(format!("Couldn't notify unverified user: {}", e));
+        }
+    });
+
+    let target_name = match state.omegga.get_player(&target).await? {
+        Some(target) if admin => {
+            state.omegga.whisper(
+                &target.name,
+                "<color=\"a00\">Your Discord account has been unlinked by the host.</>",
+            );
+            escape_markdown(&target.name)
+        }
+        Some(target) => escape_markdown(&target.name),
+        None => format!("`{}`", target),
+    };
+    admin::log(
+        state,
+        AdminEvent {
+            actor: Some(escape_markdown(&player.name)),
+            target: Some(format!("{} (<@{}>)", target_name, discord_user)),
+            ..AdminEvent::new(AdminKind::Unlink, "Unlinked in game.")
+        },
+    );
+
+    state
+        .omegga
+        .whisper(user, "<color=\"0a0\">Unlinked successfully.</>");
+    Ok(())
+}
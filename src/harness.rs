@@ -0,0 +1,939 @@
+//! End to end tests, running scripted omegga notifications and Discord gateway events through
+//! the plugin against the fakes in `fake.rs`.
+
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+use twilight_gateway::Event;
+use twilight_model::{
+    channel::Message,
+    gateway::payload::MessageCreate,
+    id::{ChannelId, GuildId, RoleId, UserId},
+};
+
+use crate::{
+    admin,
+    backend::{Game, NewMessage},
+    config::{self, Config},
+    digest,
+    events::Dispatcher,
+    fake::{DiscordRequest, FakeDiscord, FakeGame, GameOutput},
+    game, link, queue, roles,
+    verify::now,
+    State,
+};
+
+const CHANNEL: ChannelId = ChannelId(100);
+const GUILD: GuildId = GuildId(10);
+
+/// The plugin's config, with every option at its default unless overridden.
+fn config(overrides: Value) -> Config {
+    let mut config = json!({ "channel-id": CHANNEL.to_string() });
+    for (k, v) in overrides.as_object().unwrap() {
+        config[k] = v.clone();
+    }
+
+    serde_json::from_value(config).unwrap()
+}
+
+struct Harness {
+    state: State,
+    game: Arc<FakeGame>,
+    discord: Arc<FakeDiscord>,
+    dispatcher: Dispatcher,
+    current_user: Option<UserId>,
+    next_message: u64,
+}
+
+impl Harness {
+    fn new(overrides: Value) -> Self {
+        let game = Arc::new(FakeGame::default());
+        let discord = Arc::new(FakeDiscord::default());
+
+        Self {
+            state: State::new(
+                config(overrides),
+                discord.clone(),
+                Game::new(game.clone()),
+                CHANNEL,
+                None,
+            ),
+            game,
+            discord,
+            dispatcher: game::dispatcher(),
+            current_user: Some(UserId(1)),
+            next_message: 1000,
+        }
+    }
+
+    /// Send a notification from omegga, then send anything it queued for Discord.
+    async fn notify(&self, method: &str, params: Value) {
+        self.dispatcher
+            .notify(&self.state, method, Some(params))
+            .await;
+        queue::send_waiting(&self.state).await;
+    }
+
+    /// Send a message in the primary channel from a Discord user.
+    async fn message(&mut self, user: u64, name: &str, content: &str) {
+        self.message_in(CHANNEL, user, name, content).await;
+    }
+
+    /// Send a message in a channel from a Discord user.
+    async fn message_in(&mut self, channel: ChannelId, user: u64, name: &str, content: &str) {
+        self.send_message(channel, user, name, &[], content).await;
+    }
+
+    /// Send a message in the primary channel from a Discord member with some roles.
+    async fn message_with_roles(&mut self, user: u64, name: &str, roles: &[u64], content: &str) {
+        self.send_message(CHANNEL, user, name, roles, content).await;
+    }
+
+    /// Send a message in the primary channel from a Discord user, with some extra fields like
+    /// attachments or a referenced message.
+    async fn message_with(&mut self, user: u64, name: &str, content: &str, extra: Value) {
+        let mut message = self.message_json(CHANNEL, user, name, &[], content);
+        for (key, value) in extra.as_object().unwrap() {
+            message[key] = value.clone();
+        }
+        self.dispatch(message).await;
+    }
+
+    async fn send_message(
+        &mut self,
+        channel: ChannelId,
+        user: u64,
+        name: &str,
+        roles: &[u64],
+        content: &str,
+    ) {
+        let message = self.message_json(channel, user, name, roles, content);
+        self.dispatch(message).await;
+    }
+
+    fn message_json(
+        &mut self,
+        channel: ChannelId,
+        user: u64,
+        name: &str,
+        roles: &[u64],
+        content: &str,
+    ) -> Value {
+        self.next_message += 1;
+        json!({
+            "attachments": [],
+            "author": {
+                "avatar": null,
+                "discriminator": "0001",
+                "id": user.to_string(),
+                "username": name,
+            },
+            "channel_id": channel.to_string(),
+            "content": content,
+            "edited_timestamp": null,
+            "embeds": [],
+            "guild_id": GUILD.to_string(),
+            "id": self.next_message.to_string(),
+            "member": {
+                "deaf": false,
+                "joined_at": null,
+                "mute": false,
+                "nick": null,
+                "roles": roles.iter().map(u64::to_string).collect::<Vec<_>>(),
+            },
+            "mention_everyone": false,
+            "mention_roles": [],
+            "mentions": [],
+            "pinned": false,
+            "timestamp": "2021-01-01T00:00:00+00:00",
+            "tts": false,
+            "type": 0,
+        })
+    }
+
+    async fn dispatch(&mut self, message: Value) {
+        let message = serde_json::from_value::<Message>(message).unwrap();
+        crate::discord::handle_event(
+            &self.state,
+            &mut self.current_user,
+            Event::MessageCreate(Box::new(MessageCreate(message))),
+        )
+        .await;
+    }
+
+    /// The messages sent to a Discord channel.
+    fn sent_to(&self, channel: ChannelId) -> Vec<NewMessage> {
+        self.sent()
+            .into_iter()
+            .filter(|(c, _)| *c == channel)
+            .map(|(_, m)| m)
+            .collect()
+    }
+
+    /// The messages sent to every Discord channel, in order.
+    fn sent(&self) -> Vec<(ChannelId, NewMessage)> {
+        self.discord
+            .take_requests()
+            .into_iter()
+            .filter_map(|r| match r {
+                DiscordRequest::Message { channel, message } => Some((channel, message)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn game_messages_are_relayed_with_formatting() {
+    let harness = Harness::new(json!({}));
+    let player = harness.game.join("Zeb_", "zeb", &[]);
+
+    harness.notify("join", json!([player])).await;
+    harness.notify("chat", json!(["Zeb_", "hi *there*"])).await;
+
+    let sent = harness.sent_to(CHANNEL);
+    assert_eq!(
+        sent.iter().map(|m| m.content.as_str()).collect::<Vec<_>>(),
+        ["**Zeb\\_ joined the game.**", "**Zeb\\_**: hi \\*there\\*"]
+    );
+    assert!(sent.iter().all(|m| m.mentions.is_empty()));
+}
+
+#[tokio::test]
+async fn discord_messages_are_broadcast() {
+    let mut harness = Harness::new(json!({}));
+
+    harness.message(42, "bob", "hello <b>game</>").await;
+    harness.message(1, "the bot", "ignored").await;
+
+    assert_eq!(
+        harness.game.take_output(),
+        [
+            GameOutput::Broadcast(
+                "<color=\"aaaaaa\"><b>bob</b></>: hello &lt;b&gt;game&lt;/&gt;".into()
+            ),
+            GameOutput::Log("<bob> hello &lt;b&gt;game&lt;/&gt;".into()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn attachments_stickers_replies_and_embeds_are_broadcast() {
+    let mut harness = Harness::new(json!({ "embed-format": "<link=\"$url\">$title</>" }));
+    let replied = harness.message_json(
+        CHANNEL,
+        43,
+        "carol",
+        &[],
+        "what does everyone think of the new build?",
+    );
+
+    harness
+        .message_with(
+            42,
+            "bob",
+            "looks good",
+            json!({
+                "referenced_message": replied,
+                "attachments": [{
+                    "filename": "shot<1>.png",
+                    "id": "7",
+                    "proxy_url": "https://media.discordapp.net/shot.png",
+                    "size": 1536,
+                    "url": "https://cdn.discordapp.com/shot.png?a=1&b=2",
+                }],
+                "sticker_items": [{ "format_type": 1, "id": "8", "name": "wave" }],
+                "embeds": [{ "type": "link", "title": "Brickadia", "url": "https://brickadia.com" }],
+            }),
+        )
+        .await;
+    harness
+        .message_with(
+            42,
+            "bob",
+            "",
+            json!({ "sticker_items": [{ "format_type": 1, "id": "8", "name": "wave" }] }),
+        )
+        .await;
+    harness
+        .message_with(42, "bob", "", json!({ "embeds": [{ "type": "rich" }] }))
+        .await;
+
+    let broadcasts = harness
+        .game
+        .take_output()
+        .into_iter()
+        .filter_map(|o| match o {
+            GameOutput::Broadcast(line) => Some(line),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        broadcasts,
+        [
+            "<color=\"aaaaaa\"><b>bob</b></>: <color=\"aaaaaa\">[reply to carol: what does \
+             everyone think of the new bui…]</> looks good \
+             <link=\"https://cdn.discordapp.com/shot.png?a=1&b=2\">[shot&lt;1&gt;.png, 1.5 KB]</> \
+             [sticker: wave] <link=\"https://brickadia.com\">Brickadia</>",
+            "<color=\"aaaaaa\"><b>bob</b></>: [sticker: wave]",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn mentions_are_shown_by_name_in_game() {
+    let mut harness = Harness::new(json!({ "mention-player-names": true }));
+    link::link(&harness.state, "a", UserId(42)).await.unwrap();
+    harness.game.join("alice", "a", &[]);
+
+    // carol is cached once she's sent a message
+    harness.message(5, "carol", "hello").await;
+    harness.game.take_output();
+
+    harness
+        .message_with(
+            7,
+            "dave",
+            "<@42> <@!5> <@8> see <#9>",
+            json!({
+                "mentions": [{
+                    "avatar": null,
+                    "discriminator": "0008",
+                    "id": "8",
+                    "member": {
+                        "deaf": false,
+                        "joined_at": null,
+                        "mute": false,
+                        "nick": "Eve",
+                        "roles": [],
+                    },
+                    "public_flags": 0,
+                    "username": "eve",
+                }],
+            }),
+        )
+        .await;
+
+    assert_eq!(
+        harness.game.take_output()[0],
+        GameOutput::Broadcast(
+            "<color=\"aaaaaa\"><b>dave</b></>: \
+             <link=\"https://discord.com/users/42\">@alice</> \
+             <link=\"https://discord.com/users/5\">@carol</> \
+             <link=\"https://discord.com/users/8\">@Eve</> see <b>#deleted-channel</>"
+                .into()
+        )
+    );
+}
+
+#[tokio::test]
+async fn players_command_lists_players() {
+    let mut harness = Harness::new(json!({ "game-roles": ["Admin:[A]"] }));
+    harness.game.join("alice", "a", &["Admin"]);
+    harness.game.join("b_b", "b", &[]);
+
+    harness.message(42, "bob", "!players").await;
+
+    let sent = harness.sent_to(CHANNEL);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(
+        sent[0].content,
+        "**There are 2 players online.**\n[A] alice\nb\\_b\n"
+    );
+    assert!(sent[0].reply.is_some());
+}
+
+#[tokio::test]
+async fn status_is_shown_in_discord_and_game() {
+    let mut harness = Harness::new(json!({
+        "max-players": 20,
+        "join-message-format": "**$user joined.** ($players/$max, $bricks bricks)",
+    }));
+    harness.game.set_bricks(1234);
+    harness.game.add_minigame("Race");
+    harness.notify("start", json!([{ "map": "Plate" }])).await;
+    harness.sent();
+
+    let player = harness.game.join("alice", "a", &[]);
+    harness.notify("join", json!([player])).await;
+    assert_eq!(
+        harness.sent_to(CHANNEL)[0].content,
+        "**alice joined.** (1/20, 1234 bricks)"
+    );
+
+    harness.message(42, "bob", "!status").await;
+    let content = harness.sent_to(CHANNEL).remove(0).content;
+    assert!(
+        content.starts_with("**The server is online** on Plate, and has been up for 0 seconds.")
+    );
+    assert!(content.contains("**Players:** 1/20\n**Bricks:** 1234\n**Minigames:** Race\n"));
+    assert!(content.ends_with("to the server, unknown to Discord"));
+
+    harness
+        .notify("cmd:discord", json!(["alice", "status"]))
+        .await;
+    let whispers = harness
+        .game
+        .take_output()
+        .into_iter()
+        .filter_map(|o| match o {
+            GameOutput::Whisper { user, line } if user == "alice" => Some(line),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(whispers.len(), 4);
+    assert_eq!(whispers[0], "<b>Map:</> Plate, up for 0 seconds");
+    assert_eq!(whispers[1], "<b>Players:</> 1/20, <b>Bricks:</> 1234");
+    assert_eq!(whispers[2], "<b>Minigames:</> Race");
+}
+
+#[tokio::test]
+async fn empty_formats_send_nothing() {
+    let harness = Harness::new(json!({ "join-message-format": "" }));
+    let player = harness.game.join("alice", "a", &[]);
+    harness.notify("join", json!([player])).await;
+    assert!(harness.sent().is_empty());
+}
+
+#[tokio::test]
+async fn whois_finds_linked_accounts() {
+    let mut harness = Harness::new(json!({}));
+    let alice = harness.game.join("alice", "a", &["Builder"]);
+    harness.notify("join", json!([alice])).await;
+    harness.sent();
+    link::link(&harness.state, "a", UserId(42)).await.unwrap();
+
+    // bob's tag is cached once he's sent a message
+    harness.message(42, "bob", "!whois ali").await;
+    let content = harness.sent_to(CHANNEL).remove(0).content;
+    assert!(
+        content.starts_with("**alice** (`a`)\n**Discord:** <@42> (bob\\#0001)\n**Verified:** <t:")
+    );
+    assert!(content.ends_with("\n**Roles:** Builder\n**Status:** Online"));
+
+    harness.game.leave("a");
+    harness.notify("leave", json!([alice])).await;
+    harness.sent();
+
+    // players are remembered after they leave, and can be found through their Discord account
+    harness.message(5, "carol", "!whois <@!42>").await;
+    let content = harness.sent_to(CHANNEL).remove(0).content;
+    assert!(content.starts_with("**alice** (`a`)"));
+    assert!(content.contains("**Status:** Offline, last seen <t:"));
+
+    harness.message(5, "carol", "!whois <@5>").await;
+    assert_eq!(
+        harness.sent_to(CHANNEL)[0].content,
+        "**<@5> isn't linked to a player.**"
+    );
+
+    harness.game.join("carol", "c", &[]);
+    harness.game.take_output();
+    harness
+        .notify("cmd:discord", json!(["carol", "whois", "ALICE"]))
+        .await;
+    harness
+        .notify("cmd:discord", json!(["carol", "whois", "carol"]))
+        .await;
+    assert_eq!(
+        harness.game.take_output(),
+        [
+            GameOutput::Whisper {
+                user: "carol".into(),
+                line: "<b>alice</> is linked to <b>bob#0001</> on Discord.".into(),
+            },
+            GameOutput::Whisper {
+                user: "carol".into(),
+                line: "<b>carol</> isn't linked to a Discord account.".into(),
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn playtime_is_tracked_across_sessions() {
+    let mut harness = Harness::new(json!({}));
+    let alice = harness.game.join("alice", "a", &[]);
+    let bob = harness.game.join("bob", "b", &[]);
+
+    // alice played for an hour and a half an hour ago
+    let time = now();
+    harness.state.history.join(&alice, time - 5400);
+    harness.state.history.leave(&alice, time - 1800);
+    harness.game.leave("a");
+    harness.notify("join", json!([bob])).await;
+    harness.sent();
+
+    harness.message(42, "carol", "!lastseen ALICE").await;
+    harness.message(42, "carol", "!lastseen bob").await;
+    harness.message(42, "carol", "!top playtime 5").await;
+    harness.message(42, "carol", "!playtime").await;
+    let sent = harness.sent_to(CHANNEL);
+    assert_eq!(
+        sent.iter().map(|m| m.content.as_str()).collect::<Vec<_>>(),
+        [
+            format!("**alice** was last seen <t:{}:R>.", time - 1800).as_str(),
+            "**bob** is online now.",
+            "**Most playtime**\n1. alice: 1h 0m",
+            "**You are not verified!** Give the name of a player to look them up.",
+        ]
+    );
+
+    harness.game.take_output();
+    harness
+        .notify("cmd:discord", json!(["bob", "playtime", "ali"]))
+        .await;
+    harness
+        .notify("cmd:discord", json!(["bob", "playtime"]))
+        .await;
+    assert_eq!(
+        harness.game.take_output(),
+        [
+            GameOutput::Whisper {
+                user: "bob".into(),
+                line: "<b>alice</> has played for 1h 0m.".into(),
+            },
+            GameOutput::Whisper {
+                user: "bob".into(),
+                line: "<b>bob</> has played for 0m.".into(),
+            },
+        ]
+    );
+
+    // bob's session was never ended, as the server stopped, so it ends when it starts again
+    harness.notify("start", json!([{ "map": "Plate" }])).await;
+    let record = harness.state.history.get("b").unwrap();
+    assert_eq!(record.session_start, None);
+    assert!(harness
+        .game
+        .stored("history")
+        .is_some_and(|h| h["b"]["session_start"].is_null()));
+}
+
+#[tokio::test]
+async fn digests_count_activity_and_are_posted_when_due() {
+    let mut harness = Harness::new(json!({ "digest": "daily", "digest-time": "06:00" }));
+    digest::restore(&harness.state).await;
+
+    let alice = harness.game.join("alice", "a", &[]);
+    harness.notify("join", json!([alice])).await;
+    harness.notify("chat", json!(["alice", "hi"])).await;
+    harness.message(42, "bob", "hello").await;
+    harness.sent();
+
+    harness.message(42, "bob", "!digest").await;
+    let content = harness.sent_to(CHANNEL).remove(0).content;
+    assert!(content.starts_with("**Digest preview**\nActivity since <t:"));
+    assert!(content.contains("\n**Players:** 1 unique, 1 new\n**Peak:** 1 online at <t:"));
+    assert!(content.contains(
+        "\n**Chat:** 1 message to Discord, 1 to the game\n**Top chatters:** alice (1), bob (1)\n"
+    ));
+
+    // the counters are saved, so a restart doesn't lose them
+    digest::persist(&harness.state);
+    let mut saved = harness.game.stored("digest").unwrap();
+    assert_eq!(saved["to_discord"], json!(1));
+
+    // a digest was due while the plugin was stopped, so it's posted straight away
+    saved["started"] = json!(now() - 2 * 86400);
+    harness.state.omegga.store_set("digest", saved);
+    digest::restore(&harness.state).await;
+    digest::tick(&harness.state).await;
+    queue::send_waiting(&harness.state).await;
+
+    let sent = harness.sent_to(CHANNEL);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].embeds[0].title.as_deref(), Some("Daily digest"));
+    assert!(sent[0].embeds[0]
+        .description
+        .as_ref()
+        .is_some_and(|d| d.contains("**Players:** 1 unique, 1 new")));
+
+    // and counting starts again
+    harness.message(42, "bob", "!digest").await;
+    assert!(harness.sent_to(CHANNEL)[0]
+        .content
+        .contains("**Chat:** 0 messages to Discord, 0 to the game"));
+
+    // counters that can't be read start afresh, rather than posting a digest right away
+    harness
+        .state
+        .omegga
+        .store_set("digest", json!("not a digest"));
+    digest::restore(&harness.state).await;
+    digest::tick(&harness.state).await;
+    queue::send_waiting(&harness.state).await;
+    assert_eq!(harness.sent_to(CHANNEL), []);
+}
+
+#[tokio::test]
+async fn players_verify_from_discord() {
+    let mut harness = Harness::new(json!({
+        "verification": true,
+        "verified-role": "5",
+    }));
+    harness.game.join("alice", "a", &[]);
+
+    harness
+        .notify("cmd:discord", json!(["alice", "verify"]))
+        .await;
+    let code = harness.state.verifications.pending("a").unwrap().code;
+    assert!(matches!(
+        harness.game.take_output().as_slice(),
+        [GameOutput::Whisper { user, line }] if user == "alice" && line.contains(&code)
+    ));
+
+    // a wrong code doesn't verify anyone
+    harness.message(42, "bob", "!verify nope").await;
+    assert!(harness.sent_to(CHANNEL)[0]
+        .content
+        .starts_with("**There is no pending verification with that code!**"));
+
+    harness
+        .message(42, "bob", &format!("!verify {}", code))
+        .await;
+    let requests = harness.discord.take_requests();
+    assert!(requests.contains(&DiscordRequest::AddRole {
+        user: UserId(42),
+        role: RoleId(5),
+    }));
+    assert!(requests.iter().any(|r| matches!(
+        r,
+        DiscordRequest::Message { message, .. } if message.content.starts_with("**Success!**")
+    )));
+
+    assert_eq!(harness.game.stored("g2d_a"), Some(json!("42")));
+    assert_eq!(harness.game.stored("d2g_42"), Some(json!("a")));
+    assert!(harness.state.verifications.pending("a").is_none());
+    assert!(harness.game.take_output().iter().any(
+        |o| matches!(o, GameOutput::Whisper { user, line } if user == "alice" && line.contains("Success!"))
+    ));
+
+    // verifying again is refused until they relink
+    harness
+        .notify("cmd:discord", json!(["alice", "verify"]))
+        .await;
+    assert!(matches!(
+        harness.game.take_output().as_slice(),
+        [GameOutput::Whisper { line, .. }] if line.contains("already verified")
+    ));
+}
+
+#[tokio::test]
+async fn players_can_finish_verifying_while_offline() {
+    let mut harness = Harness::new(json!({ "verification": true }));
+    let alice = harness.game.join("alice", "a", &[]);
+    harness.notify("join", json!([alice])).await;
+    harness
+        .notify("cmd:discord", json!(["alice", "verify"]))
+        .await;
+    let code = harness.state.verifications.pending("a").unwrap().code;
+
+    // the plugin restarts after alice left, so her code is restored without her online
+    harness.game.leave("a");
+    let value = harness.state.verifications.to_value();
+    harness.state.verifications.remove("a");
+    harness.state.verifications.restore(value);
+    harness.sent();
+    harness.game.take_output();
+
+    harness
+        .message(42, "bob", &format!("!verify {}", code))
+        .await;
+    assert_eq!(
+        harness.sent_to(CHANNEL)[0].content,
+        "**Success!** You've been verified as **alice** in Brickadia."
+    );
+    assert_eq!(harness.game.stored("g2d_a"), Some(json!("42")));
+    assert_eq!(harness.game.stored("d2g_42"), Some(json!("a")));
+
+    // alice isn't around to be told
+    assert!(!harness
+        .game
+        .take_output()
+        .iter()
+        .any(|o| matches!(o, GameOutput::Whisper { .. })));
+}
+
+#[tokio::test]
+async fn synced_roles_are_quoted_in_console_commands() {
+    let harness = Harness::new(json!({ "role-sync": ["5:Build\"er"] }));
+    harness.game.join("al\"ice", "a", &[]);
+    link::link(&harness.state, "a", UserId(42)).await.unwrap();
+
+    roles::sync_user(&harness.state, UserId(42), vec![RoleId(5)])
+        .await
+        .unwrap();
+    assert!(harness.game.take_output().contains(&GameOutput::Command(
+        "Chat.Command /GrantRole \"Build'er\" \"al'ice\"".into()
+    )));
+}
+
+#[tokio::test]
+async fn config_reloads_are_validated() {
+    let harness = Harness::new(json!({}));
+    let player = harness.game.join("Zeb_", "zeb", &[]);
+
+    config::reload(
+        &harness.state,
+        config(json!({ "join-message-format": "$nobody joined" })),
+    )
+    .await;
+    assert!(matches!(
+        harness.game.take_output().as_slice(),
+        [GameOutput::Error(e)] if e.contains("join-message-format has an unknown formatter $nobody")
+    ));
+
+    config::reload(
+        &harness.state,
+        config(json!({ "join-message-format": "$user is here" })),
+    )
+    .await;
+    harness.notify("join", json!([player])).await;
+    assert_eq!(harness.sent_to(CHANNEL)[0].content, "Zeb\\_ is here");
+}
+
+#[tokio::test]
+async fn messages_are_routed_between_channels() {
+    const LOG: ChannelId = ChannelId(200);
+    const STAFF: ChannelId = ChannelId(300);
+
+    let mut harness = Harness::new(json!({
+        "channels": ["200:to-discord:join-leave", "300:both:chat:[Staff]:f80"],
+        "channel-formats": ["200:join-message-format:+ $user"],
+    }));
+    let player = harness.game.join("Zeb", "zeb", &[]);
+
+    harness.notify("join", json!([player])).await;
+    harness.notify("chat", json!(["Zeb", "hi"])).await;
+
+    let sent = harness
+        .sent()
+        .into_iter()
+        .map(|(c, m)| (c, m.content))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sent,
+        [
+            (CHANNEL, "**Zeb joined the game.**".into()),
+            (LOG, "+ Zeb".into()),
+            (CHANNEL, "**Zeb**: hi".into()),
+            (STAFF, "**Zeb**: hi".into()),
+        ]
+    );
+
+    harness.message_in(STAFF, 42, "bob", "hello").await;
+    assert_eq!(
+        harness.game.take_output()[0],
+        GameOutput::Broadcast(
+            "<color=\"f80\">[Staff]</> <color=\"aaaaaa\"><b>bob</b></>: hello".into()
+        )
+    );
+
+    // the log channel only goes one way
+    harness.message_in(LOG, 42, "bob", "not relayed").await;
+    assert_eq!(harness.game.take_output(), []);
+
+    // ...so it doesn't take commands either
+    harness.message_in(LOG, 42, "bob", "!players").await;
+    assert_eq!(harness.game.take_output(), []);
+    assert!(harness.sent().is_empty());
+}
+
+#[tokio::test]
+async fn wiping_only_removes_links() {
+    let harness = Harness::new(json!({}));
+    harness.game.join_host("Host", "h");
+    link::link(&harness.state, "a", UserId(42)).await.unwrap();
+    harness.state.omegga.store_set("history", json!([]));
+    harness.state.omegga.store_set("status_message", json!("5"));
+
+    harness.notify("cmd:discord", json!(["Host", "wipe"])).await;
+
+    for key in ["g2d_a", "d2g_42", "linked_a"] {
+        assert_eq!(harness.game.stored(key), None, "{}", key);
+    }
+    assert_eq!(harness.game.stored("history"), Some(json!([])));
+    assert_eq!(harness.game.stored("status_message"), Some(json!("5")));
+}
+
+#[tokio::test]
+async fn admin_events_are_sent_as_embeds() {
+    const ADMIN: ChannelId = ChannelId(300);
+
+    let harness = Harness::new(json!({
+        "channels": ["300:to-discord:admin"],
+        "admin-events": ["ban", "wipe", "host-command"],
+    }));
+    let host = harness.game.join_host("Host", "h");
+    let player = harness.game.join("Zeb", "zeb", &[]);
+
+    harness.notify("join", json!([player])).await;
+    harness.notify("cmd:discord", json!(["Host", "wipe"])).await;
+    harness.notify("cmd:discord", json!(["Host", "help"])).await;
+    harness.notify("cmd:discord", json!(["Zeb", "help"])).await;
+    harness.notify("start", json!([{ "map": "Plate" }])).await;
+
+    let sent = harness.sent_to(ADMIN);
+    let titles = sent
+        .iter()
+        .map(|m| m.embeds[0].title.clone().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(titles, ["Verification store wiped", "Host command"]);
+    assert_eq!(sent[0].embeds[0].fields[0].value, "Host");
+    assert!(sent[0].embeds[0].timestamp.is_some());
+    assert_eq!(
+        sent[1].embeds[0].description.as_deref(),
+        Some("`/discord help`")
+    );
+
+    // bans already in the list aren't logged
+    harness.game.ban("old", "h", "");
+    let mut known = None;
+    admin::check_bans(&harness.state, &mut known).await.unwrap();
+    harness.game.ban("zeb", &host.id, "griefing");
+    admin::check_bans(&harness.state, &mut known).await.unwrap();
+    queue::send_waiting(&harness.state).await;
+
+    let sent = harness.sent_to(ADMIN);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(
+        sent[0].embeds[0]
+            .fields
+            .iter()
+            .map(|f| f.value.as_str())
+            .collect::<Vec<_>>(),
+        ["Host", "Zeb"]
+    );
+    assert_eq!(
+        sent[0].embeds[0].description.as_deref(),
+        Some("Reason: griefing\nExpires: 2021-01-02T00:00:00.000Z")
+    );
+}
+
+#[tokio::test]
+async fn moderators_run_commands_by_role() {
+    const ADMIN: ChannelId = ChannelId(300);
+
+    let mut harness = Harness::new(json!({
+        "channels": ["300:to-discord:admin"],
+        "moderation-roles": ["kick:7", "*:8"],
+    }));
+    harness.game.join("Zeb", "zeb", &[]);
+    harness.game.join("Zebra", "zebra", &[]);
+    harness.game.join("alice", "a", &[]);
+
+    harness.message(42, "bob", "!kick alice").await;
+    harness
+        .message_with_roles(42, "bob", &[7], "!ban alice 1d")
+        .await;
+    assert_eq!(
+        harness
+            .sent_to(CHANNEL)
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect::<Vec<_>>(),
+        [
+            "**You don't have permission to run that command!**",
+            "**You don't have permission to run that command!**"
+        ]
+    );
+
+    harness
+        .message_with_roles(42, "bob", &[7], "!kick ze")
+        .await;
+    assert_eq!(
+        harness.sent_to(CHANNEL)[0].content,
+        "**More than one player matches ze:** Zeb, Zebra"
+    );
+
+    harness
+        .message_with_roles(42, "bob", &[7], "!kick LIC being \"rude\"")
+        .await;
+    assert_eq!(harness.sent_to(CHANNEL)[0].content, "**Kicked alice.**");
+    harness
+        .message_with_roles(42, "bob", &[8], "!ban zebra 2h")
+        .await;
+    harness
+        .message_with_roles(42, "bob", &[8], "!clearbricks zeb")
+        .await;
+
+    let output = harness.game.take_output();
+    for expected in [
+        GameOutput::Command("Chat.Command /Kick \"alice\" \"being 'rude'\"".into()),
+        GameOutput::Command("Chat.Command /Ban \"Zebra\" 120 \"\"".into()),
+        GameOutput::ClearBricks("zeb".into()),
+    ] {
+        assert!(output.contains(&expected), "{:?}", output);
+    }
+    assert!(
+        !output.iter().any(|o| matches!(o, GameOutput::Broadcast(_))),
+        "{:?}",
+        output
+    );
+
+    queue::send_waiting(&harness.state).await;
+    let logged = harness.sent_to(ADMIN);
+    assert_eq!(logged.len(), 3);
+    assert_eq!(
+        logged[1].embeds[0].description.as_deref(),
+        Some("`ban` Banned for 2 hours. No reason given.")
+    );
+    assert_eq!(logged[1].embeds[0].fields[0].value, "<@42>");
+}
+
+#[tokio::test]
+async fn owners_run_console_commands() {
+    let mut harness = Harness::new(json!({ "console-owners": ["42"] }));
+
+    harness.message(43, "eve", "!console Server.Status").await;
+    harness.message(42, "bob", "!console EXIT").await;
+    harness
+        .message(42, "bob", "!console Server.Status\nExit")
+        .await;
+    harness
+        .message(42, "bob", "!console Server.Status\rExit")
+        .await;
+    assert_eq!(
+        harness
+            .sent_to(CHANNEL)
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect::<Vec<_>>(),
+        [
+            "**Only the server's owners can run console commands!**",
+            "**That command is blocked by `console-denylist`.**",
+            "**Please run one command at a time.**",
+            "**Please run one command at a time.**",
+        ]
+    );
+    assert!(!harness
+        .game
+        .take_output()
+        .iter()
+        .any(|o| matches!(o, GameOutput::Command(_))));
+
+    // output arrives while the command waits for it
+    let state = harness.state.clone();
+    tokio::join!(
+        harness.message(42, "bob", "!console Server.Status"),
+        async move {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            game::dispatcher()
+                .notify(&state, "line", Some(json!(["[2021][1]Players: 0"])))
+                .await;
+        }
+    );
+
+    let output = harness.game.take_output();
+    assert!(output.contains(&GameOutput::Command("Server.Status".into())));
+    assert!(
+        !output.iter().any(|o| matches!(o, GameOutput::Broadcast(_))),
+        "{:?}",
+        output
+    );
+    assert_eq!(
+        harness.sent_to(CHANNEL)[0].content,
+        "**Ran the command.**\n```\nPlayers: 0\n```"
+    );
+}
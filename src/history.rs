@@ -0,0 +1,287 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use omegga::resources::Player;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Result,
+    moderation::{self, Resolved},
+    verify::now,
+    State,
+};
+
+/// The store key the players that have been seen are persisted under.
+const STORE_KEY: &str = "history";
+
+/// How often online players are marked as seen, so sessions cut short by the server stopping
+/// are closed at about the right time.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What's remembered about a player who's been on the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// Their name when they were last seen.
+    pub name: String,
+
+    /// The UNIX times in seconds they were first and last seen at.
+    pub first_seen: u64,
+    pub last_seen: u64,
+
+    /// The seconds played in every session that's ended.
+    #[serde(default)]
+    pub playtime: u64,
+
+    /// The UNIX time in seconds the current session started at, while they're online.
+    #[serde(default)]
+    pub session_start: Option<u64>,
+}
+
+impl Record {
+    /// The seconds played in total as of `time`, including the current session.
+    pub fn playtime(&self, time: u64) -> u64 {
+        let session = self
+            .session_start
+            .map_or(0, |start| time.saturating_sub(start));
+        self.playtime + session
+    }
+
+    /// End the current session at `time`, if there is one.
+    fn close_session(&mut self, time: u64) {
+        if let Some(start) = self.session_start.take() {
+            self.playtime += time.saturating_sub(start);
+            self.last_seen = self.last_seen.max(time);
+        }
+    }
+}
+
+/// Every player that's been seen on the server, by UUID.
+#[derive(Debug, Default)]
+pub struct History {
+    records: Mutex<HashMap<String, Record>>,
+}
+
+impl History {
+    /// Record that a player joined at `time`, starting a session.
+    pub fn join(&self, player: &Player, time: u64) {
+        let mut records = self.records.lock().unwrap();
+        let record = records.entry(player.id.clone()).or_insert_with(|| Record {
+            name: player.name.clone(),
+            first_seen: time,
+            last_seen: time,
+            playtime: 0,
+            session_start: None,
+        });
+        record.name = player.name.clone();
+        record.last_seen = time;
+        record.session_start.get_or_insert(time);
+    }
+
+    /// Record that a player left at `time`, ending their session.
+    pub fn leave(&self, player: &Player, time: u64) {
+        if let Some(record) = self.records.lock().unwrap().get_mut(&player.id) {
+            record.name = player.name.clone();
+            record.close_session(time);
+            record.last_seen = time;
+        }
+    }
+
+    /// Mark everyone with a session as seen at `time`, returning whether anyone was.
+    fn checkpoint(&self, time: u64) -> bool {
+        let mut any = false;
+        for record in self.records.lock().unwrap().values_mut() {
+            if record.session_start.is_some() {
+                record.last_seen = time;
+                any = true;
+            }
+        }
+        any
+    }
+
+    /// End the sessions of everyone but the players `online`, as of when they were last seen.
+    ///
+    /// This is for sessions that were never ended, because the server or the plugin stopped
+    /// while they were online.
+    pub fn close_sessions(&self, online: &[String]) {
+        for (id, record) in self.records.lock().unwrap().iter_mut() {
+            if !online.contains(id) {
+                let last_seen = record.last_seen;
+                record.close_session(last_seen);
+            }
+        }
+    }
+
+    /// What's remembered about a player, by their UUID.
+    pub fn get(&self, id: &str) -> Option<Record> {
+        self.records.lock().unwrap().get(id).cloned()
+    }
+
+    /// Every player that's been seen, as their UUID and last known name.
+    pub fn names(&self) -> Vec<(String, String)> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, r)| (id.clone(), r.name.clone()))
+            .collect()
+    }
+
+    /// The players who've played the most as of `time`, with their playtime in seconds.
+    pub fn top_playtime(&self, count: usize, time: u64) -> Vec<(String, u64)> {
+        let mut players = self
+            .records
+            .lock()
+            .unwrap()
+            .values()
+            .map(|r| (r.name.clone(), r.playtime(time)))
+            .filter(|(_, playtime)| *playtime > 0)
+            .collect::<Vec<_>>();
+
+        players.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        players.truncate(count);
+        players
+    }
+}
+
+/// A player that was looked up, by their UUID and name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Known {
+    pub id: String,
+    pub name: String,
+}
+
+/// Find a player who's online or has been seen before, by their UUID, or by their name or
+/// part of it. Online players are preferred, so a name that's taken now isn't ambiguous.
+pub async fn find(state: &State, query: &str) -> Result<Resolved<Known>> {
+    let online = state
+        .omegga
+        .get_players()
+        .await?
+        .into_iter()
+        .map(|p| Known {
+            id: p.id,
+            name: p.name,
+        })
+        .collect::<Vec<_>>();
+
+    match moderation::resolve_by(online.clone(), query, |k| (&k.id, &k.name)) {
+        Resolved::NotFound => (),
+        resolved => return Ok(resolved),
+    }
+
+    let seen = state
+        .history
+        .names()
+        .into_iter()
+        .filter(|(id, _)| !online.iter().any(|k| k.id == *id))
+        .map(|(id, name)| Known { id, name })
+        .collect();
+    Ok(moderation::resolve_by(seen, query, |k| (&k.id, &k.name)))
+}
+
+/// Load the players that have been seen from the store, ending the sessions of anyone who
+/// left while the plugin was stopped and starting them for anyone online.
+pub async fn restore(state: &State) {
+    match state.omegga.store_get(STORE_KEY).await {
+        Ok(Some(value)) => {
+            *state.history.records.lock().unwrap() =
+                serde_json::from_value(value).unwrap_or_default();
+        }
+        Ok(None) => (),
+        Err(e) => state
+            .omegga
+            .error(format!("Error restoring player history: {}", e)),
+    }
+
+    match state.omegga.get_players().await {
+        Ok(players) => {
+            let online = players.iter().map(|p| p.id.clone()).collect::<Vec<_>>();
+            state.history.close_sessions(&online);
+            for player in players.iter() {
+                state.history.join(player, now());
+            }
+            persist(state);
+        }
+        Err(e) => state
+            .omegga
+            .error(format!("Error getting players for history: {}", e)),
+    }
+}
+
+/// Save the players that have been seen to the store.
+pub fn persist(state: &State) {
+    let records = state.history.records.lock().unwrap().clone();
+    if let Ok(value) = serde_json::to_value(records) {
+        state.omegga.store_set(STORE_KEY, value);
+    }
+}
+
+/// Mark everyone online as seen just now, and save it.
+pub fn checkpoint(state: &State) {
+    if state.history.checkpoint(now()) {
+        persist(state);
+    }
+}
+
+/// Keep marking everyone online as seen in the background.
+pub fn spawn(state: State) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(CHECKPOINT_INTERVAL).await;
+            checkpoint(&state);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use omegga::resources::Player;
+
+    use super::History;
+
+    fn player(name: &str) -> Player {
+        Player {
+            name: name.into(),
+            id: name.to_lowercase(),
+            controller: String::new(),
+            state: String::new(),
+            host: None,
+        }
+    }
+
+    #[test]
+    fn sessions_add_up_to_playtime() {
+        let history = History::default();
+        let mut alice = player("alice");
+
+        history.join(&alice, 100);
+        history.leave(&alice, 160);
+        alice.name = "Alice2".into();
+        history.join(&alice, 1000);
+
+        let record = history.get("alice").unwrap();
+        assert_eq!(record.name, "Alice2");
+        assert_eq!((record.first_seen, record.last_seen), (100, 1000));
+        assert_eq!(record.playtime(1030), 90);
+        assert_eq!(history.names(), [("alice".into(), "Alice2".into())]);
+    }
+
+    #[test]
+    fn unfinished_sessions_end_when_last_seen() {
+        let history = History::default();
+        history.join(&player("a"), 100);
+        history.join(&player("b"), 100);
+        history.checkpoint(150);
+
+        // the server restarted, and only b is back
+        history.close_sessions(&["b".into()]);
+
+        let a = history.get("a").unwrap();
+        assert_eq!((a.playtime, a.session_start), (50, None));
+        assert_eq!(history.get("b").unwrap().session_start, Some(100));
+        assert_eq!(
+            history.top_playtime(5, 200),
+            [("b".into(), 100), ("a".into(), 50)]
+        );
+        assert_eq!(history.top_playtime(1, 200).len(), 1);
+    }
+}
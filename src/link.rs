@@ -0,0 +1,112 @@
+use serde_json::Value;
+use twilight_model::id::UserId;
+
+use crate::{error::Result, verify::now, State};
+
+// Links between players and Discord users are stored both ways, as `g2d_<player uuid>`
+// holding the Discord user ID and `d2g_<discord user id>` holding the player UUID. The UNIX
+// time a player was linked at is stored as `linked_<player uuid>`.
+
+fn g2d(player: &str) -> String {
+    format!("g2d_{}", player)
+}
+
+fn d2g(user: UserId) -> String {
+    format!("d2g_{}", user)
+}
+
+fn linked(player: &str) -> String {
+    format!("linked_{}", player)
+}
+
+/// Get the Discord user linked to a player, if any.
+pub async fn linked_user(state: &State, player: &str) -> Result<Option<UserId>> {
+    Ok(state
+        .omegga
+        .store_get(g2d(player))
+        .await?
+        .and_then(|v| v.as_str().and_then(|id| id.parse().ok()))
+        .map(UserId))
+}
+
+/// Get the UUID of the player linked to a Discord user, if any.
+pub async fn linked_player(state: &State, user: UserId) -> Result<Option<String>> {
+    Ok(state
+        .omegga
+        .store_get(d2g(user))
+        .await?
+        .and_then(|v| v.as_str().map(str::to_owned)))
+}
+
+/// Get the UNIX time in seconds a player was linked at, if they're linked and it was recorded.
+pub async fn linked_at(state: &State, player: &str) -> Result<Option<u64>> {
+    Ok(state
+        .omegga
+        .store_get(linked(player))
+        .await?
+        .and_then(|v| v.as_u64()))
+}
+
+/// Link a player to a Discord user.
+///
+/// Any previous links either of them had are removed first, so no stale mappings are left
+/// behind. Returns the Discord user the player was previously linked to, if it was someone else.
+pub async fn link(state: &State, player: &str, user: UserId) -> Result<Option<UserId>> {
+    let previous_user = unlink_player(state, player).await?;
+    unlink_user(state, user).await?;
+
+    state
+        .omegga
+        .store_set(g2d(player), Value::String(user.to_string()));
+    state
+        .omegga
+        .store_set(d2g(user), Value::String(player.to_owned()));
+    state.omegga.store_set(linked(player), Value::from(now()));
+
+    Ok(previous_user.filter(|u| *u != user))
+}
+
+/// Remove a player's link, returning the Discord user they were linked to.
+pub async fn unlink_player(state: &State, player: &str) -> Result<Option<UserId>> {
+    let user = linked_user(state, player).await?;
+    state.omegga.store_delete(g2d(player)).await;
+    state.omegga.store_delete(linked(player)).await;
+
+    if let Some(user) = user {
+        // only remove the reverse mapping if it still points back at this player
+        if linked_player(state, user).await?.as_deref() == Some(player) {
+            state.omegga.store_delete(d2g(user)).await;
+        }
+    }
+
+    Ok(user)
+}
+
+/// Remove a Discord user's link, returning the UUID of the player they were linked to.
+pub async fn unlink_user(state: &State, user: UserId) -> Result<Option<String>> {
+    let player = linked_player(state, user).await?;
+    state.omegga.store_delete(d2g(user)).await;
+
+    if let Some(player) = player.as_deref() {
+        if linked_user(state, player).await? == Some(user) {
+            state.omegga.store_delete(g2d(player)).await;
+            state.omegga.store_delete(linked(player)).await;
+        }
+    }
+
+    Ok(player)
+}
+
+/// Remove every link, leaving the rest of the store alone.
+pub async fn unlink_all(state: &State) -> Result<()> {
+    for key in state.omegga.store_keys().await? {
+        if ["g2d_", "d2g_", "linked_"]
+            .iter()
+            .any(|prefix| key.starts_with(prefix))
+        {
+            state.omegga.store_delete(key).await;
+        }
+    }
+
+    Ok(())
+}
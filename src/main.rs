@@ -1,107 +1,157 @@
+mod admin;
+mod backend;
+mod channels;
+mod commands;
+mod config;
+mod console;
+mod digest;
 mod discord;
+mod error;
+mod events;
+#[cfg(test)]
+mod fake;
 mod format;
-
-use std::{fs::File, sync::Arc};
-
-use anyhow::Result;
-
-use dashmap::{mapref::entry::Entry, DashMap};
-use format::format_content;
-use omegga::{resources::Player, rpc, Omegga};
-use rand::{distributions, Rng};
-use serde::{Deserialize, Serialize};
+mod game;
+#[cfg(test)]
+mod harness;
+mod history;
+mod link;
+mod markdown;
+mod moderation;
+mod playtime;
+mod queue;
+mod rename;
+mod roles;
+mod status;
+mod verify;
+mod webhook;
+mod whois;
+
+use std::sync::Arc;
+
+use omegga::{rpc, Omegga};
 use serde_json::json;
 use twilight_cache_inmemory::{InMemoryCache, ResourceType};
 use twilight_gateway::{Intents, Shard};
 use twilight_http::Client as HttpClient;
 use twilight_model::id::ChannelId;
 
-use crate::format::{compose_vec, role_text, Formatter};
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Config {
-    pub token: String,
-
-    #[serde(rename = "channel-id")]
-    pub channel_id: String,
+use crate::{
+    admin::{AdminEvent, AdminKind},
+    backend::{DiscordBackend, Game},
+    commands::CommandMode,
+    config::{Config, SharedConfig, CONFIG_PATH},
+    console::Capture,
+    digest::Digest,
+    error::{Error, Result},
+    history::History,
+    queue::{DropPolicy, Queue},
+    rename::ChannelName,
+    status::Status,
+    verify::Verifications,
+    webhook::Webhook,
+};
 
-    #[serde(rename = "discord-link")]
-    pub discord_link: String,
+#[derive(Clone)]
+pub struct State {
+    /// The plugin config, which may be reloaded.
+    pub config: SharedConfig,
 
-    #[serde(rename = "channel-name-online-format")]
-    pub channel_name_online_format: String,
+    /// Requests to Discord.
+    pub discord: Arc<dyn DiscordBackend>,
 
-    #[serde(rename = "discord-prefix")]
-    pub discord_prefix: String,
+    /// The game server, through omegga.
+    pub omegga: Game,
 
-    #[serde(rename = "game-message-format")]
-    pub game_message_format: String,
+    /// The Discord cache.
+    pub cache: InMemoryCache,
 
-    #[serde(rename = "discord-message-format")]
-    pub discord_message_format: String,
+    /// The ID messages are being sent to in Discord.
+    pub channel_id: ChannelId,
 
-    #[serde(rename = "join-message-format")]
-    pub join_message_format: String,
+    /// Pending verification codes to verify on Discord.
+    pub verifications: Arc<Verifications>,
 
-    #[serde(rename = "leave-message-format")]
-    pub leave_message_format: String,
+    /// The webhook game messages are executed through, if any.
+    pub webhook: Option<Webhook>,
 
-    #[serde(rename = "server-start-format")]
-    pub server_start_format: String,
+    /// The live status message.
+    pub status: Arc<Status>,
 
-    #[serde(rename = "game-roles")]
-    pub game_roles: Vec<String>,
+    /// The channel renamed to show the player count.
+    pub channel_name: Arc<ChannelName>,
 
-    #[serde(rename = "discord-roles")]
-    pub discord_roles: Vec<String>,
+    /// Messages waiting to be sent to Discord.
+    pub queue: Arc<Queue>,
 
-    pub verification: bool,
+    /// Console output captured for commands run from Discord.
+    pub console: Arc<Capture>,
 
-    #[serde(rename = "verified-role")]
-    pub verified_role: String,
+    /// The players that have been seen on the server.
+    pub history: Arc<History>,
 
-    #[serde(rename = "verified-nickname")]
-    pub verified_nickname: bool,
+    /// The activity counted for the next digest.
+    pub digest: Arc<Digest>,
 }
 
-#[derive(Clone)]
-pub struct State {
-    /// The plugin config.
-    pub config: Arc<Config>,
-
-    /// The Discord HTTP client.
-    pub http: HttpClient,
-
-    /// The Omegga interface.
-    pub omegga: Arc<Omegga>,
-
-    /// The Discord cache.
-    pub cache: InMemoryCache,
-
-    /// The ID messages are being sent to in Discord.
-    pub channel_id: ChannelId,
-
-    /// A buffer of player UUID to verification code to verify on Discord.
-    pub verify_buffer: Arc<DashMap<String, String>>,
-}
+impl State {
+    pub fn new(
+        config: Config,
+        discord: Arc<dyn DiscordBackend>,
+        omegga: Game,
+        channel_id: ChannelId,
+        webhook: Option<Webhook>,
+    ) -> Self {
+        // start a cache for discord resources
+        let cache = InMemoryCache::builder()
+            .resource_types(
+                ResourceType::GUILD
+                    | ResourceType::CHANNEL
+                    | ResourceType::MEMBER
+                    | ResourceType::ROLE
+                    | ResourceType::USER,
+            )
+            .build();
+
+        Self {
+            discord,
+            omegga,
+            cache,
+            channel_id,
+            verifications: Arc::new(Verifications::new(
+                config.verify_code_ttl,
+                config.verify_max_attempts,
+                config.verify_cooldown,
+            )),
+            webhook,
+            status: Arc::new(Status::new(
+                config.status_channel_id.parse().ok().map(ChannelId),
+            )),
+            channel_name: Arc::new(ChannelName::new(
+                &config.channel_name_online_format,
+                config.stat_channel_id.parse().ok().map(ChannelId),
+                channel_id,
+            )),
+            queue: Arc::new(Queue::new(
+                config.queue_size,
+                DropPolicy::parse(&config.queue_drop_policy),
+            )),
+            console: Arc::default(),
+            history: Arc::default(),
+            digest: Arc::default(),
+            config: SharedConfig::new(config),
+        }
+    }
 
-async fn user_formatters(state: &State, user: String) -> Result<Vec<Formatter>> {
-    let roles = state
-        .omegga
-        .get_player_roles(&user)
-        .await?
-        .unwrap_or_else(Vec::new);
+    /// The current config.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.get()
+    }
 
-    Ok(vec![
-        Formatter {
-            key: "role",
-            value: role_text(&roles, &state.config.game_roles),
-        },
-        Formatter {
-            key: "user",
-            value: user,
-        },
-    ])
+    /// How commands can be run from Discord.
+    pub fn command_mode(&self) -> CommandMode {
+        CommandMode::parse(&self.config().command_mode)
+    }
 }
 
 #[tokio::main]
@@ -110,36 +160,59 @@ async fn main() -> Result<()> {
     let omegga = Arc::new(Omegga::new());
     let mut rx = omegga.spawn();
 
-    // read the config
-    let config = Arc::new(serde_json::from_reader::<_, Config>(File::open(
-        "config.json",
-    )?)?);
+    // read the config, letting the user know what's wrong with it
+    let config = match Config::read(CONFIG_PATH) {
+        Ok(config) => config,
+        Err(e) => {
+            omegga.error(format!("Couldn't load the config: {}", e));
+            return Err(e);
+        }
+    };
 
-    // connect to discord's gateway
-    let (shard, events) = Shard::builder(
-        &config.token,
-        Intents::GUILDS | Intents::GUILD_MESSAGES | Intents::DIRECT_MESSAGES,
-    )
-    .build();
+    // connect to discord's gateway, watching members only if their roles are synced
+    let mut intents = Intents::GUILDS | Intents::GUILD_MESSAGES | Intents::DIRECT_MESSAGES;
+    if !config.role_sync.is_empty() {
+        intents |= Intents::GUILD_MEMBERS;
+    }
+    let (shard, events) = Shard::builder(&config.token, intents).build();
+    let shard = Arc::new(shard);
 
     // instantiate a discord http client
     let http = HttpClient::new(config.token.clone());
-    let channel_id = ChannelId(config.channel_id.parse().unwrap());
-
-    // start a cache for discord resources
-    let cache = InMemoryCache::builder()
-        .resource_types(ResourceType::GUILD | ResourceType::MEMBER | ResourceType::ROLE)
-        .build();
+    let channel_id = ChannelId(
+        config
+            .channel_id
+            .parse()
+            .map_err(|_| Error::Config("channel-id must be a channel ID".into()))?,
+    );
+
+    // find the webhook to send game messages through
+    let webhook = if !config.webhook_url.is_empty() {
+        let webhook = Webhook::parse(&config.webhook_url);
+        if webhook.is_none() {
+            omegga.error("Invalid webhook URL, falling back to bot messages.");
+        }
+        webhook
+    } else if config.webhook_create {
+        match Webhook::find_or_create(&http, channel_id).await {
+            Ok(w) => Some(w),
+            Err(e) => {
+                omegga.error(format!("Error creating webhook: {}", e));
+                None
+            }
+        }
+    } else {
+        None
+    };
 
     // handle discord events in a separate task
-    let state = State {
+    let state = State::new(
         config,
-        http,
-        omegga,
-        cache,
+        Arc::new(http),
+        Game::new(omegga.clone()),
         channel_id,
-        verify_buffer: Arc::new(DashMap::new()),
-    };
+        webhook,
+    );
 
     let task_state = state.clone();
     tokio::spawn(async move {
@@ -150,240 +223,69 @@ async fn main() -> Result<()> {
         }
     });
 
+    let dispatcher = game::dispatcher();
+    let mut started = false;
     while let Some(message) = rx.recv().await {
         match message {
-            rpc::Message::Request { method, id, .. } if method == "init" || method == "stop" => {
+            rpc::Message::Request {
+                method, id, params, ..
+            } if method == "init" || method == "stop" => {
                 match method.as_str() {
-                    "init" => {
-                        shard.start().await?;
-                        state.omegga.write_response(
+                    // omegga sends the config again if it's changed, which is swapped in
+                    "init" if started => {
+                        config::reload_from_params(&state, params).await;
+                        omegga.write_response(
                             id,
                             Some(json!({"registeredCommands": ["discord"]})),
                             None,
                         );
                     }
-                    "stop" => state.omegga.write_response(id, None, None),
-                    _ => (),
-                }
-            }
-            rpc::Message::Notification { method, params, .. } if method == "start" => {
-                #[derive(Serialize, Deserialize, Default)]
-                struct StartObject {
-                    map: String,
-                }
-
-                let params = serde_json::from_value::<Vec<StartObject>>(match params {
-                    Some(p) => p,
-                    None => continue,
-                })
-                .unwrap()
-                .into_iter()
-                .next()
-                .unwrap_or_default();
-
-                state
-                    .http
-                    .create_message(channel_id)
-                    .content(&format_content(
-                        state.config.server_start_format.clone(),
-                        &[Formatter {
-                            key: "map",
-                            value: params.map,
-                        }],
-                    ))?
-                    .exec()
-                    .await?;
-            }
-            rpc::Message::Notification { method, params, .. } if method == "chat" => {
-                let mut params = serde_json::from_value::<Vec<String>>(match params {
-                    Some(p) => p,
-                    None => continue,
-                })
-                .unwrap()
-                .into_iter();
-
-                let user = params.next().unwrap();
-                let message = params.next().unwrap_or_else(String::new);
-
-                let formatters = compose_vec(vec![
-                    user_formatters(&state, user.clone()).await?,
-                    vec![Formatter {
-                        key: "message",
-                        value: message,
-                    }],
-                ]);
-
-                match state
-                    .http
-                    .create_message(channel_id)
-                    .content(&format_content(
-                        state.config.discord_message_format.clone(),
-                        &formatters,
-                    ))?
-                    .exec()
-                    .await
-                {
-                    Ok(_) => (),
-                    Err(e) => state
-                        .omegga
-                        .log(format!("Error sending chat message: {}", e)),
-                }
-            }
-            rpc::Message::Notification { method, params, .. }
-                if method == "join" || method == "leave" =>
-            {
-                let mut params = serde_json::from_value::<Vec<Player>>(match params {
-                    Some(p) => p,
-                    None => continue,
-                })
-                .unwrap()
-                .into_iter();
-
-                let player = params.next().unwrap();
-
-                if method == "leave" {
-                    // remove from the verify buffer if the player leaves
-                    state.verify_buffer.remove(&player.id);
-                }
-
-                let formatters = user_formatters(&state, player.name.clone()).await?;
-
-                match match state
-                    .http
-                    .create_message(channel_id)
-                    .content(&format_content(
-                        match method.as_str() {
-                            "join" => state.config.join_message_format.clone(),
-                            "leave" => state.config.leave_message_format.clone(),
-                            _ => unreachable!(),
-                        },
-                        &formatters,
-                    )) {
-                    Ok(r) => r,
-                    Err(e) => {
-                        state
-                            .omegga
-                            .log(format!("Error sending {} message: {}", method, e));
-                        continue;
-                    }
-                }
-                .exec()
-                .await
-                {
-                    Ok(_) => (),
-                    Err(e) => {
-                        state
-                            .omegga
-                            .log(format!("Error sending {} message: {}", method, e));
-                        continue;
-                    }
-                }
-
-                if !state.config.channel_name_online_format.is_empty() {
-                    let players = state.omegga.get_players().await?;
-                    let name = format_content(
-                        state.config.channel_name_online_format.to_owned(),
-                        &vec![Formatter {
-                            key: "n",
-                            value: players.len().to_string(),
-                        }],
-                    );
-                    if let Err(error) = state
-                        .http
-                        .update_channel(channel_id)
-                        .name(&name)?
-                        .exec()
-                        .await
-                    {
-                        state
-                            .omegga
-                            .error(format!("Error on updating channel: {}", error));
-                    }
-                }
-            }
-            rpc::Message::Notification { method, .. } if method == "chatcmd:discord" => {
-                state.omegga.broadcast(format!(
-                    "Join this server's <link=\"{}\">Discord</>.",
-                    state.config.discord_link
-                ))
-            }
-            rpc::Message::Notification { method, params, .. } if method == "cmd:discord" => {
-                let mut params = serde_json::from_value::<Vec<String>>(params.unwrap())
-                    .unwrap()
-                    .into_iter();
-                let user = params.next().unwrap();
-                let subcommand = match params.next() {
-                    Some(s) => s,
-                    None => {
-                        state
-                            .omegga
-                            .whisper(&user, "<color=\"a00\">Please specify a command to run.</>");
-                        continue;
-                    }
-                };
-                let _args = params.collect::<Vec<_>>();
-
-                match subcommand.as_str() {
-                    "wipe" => {
-                        let player = state.omegga.get_player(&user).await?.unwrap();
-                        if player.host.unwrap_or(false) {
-                            state.omegga.store_wipe();
-                            state.omegga.broadcast("Verification store has been wiped.");
+                    "init" => {
+                        started = true;
+                        config::reload_from_params(&state, params).await;
+                        verify::restore(&state).await;
+                        status::restore(&state).await;
+                        history::restore(&state).await;
+                        digest::restore(&state).await;
+                        if let Err(e) = shard.start().await {
+                            state
+                                .omegga
+                                .error(format!("Error connecting to Discord: {}", e));
                         }
+                        roles::spawn_reconciler(state.clone());
+                        status::spawn(state.clone());
+                        status::watch_gateway(state.clone(), shard.clone());
+                        rename::spawn(state.clone());
+                        queue::spawn(state.clone());
+                        config::spawn_watcher(state.clone());
+                        admin::spawn(state.clone());
+                        history::spawn(state.clone());
+                        digest::spawn(state.clone());
+                        omegga.write_response(
+                            id,
+                            Some(json!({"registeredCommands": ["discord"]})),
+                            None,
+                        );
                     }
-                    "verify" => {
-                        if !state.config.verification {
-                            continue;
-                        }
-
-                        let player = state.omegga.get_player(&user).await?.unwrap();
-
-                        // check if the user is already verified
-                        let entry = state.omegga.store_get(format!("g2d_{}", player.id)).await?;
-
-                        match entry {
-                            Some(_) => {
-                                // the user is verified
-                                state
-                                    .omegga
-                                    .whisper(&user, "<color=\"a00\">You are already verified!</>");
-                            }
-                            None => {
-                                // the user is not verified
-                                match state.verify_buffer.entry(player.id) {
-                                    Entry::Occupied(entry) => state.omegga.whisper(&user, format!(
-                                        "<color=\"a00\">You have already initiated the verification process! Send <code>{}verify {}</> in the game channel.</>",
-                                        state.config.discord_prefix,
-                                        entry.get()
-                                    )),
-                                    Entry::Vacant(entry) => {
-                                        let code = rand::thread_rng()
-                                            .sample_iter(&distributions::Alphanumeric)
-                                            .take(6)
-                                            .map(char::from)
-                                            .collect::<String>();
+                    "stop" => {
+                        // let staff know, sending it before the plugin is stopped
+                        admin::log(
+                            &state,
+                            AdminEvent::new(AdminKind::Stop, "The plugin is stopping."),
+                        );
+                        queue::send_waiting(&state).await;
 
-                                        let entry = entry.insert(code);
-                                        state.omegga.whisper(&user, format!(
-                                            "To verify, send <code>{}verify {}</> in the game channel.",
-                                            state.config.discord_prefix,
-                                            entry.value()
-                                        ));
-                                    }
-                                }
-                            }
-                        }
+                        // sessions are ended when it next starts, as of now
+                        history::checkpoint(&state);
+                        digest::persist(&state);
+                        omegga.write_response(id, None, None);
                     }
-
-                    _ => state.omegga.whisper(
-                        &user,
-                        format!(
-                            "Join this server's <link=\"{}\">Discord</>.",
-                            state.config.discord_link
-                        ),
-                    ),
+                    _ => (),
                 }
             }
+            rpc::Message::Notification { method, params, .. } => {
+                dispatcher.notify(&state, &method, params).await;
+            }
             _ => (),
         }
     }
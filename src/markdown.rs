@@ -0,0 +1,489 @@
+/// A node of a parsed Discord message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// Plain text.
+    Text(String),
+
+    /// A line break.
+    LineBreak,
+
+    /// `**bold**`
+    Bold(Vec<Node>),
+
+    /// `*italic*` or `_italic_`
+    Italic(Vec<Node>),
+
+    /// `__underline__`
+    Underline(Vec<Node>),
+
+    /// `~~strikethrough~~`
+    Strikethrough(Vec<Node>),
+
+    /// `||spoiler||`
+    Spoiler(Vec<Node>),
+
+    /// `` `code` ``, kept literally.
+    Code(String),
+
+    /// A fenced code block, kept literally.
+    CodeBlock {
+        language: Option<String>,
+        code: String,
+    },
+
+    /// A bare URL, a URL in angle brackets, or a masked `[label](url)` link.
+    Link { url: String, label: Vec<Node> },
+
+    /// A custom emoji, like `<:name:id>` or `<a:name:id>`.
+    Emoji { name: String, id: String },
+
+    /// A user mention, like `<@id>` or `<@!id>`.
+    UserMention(String),
+
+    /// A role mention, like `<@&id>`.
+    RoleMention(String),
+
+    /// A channel mention, like `<#id>`.
+    ChannelMention(String),
+
+    /// A timestamp, like `<t:1618953630:R>`, with its style. The style is `f` if none is
+    /// given.
+    Timestamp { time: i64, style: char },
+
+    /// A block quote, either `> line` or `>>> rest of message`.
+    Quote(Vec<Node>),
+
+    /// A header, like `# text`.
+    Header { level: usize, children: Vec<Node> },
+
+    /// A list item, like `- item` or `1. item`.
+    ListItem {
+        marker: Option<String>,
+        children: Vec<Node>,
+    },
+}
+
+/// Parse a Discord message into a list of nodes.
+pub fn parse(source: &str) -> Vec<Node> {
+    let chars = source.chars().collect::<Vec<_>>();
+    Parser { chars: &chars }.parse(0, chars.len(), true)
+}
+
+/// Characters that can be escaped with a backslash.
+const ESCAPABLE: &str = "\\*_~`|>#-:[]()<";
+
+/// Characters trimmed from the end of a bare URL.
+const URL_TRAILING: &str = ".,:;\"')]!?";
+
+struct Parser<'a> {
+    chars: &'a [char],
+}
+
+impl<'a> Parser<'a> {
+    fn at(&self, i: usize) -> Option<char> {
+        self.chars.get(i).copied()
+    }
+
+    fn starts_with(&self, i: usize, end: usize, pattern: &str) -> bool {
+        (i..)
+            .zip(pattern.chars())
+            .all(|(j, c)| j < end && self.chars[j] == c)
+    }
+
+    fn is_word(&self, i: Option<usize>) -> bool {
+        i.and_then(|i| self.at(i))
+            .map(|c| c.is_alphanumeric() || c == '_')
+            .unwrap_or(false)
+    }
+
+    fn collect(&self, from: usize, to: usize) -> String {
+        self.chars[from..to].iter().collect()
+    }
+
+    fn line_end(&self, i: usize, end: usize) -> usize {
+        (i..end).find(|&j| self.chars[j] == '\n').unwrap_or(end)
+    }
+
+    /// Parse the range `from..to`. Line-level constructs (quotes, headers, lists) are
+    /// only recognized when `block` is set.
+    fn parse(&self, from: usize, to: usize, block: bool) -> Vec<Node> {
+        let mut nodes = vec![];
+        let mut text = String::new();
+        let mut i = from;
+
+        macro_rules! push {
+            ($node:expr) => {{
+                if !text.is_empty() {
+                    nodes.push(Node::Text(std::mem::take(&mut text)));
+                }
+                nodes.push($node);
+            }};
+        }
+
+        while i < to {
+            if block && (i == from || self.chars[i - 1] == '\n') {
+                if let Some((node, next)) = self.block(i, to) {
+                    push!(node);
+                    i = next;
+                    continue;
+                }
+            }
+
+            if let Some((node, next)) = self.inline(i, to) {
+                push!(node);
+                i = next;
+                continue;
+            }
+
+            match self.chars[i] {
+                '\n' => push!(Node::LineBreak),
+                '\\' if i + 1 < to && ESCAPABLE.contains(self.chars[i + 1]) => {
+                    text.push(self.chars[i + 1]);
+                    i += 1;
+                }
+                c => text.push(c),
+            }
+            i += 1;
+        }
+
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+
+        nodes
+    }
+
+    /// Try to parse a line-level construct at the start of a line.
+    fn block(&self, i: usize, end: usize) -> Option<(Node, usize)> {
+        if self.starts_with(i, end, ">>> ") {
+            return Some((Node::Quote(self.parse(i + 4, end, false)), end));
+        }
+
+        let line_end = self.line_end(i, end);
+
+        if self.starts_with(i, end, "> ") {
+            return Some((Node::Quote(self.parse(i + 2, line_end, false)), line_end));
+        }
+
+        let level = (i..line_end).take_while(|&j| self.chars[j] == '#').count();
+        if (1..=3).contains(&level) && self.at(i + level) == Some(' ') {
+            return Some((
+                Node::Header {
+                    level,
+                    children: self.parse(i + level + 1, line_end, false),
+                },
+                line_end,
+            ));
+        }
+
+        if self.starts_with(i, end, "- ") || self.starts_with(i, end, "* ") {
+            return Some((
+                Node::ListItem {
+                    marker: None,
+                    children: self.parse(i + 2, line_end, false),
+                },
+                line_end,
+            ));
+        }
+
+        let digits = (i..line_end)
+            .take_while(|&j| self.chars[j].is_ascii_digit())
+            .count();
+        if digits > 0 && self.starts_with(i + digits, line_end, ". ") {
+            return Some((
+                Node::ListItem {
+                    marker: Some(self.collect(i, i + digits)),
+                    children: self.parse(i + digits + 2, line_end, false),
+                },
+                line_end,
+            ));
+        }
+
+        None
+    }
+
+    /// Try to parse an inline construct.
+    fn inline(&self, i: usize, end: usize) -> Option<(Node, usize)> {
+        match self.chars[i] {
+            '`' => self.code_block(i, end).or_else(|| self.code(i, end)),
+            '<' => self.angle(i, end),
+            '[' => self.masked_link(i, end),
+            'h' => self.url(i, end),
+            '*' => self
+                .span(i, end, "**", Node::Bold)
+                .or_else(|| self.span(i, end, "*", Node::Italic)),
+            '_' => self
+                .span(i, end, "__", Node::Underline)
+                .or_else(|| self.span(i, end, "_", Node::Italic)),
+            '~' => self.span(i, end, "~~", Node::Strikethrough),
+            '|' => self.span(i, end, "||", Node::Spoiler),
+            _ => None,
+        }
+    }
+
+    /// Parse a fenced code block.
+    fn code_block(&self, i: usize, end: usize) -> Option<(Node, usize)> {
+        if !self.starts_with(i, end, "```") {
+            return None;
+        }
+
+        let start = i + 3;
+        let close = (start..end).find(|&j| self.starts_with(j, end, "```"))?;
+
+        let mut code = self.collect(start, close);
+        let mut language = None;
+        if let Some((first, rest)) = code.split_once('\n') {
+            if !first.is_empty()
+                && first
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_+-.".contains(c))
+            {
+                language = Some(first.to_owned());
+                code = rest.to_owned();
+            }
+        }
+
+        let code = code.trim_matches('\n').to_owned();
+        if code.is_empty() {
+            return None;
+        }
+
+        Some((Node::CodeBlock { language, code }, close + 3))
+    }
+
+    /// Parse an inline code span, delimited by runs of the same number of backticks.
+    fn code(&self, i: usize, end: usize) -> Option<(Node, usize)> {
+        let ticks = (i..end).take_while(|&j| self.chars[j] == '`').count();
+        let start = i + ticks;
+
+        let mut j = start;
+        while j < end {
+            let run = (j..end).take_while(|&k| self.chars[k] == '`').count();
+            if run == ticks && j > start {
+                let code = self.collect(start, j);
+                let code = match code.strip_prefix(' ').and_then(|c| c.strip_suffix(' ')) {
+                    Some(c) if !c.trim().is_empty() => c.to_owned(),
+                    _ => code,
+                };
+                return Some((Node::Code(code), j + run));
+            }
+            j += run.max(1);
+        }
+
+        None
+    }
+
+    /// Parse a token in angle brackets: mentions, timestamps, custom emoji and unembedded links.
+    fn angle(&self, i: usize, end: usize) -> Option<(Node, usize)> {
+        let close = (i + 1..end)
+            .take_while(|&j| !self.chars[j].is_whitespace())
+            .find(|&j| self.chars[j] == '>')?;
+        let inner = self.collect(i + 1, close);
+
+        let is_id = |id: &str| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit());
+        let node = if let Some(id) = inner.strip_prefix("@&") {
+            if !is_id(id) {
+                return None;
+            }
+            Node::RoleMention(id.to_owned())
+        } else if let Some(id) = inner.strip_prefix('@') {
+            let id = id.strip_prefix('!').unwrap_or(id);
+            if !is_id(id) {
+                return None;
+            }
+            Node::UserMention(id.to_owned())
+        } else if let Some(id) = inner.strip_prefix('#') {
+            if !is_id(id) {
+                return None;
+            }
+            Node::ChannelMention(id.to_owned())
+        } else if let Some(timestamp) = inner.strip_prefix("t:") {
+            let (time, style) = match timestamp.split_once(':') {
+                Some((time, style)) if style.len() == 1 && "tTdDfFR".contains(style) => {
+                    (time, style.chars().next()?)
+                }
+                Some(_) => return None,
+                None => (timestamp, 'f'),
+            };
+            Node::Timestamp {
+                time: time.parse().ok()?,
+                style,
+            }
+        } else if inner.starts_with(':') || inner.starts_with("a:") {
+            let mut parts = inner.split(':').skip(1);
+            let name = parts.next()?;
+            let id = parts.next()?;
+            if name.is_empty()
+                || id.is_empty()
+                || !id.chars().all(|c| c.is_ascii_digit())
+                || parts.next().is_some()
+            {
+                return None;
+            }
+            Node::Emoji {
+                name: name.to_owned(),
+                id: id.to_owned(),
+            }
+        } else if inner.starts_with("http://") || inner.starts_with("https://") {
+            Node::Link {
+                url: inner.clone(),
+                label: vec![Node::Text(inner)],
+            }
+        } else {
+            return None;
+        };
+
+        Some((node, close + 1))
+    }
+
+    /// Parse a bare URL.
+    fn url(&self, i: usize, end: usize) -> Option<(Node, usize)> {
+        if !(self.starts_with(i, end, "http://") || self.starts_with(i, end, "https://"))
+            || self.is_word(i.checked_sub(1))
+        {
+            return None;
+        }
+
+        let mut close = (i..end)
+            .find(|&j| self.chars[j].is_whitespace() || self.chars[j] == '<')
+            .unwrap_or(end);
+        while close > i && URL_TRAILING.contains(self.chars[close - 1]) {
+            close -= 1;
+        }
+
+        let url = self.collect(i, close);
+        if url.ends_with("://") {
+            return None;
+        }
+
+        Some((
+            Node::Link {
+                url: url.clone(),
+                label: vec![Node::Text(url)],
+            },
+            close,
+        ))
+    }
+
+    /// Parse a masked link, like `[label](https://example.com)`.
+    fn masked_link(&self, i: usize, end: usize) -> Option<(Node, usize)> {
+        let line_end = self.line_end(i, end);
+        let label_end = (i + 1..line_end).find(|&j| self.chars[j] == ']')?;
+        if label_end == i + 1 || self.at(label_end + 1) != Some('(') {
+            return None;
+        }
+
+        let url_start = label_end + 2;
+        let url_end = (url_start..line_end).find(|&j| self.chars[j] == ')')?;
+        let url = self.collect(url_start, url_end);
+        if !(url.starts_with("http://") || url.starts_with("https://"))
+            || url.contains(char::is_whitespace)
+        {
+            return None;
+        }
+
+        Some((
+            Node::Link {
+                url,
+                label: self.parse(i + 1, label_end, false),
+            },
+            url_end + 1,
+        ))
+    }
+
+    /// Parse a span of text wrapped in `delim`, like `**bold**`.
+    fn span(
+        &self,
+        i: usize,
+        end: usize,
+        delim: &str,
+        wrap: fn(Vec<Node>) -> Node,
+    ) -> Option<(Node, usize)> {
+        let len = delim.chars().count();
+        let marker = delim.chars().next()?;
+        let start = i + len;
+
+        if !self.starts_with(i, end, delim) || start >= end {
+            return None;
+        }
+
+        // single-character delimiters need to hug their content, and underscores only
+        // count at word boundaries so snake_case is left alone
+        if len == 1 {
+            if self.chars[start].is_whitespace() || self.chars[start] == marker {
+                return None;
+            }
+            if marker == '_' && self.is_word(i.checked_sub(1)) {
+                return None;
+            }
+        }
+
+        let close = self.find_close(start, end, delim)?;
+        Some((wrap(self.parse(start, close, false)), close + len))
+    }
+
+    /// Find the closing delimiter of a span, skipping over escapes, code and links.
+    fn find_close(&self, from: usize, end: usize, delim: &str) -> Option<usize> {
+        let len = delim.chars().count();
+        let marker = delim.chars().next()?;
+        let mut j = from;
+
+        while j < end {
+            match self.chars[j] {
+                '\\' => {
+                    j += 2;
+                    continue;
+                }
+                '`' => {
+                    if let Some((_, next)) = self.code_block(j, end).or_else(|| self.code(j, end)) {
+                        j = next;
+                        continue;
+                    }
+                }
+                'h' => {
+                    if let Some((_, next)) = self.url(j, end) {
+                        j = next;
+                        continue;
+                    }
+                }
+                '<' => {
+                    if let Some((_, next)) = self.angle(j, end) {
+                        j = next;
+                        continue;
+                    }
+                }
+                _ => (),
+            }
+
+            if j > from && self.starts_with(j, end, delim) {
+                let after = self.at(j + len).filter(|_| j + len < end);
+
+                if len == 1 {
+                    // a doubled single delimiter is a nested span, so skip both
+                    if after == Some(marker) {
+                        j += 2;
+                        continue;
+                    }
+                    if self.chars[j - 1].is_whitespace() {
+                        j += 1;
+                        continue;
+                    }
+                    if marker == '_' && self.is_word(Some(j + 1)) && j + 1 < end {
+                        j += 1;
+                        continue;
+                    }
+                    return Some(j);
+                }
+
+                // prefer the outermost closing delimiter, so `***a***` nests properly
+                if after != Some(marker) {
+                    return Some(j);
+                }
+            }
+
+            j += 1;
+        }
+
+        None
+    }
+}
@@ -0,0 +1,427 @@
+use omegga::resources::Player;
+use twilight_model::{
+    application::command::{ChoiceCommandOptionData, Command, CommandOption},
+    id::RoleId,
+};
+
+use crate::{
+    admin::{self, AdminEvent, AdminKind},
+    commands::{self, Invoker},
+    config::Config,
+    discord,
+    error::Result,
+    format::{describe_duration, escape_markdown, format_to_game, sanitize},
+    State,
+};
+
+/// The moderation commands, which only members with `admin-roles` or `moderation-roles` can run.
+pub const COMMANDS: &[&str] = &["kick", "ban", "unban", "mute", "broadcast", "clearbricks"];
+
+/// How many matching players to list when a name is ambiguous.
+const AMBIGUOUS_LIMIT: usize = 10;
+
+/// Parse a `moderation-roles` entry, formatted like `COMMAND:ROLE ID`, where the command may
+/// be `*` for every command.
+pub fn parse_role(entry: &str) -> Option<(String, RoleId)> {
+    let (command, role) = entry.split_once(':')?;
+    let command = command.trim();
+    if command != "*" && !COMMANDS.contains(&command) {
+        return None;
+    }
+
+    match role.trim().parse() {
+        Ok(role) if role != 0 => Some((command.to_owned(), RoleId(role))),
+        _ => None,
+    }
+}
+
+/// Whether anyone can run moderation commands.
+pub fn enabled(config: &Config) -> bool {
+    !config.admin_roles.is_empty() || !config.moderation_roles.is_empty()
+}
+
+/// Whether the invoker may run a moderation command.
+fn allowed(state: &State, invoker: &Invoker, command: &str) -> bool {
+    if invoker.is_admin(state) {
+        return true;
+    }
+
+    let roles = invoker.member_roles(state);
+    state
+        .config()
+        .moderation_roles
+        .iter()
+        .filter_map(|e| parse_role(e))
+        .any(|(c, role)| (c == "*" || c == command) && roles.contains(&role))
+}
+
+/// The application commands for moderation.
+pub fn definitions() -> Vec<Command> {
+    let option = |name: &str, description: &str, required: bool| {
+        CommandOption::String(ChoiceCommandOptionData {
+            choices: vec![],
+            description: description.into(),
+            name: name.into(),
+            required,
+        })
+    };
+    let player = || option("player", "The name or UUID of the player.", true);
+    let duration = || option("duration", "How long for, like 30m, 12h, 7d or perm.", true);
+    let reason = || option("reason", "Why, which is shown to the player.", false);
+
+    vec![
+        commands::command(
+            "kick",
+            "Kick a player from the game.",
+            vec![player(), reason()],
+        ),
+        commands::command(
+            "ban",
+            "Ban a player from the game.",
+            vec![player(), duration(), reason()],
+        ),
+        commands::command(
+            "unban",
+            "Unban a player.",
+            vec![option(
+                "player",
+                "The name or UUID of the banned player.",
+                true,
+            )],
+        ),
+        commands::command(
+            "mute",
+            "Mute a player in game chat.",
+            vec![player(), duration()],
+        ),
+        commands::command(
+            "broadcast",
+            "Send a message to everyone in game.",
+            vec![option("message", "The message to send.", true)],
+        ),
+        commands::command("clearbricks", "Clear a player's bricks.", vec![player()]),
+    ]
+}
+
+/// The result of looking up a player by part of their name.
+#[derive(Debug, Clone)]
+pub enum Resolved<T = Player> {
+    Found(T),
+    NotFound,
+
+    /// More than one player matched, with the names of the first few.
+    Ambiguous(Vec<String>),
+}
+
+/// Find an online player by their UUID, or by their name or part of it, ignoring case.
+///
+/// An exact name is always preferred, so `Zeb` isn't ambiguous when `Zebra` is online too.
+pub fn resolve(players: Vec<Player>, query: &str) -> Resolved {
+    resolve_by(players, query, |p| (&p.id, &p.name))
+}
+
+/// Find a player like [`resolve`], in anything with a UUID and a name.
+pub fn resolve_by<T>(
+    mut players: Vec<T>,
+    query: &str,
+    id_name: impl Fn(&T) -> (&str, &str),
+) -> Resolved<T> {
+    let query = query.to_lowercase();
+    let exact = players.iter().position(|p| {
+        let (id, name) = id_name(p);
+        id == query || name.to_lowercase() == query
+    });
+    if let Some(i) = exact {
+        return Resolved::Found(players.swap_remove(i));
+    }
+
+    let mut matches = players
+        .into_iter()
+        .filter(|p| id_name(p).1.to_lowercase().contains(&query))
+        .collect::<Vec<_>>();
+
+    match matches.len() {
+        0 => Resolved::NotFound,
+        1 => Resolved::Found(matches.remove(0)),
+        _ => Resolved::Ambiguous(
+            matches
+                .iter()
+                .take(AMBIGUOUS_LIMIT)
+                .map(|p| id_name(p).1.to_owned())
+                .collect(),
+        ),
+    }
+}
+
+/// Find an online player, or describe why they couldn't be found.
+async fn find_player(state: &State, query: &str) -> Result<std::result::Result<Player, String>> {
+    if query.is_empty() {
+        return Ok(Err("**Please give the name of a player.**".into()));
+    }
+
+    Ok(match resolve(state.omegga.get_players().await?, query) {
+        Resolved::Found(player) => Ok(player),
+        Resolved::NotFound => Err(format!(
+            "**No player online matches {}.**",
+            escape_markdown(query)
+        )),
+        Resolved::Ambiguous(names) => Err(format!(
+            "**More than one player matches {}:** {}",
+            escape_markdown(query),
+            names
+                .iter()
+                .map(|n| escape_markdown(n))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    })
+}
+
+/// Parse a duration like `90s`, `30m`, `12h`, `7d` or `2w` into seconds. Plain numbers are
+/// minutes, and `perm` is permanent, as `None`.
+pub fn parse_duration(text: &str) -> Option<Option<u64>> {
+    if ["perm", "permanent", "forever"].contains(&text) {
+        return Some(None);
+    }
+
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let n = text[..split].parse::<u64>().ok().filter(|n| *n > 0)?;
+    let unit = match &text[split..] {
+        "s" => 1,
+        "" | "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 604800,
+        _ => return None,
+    };
+
+    // brickadia takes the length in minutes as an i64, so anything longer won't fit
+    n.checked_mul(unit)
+        .filter(|seconds| *seconds <= i64::MAX as u64)
+        .map(Some)
+}
+
+/// Quote an argument to a console command, so it can't end the quotes or the line.
+pub(crate) fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "'").replace(['\n', '\r'], " "))
+}
+
+/// Split the first word off of some arguments.
+pub fn first_word(args: &str) -> (&str, &str) {
+    match args.split_once(char::is_whitespace) {
+        Some((first, rest)) => (first, rest.trim()),
+        None => (args, ""),
+    }
+}
+
+/// Run a moderation command, returning the reply.
+pub async fn run(state: &State, invoker: &Invoker, command: &str, args: &str) -> Result<String> {
+    if !allowed(state, invoker, command) {
+        return Ok(String::from(
+            "**You don't have permission to run that command!**",
+        ));
+    }
+
+    let (query, rest) = first_word(args);
+
+    // the target as shown in the reply and the admin log, and what was done to them
+    let (target, details, reply) = match command {
+        "kick" => {
+            let player = match find_player(state, query).await? {
+                Ok(player) => player,
+                Err(reply) => return Ok(reply),
+            };
+
+            state.omegga.writeln(format!(
+                "Chat.Command /Kick {} {}",
+                quote(&player.name),
+                quote(rest)
+            ));
+            (
+                Some(escape_markdown(&player.name)),
+                describe_reason(rest),
+                format!("**Kicked {}.**", escape_markdown(&player.name)),
+            )
+        }
+        "ban" | "mute" => {
+            let player = match find_player(state, query).await? {
+                Ok(player) => player,
+                Err(reply) => return Ok(reply),
+            };
+
+            let (duration, reason) = first_word(rest);
+            let seconds = match parse_duration(duration) {
+                Some(seconds) => seconds,
+                None => {
+                    return Ok(String::from(
+                        "**Please give a duration like 30m, 12h, 7d or perm.**",
+                    ))
+                }
+            };
+
+            // brickadia takes durations in minutes, with -1 being permanent
+            let minutes = seconds.map_or(-1, |s| s.div_ceil(60) as i64);
+            let length = match seconds {
+                Some(seconds) => format!("for {}", describe_duration(seconds)),
+                None => "permanently".to_owned(),
+            };
+
+            let (console, verb) = if command == "ban" {
+                ("Ban", "Banned")
+            } else {
+                ("Mute", "Muted")
+            };
+            state.omegga.writeln(format!(
+                "Chat.Command /{} {} {} {}",
+                console,
+                quote(&player.name),
+                minutes,
+                quote(reason)
+            ));
+            (
+                Some(escape_markdown(&player.name)),
+                format!("{} {}. {}", verb, length, describe_reason(reason)),
+                format!("**{} {} {}.**", verb, escape_markdown(&player.name), length),
+            )
+        }
+        "unban" => {
+            if query.is_empty() {
+                return Ok(String::from(
+                    "**Please give the name or UUID of a player.**",
+                ));
+            }
+
+            state
+                .omegga
+                .writeln(format!("Chat.Command /Unban {}", quote(query)));
+            (
+                Some(escape_markdown(query)),
+                String::new(),
+                format!("**Unbanned {}.**", escape_markdown(query)),
+            )
+        }
+        "broadcast" => {
+            if args.is_empty() {
+                return Ok(String::from("**Please give a message to send.**"));
+            }
+
+            let trusted = invoker
+                .member_roles(state)
+                .iter()
+                .any(|r| state.config().trusted_roles.contains(&r.to_string()));
+            let mentions = discord::mention_names(state, invoker.guild_id, args, &[]).await?;
+            state.omegga.broadcast(format!(
+                "<b>{}</>: {}",
+                sanitize(&invoker.name),
+                format_to_game(args.to_owned(), trusted, &mentions)
+            ));
+            (
+                None,
+                escape_markdown(args),
+                String::from("**Sent to everyone in game.**"),
+            )
+        }
+        "clearbricks" => {
+            let player = match find_player(state, query).await? {
+                Ok(player) => player,
+                Err(reply) => return Ok(reply),
+            };
+
+            state.omegga.clear_bricks(&player.id);
+            (
+                Some(escape_markdown(&player.name)),
+                String::from("Cleared their bricks."),
+                format!("**Cleared {}'s bricks.**", escape_markdown(&player.name)),
+            )
+        }
+        _ => return Ok(String::from("**Unknown command.**")),
+    };
+
+    state.omegga.log(format!(
+        "{} ran {} from Discord with {}",
+        invoker.name, command, args
+    ));
+    admin::log(
+        state,
+        AdminEvent {
+            actor: Some(format!("<@{}>", invoker.id)),
+            target,
+            ..AdminEvent::new(
+                AdminKind::Moderation,
+                format!("`{}` {}", command, details).trim_end(),
+            )
+        },
+    );
+
+    Ok(reply)
+}
+
+fn describe_reason(reason: &str) -> String {
+    match reason {
+        "" => String::from("No reason given."),
+        reason => format!("Reason: {}", escape_markdown(reason)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use omegga::resources::Player;
+    use twilight_model::id::RoleId;
+
+    use super::{parse_duration, parse_role, resolve, Resolved};
+
+    fn player(name: &str) -> Player {
+        Player {
+            name: name.into(),
+            id: name.to_lowercase(),
+            controller: String::new(),
+            state: String::new(),
+            host: Some(false),
+        }
+    }
+
+    #[test]
+    fn partial_names_are_resolved() {
+        let players = vec![player("Zeb"), player("Zebra"), player("alice")];
+
+        assert!(matches!(
+            resolve(players.clone(), "zeb"),
+            Resolved::Found(p) if p.name == "Zeb"
+        ));
+        assert!(matches!(
+            resolve(players.clone(), "LIC"),
+            Resolved::Found(p) if p.name == "alice"
+        ));
+        assert!(matches!(
+            resolve(players.clone(), "e"),
+            Resolved::Ambiguous(names) if names == ["Zeb", "Zebra", "alice"]
+        ));
+        assert!(matches!(resolve(players, "bob"), Resolved::NotFound));
+    }
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("30"), Some(Some(1800)));
+        assert_eq!(parse_duration("12h"), Some(Some(43200)));
+        assert_eq!(parse_duration("perm"), Some(None));
+        assert_eq!(parse_duration("0d"), None);
+        assert_eq!(parse_duration("5y"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("99999999999999999w"), None);
+        assert_eq!(parse_duration("9223372036854775808s"), None);
+        assert_eq!(
+            parse_duration("9223372036854775807s"),
+            Some(Some(i64::MAX as u64))
+        );
+    }
+
+    #[test]
+    fn moderation_roles_are_parsed() {
+        assert_eq!(parse_role("kick:5"), Some(("kick".into(), RoleId(5))));
+        assert_eq!(parse_role("*:5"), Some(("*".into(), RoleId(5))));
+        assert_eq!(parse_role("explode:5"), None);
+        assert_eq!(parse_role("kick:mods"), None);
+    }
+}
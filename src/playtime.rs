@@ -0,0 +1,266 @@
+use twilight_model::application::command::{
+    ChoiceCommandOptionData, Command, CommandOption, CommandOptionChoice,
+};
+
+use crate::{
+    commands::{self, Invoker},
+    error::Result,
+    format::{describe_duration, escape_markdown, sanitize},
+    history::{self, Known},
+    link,
+    moderation::{first_word, Resolved},
+    verify::now,
+    State,
+};
+
+/// The commands for playtime and when players were last seen, in Discord and in game.
+pub const COMMANDS: &[&str] = &["playtime", "lastseen", "top"];
+
+/// How many players the leaderboard shows, unless asked for more or fewer.
+const TOP_DEFAULT: usize = 10;
+
+/// The most players the leaderboard can show.
+const TOP_LIMIT: usize = 25;
+
+/// The application commands for playtime.
+pub fn definitions() -> Vec<Command> {
+    let player = |description: &str, required: bool| {
+        CommandOption::String(ChoiceCommandOptionData {
+            choices: vec![],
+            description: description.into(),
+            name: "player".into(),
+            required,
+        })
+    };
+
+    vec![
+        commands::command(
+            "playtime",
+            "Show how long a player has played for.",
+            vec![player(
+                "The name or UUID of the player. Leave blank for your own.",
+                false,
+            )],
+        ),
+        commands::command(
+            "lastseen",
+            "Show when a player was last online.",
+            vec![player("The name or UUID of the player.", true)],
+        ),
+        commands::command(
+            "top",
+            "Show a leaderboard.",
+            vec![
+                CommandOption::String(ChoiceCommandOptionData {
+                    choices: vec![CommandOptionChoice::String {
+                        name: "playtime".into(),
+                        value: "playtime".into(),
+                    }],
+                    description: "The leaderboard to show.".into(),
+                    name: "board".into(),
+                    required: true,
+                }),
+                CommandOption::Integer(ChoiceCommandOptionData {
+                    choices: vec![],
+                    description: format!("How many players to show, up to {}.", TOP_LIMIT),
+                    name: "count".into(),
+                    required: false,
+                }),
+            ],
+        ),
+    ]
+}
+
+/// Describe some playtime, like `5h 12m`.
+pub fn describe_playtime(seconds: u64) -> String {
+    match seconds / 60 {
+        minutes if minutes >= 60 => format!("{}h {}m", minutes / 60, minutes % 60),
+        minutes => format!("{}m", minutes),
+    }
+}
+
+/// Parse the arguments to `top`, returning how many players to show.
+fn parse_top(args: &str) -> std::result::Result<usize, &'static str> {
+    let (board, count) = first_word(args);
+    if !board.is_empty() && !board.eq_ignore_ascii_case("playtime") {
+        return Err("The only leaderboard is playtime.");
+    }
+
+    match count {
+        "" => Ok(TOP_DEFAULT),
+        count => match count.parse::<usize>() {
+            Ok(count) if count > 0 => Ok(count.min(TOP_LIMIT)),
+            _ => Err("Please give a number of players to show."),
+        },
+    }
+}
+
+/// Why a player couldn't be found by `query`, escaping names with `escape`.
+fn describe_missing(query: &str, resolved: Resolved<Known>, escape: fn(&str) -> String) -> String {
+    match resolved {
+        Resolved::Ambiguous(names) => format!(
+            "More than one player matches {}: {}",
+            escape(query),
+            names
+                .iter()
+                .map(|n| escape(n))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        _ => format!("No player has been seen matching {}.", escape(query)),
+    }
+}
+
+/// Run a playtime command from Discord, returning the reply.
+pub async fn run(state: &State, invoker: &Invoker, command: &str, args: &str) -> Result<String> {
+    if command == "top" {
+        return Ok(match parse_top(args) {
+            Ok(count) if top(state, count).is_empty() => "**Nobody has played yet.**".into(),
+            Ok(count) => top(state, count).into_iter().fold(
+                String::from("**Most playtime**"),
+                |text, (rank, name, time)| {
+                    format!("{}\n{}. {}: {}", text, rank, escape_markdown(&name), time)
+                },
+            ),
+            Err(reason) => format!("**{}**", reason),
+        });
+    }
+
+    let player = if args.is_empty() && command == "playtime" {
+        let id = match link::linked_player(state, invoker.id).await? {
+            Some(id) => id,
+            None => {
+                return Ok(String::from(
+                    "**You are not verified!** Give the name of a player to look them up.",
+                ))
+            }
+        };
+        Known {
+            name: state
+                .history
+                .get(&id)
+                .map_or_else(|| id.clone(), |r| r.name),
+            id,
+        }
+    } else if args.is_empty() {
+        return Ok(String::from("**Please give the name of a player.**"));
+    } else {
+        match history::find(state, args).await? {
+            Resolved::Found(player) => player,
+            resolved => {
+                return Ok(format!(
+                    "**{}**",
+                    describe_missing(args, resolved, escape_markdown)
+                ))
+            }
+        }
+    };
+
+    let record = state.history.get(&player.id);
+    let name = escape_markdown(&player.name);
+    Ok(match (command, record) {
+        ("playtime", Some(record)) => format!(
+            "**{}** has played for {} since first joining <t:{}:D>.",
+            name,
+            describe_playtime(record.playtime(now())),
+            record.first_seen
+        ),
+        (_, Some(record)) if record.session_start.is_none() => {
+            format!("**{}** was last seen <t:{}:R>.", name, record.last_seen)
+        }
+        (_, Some(_)) => format!("**{}** is online now.", name),
+        (_, None) => format!("**{}** hasn't been seen yet.", name),
+    })
+}
+
+/// Run a playtime command for someone in game, whispering the result to them.
+pub async fn run_in_game(state: &State, user: &str, command: &str, args: &str) -> Result<()> {
+    let whisper = |line: String| state.omegga.whisper(user, line);
+
+    if command == "top" {
+        match parse_top(args) {
+            Ok(count) if top(state, count).is_empty() => {
+                whisper("<color=\"a00\">Nobody has played yet.</>".into())
+            }
+            Ok(count) => {
+                whisper("<b>Most playtime</>".into());
+                for (rank, name, time) in top(state, count) {
+                    whisper(format!("{}. {}: {}", rank, sanitize(&name), time));
+                }
+            }
+            Err(reason) => whisper(format!("<color=\"a00\">{}</>", reason)),
+        }
+        return Ok(());
+    }
+
+    // players can look themselves up
+    let query = match args {
+        "" if command == "playtime" => user,
+        "" => {
+            whisper("<color=\"a00\">Please give the name of a player.</>".into());
+            return Ok(());
+        }
+        args => args,
+    };
+
+    let player = match history::find(state, query).await? {
+        Resolved::Found(player) => player,
+        resolved => {
+            whisper(format!(
+                "<color=\"a00\">{}</>",
+                describe_missing(query, resolved, sanitize)
+            ));
+            return Ok(());
+        }
+    };
+
+    let record = state.history.get(&player.id);
+    let name = sanitize(&player.name);
+    whisper(match (command, record) {
+        ("playtime", Some(record)) => format!(
+            "<b>{}</> has played for {}.",
+            name,
+            describe_playtime(record.playtime(now()))
+        ),
+        (_, Some(record)) if record.session_start.is_none() => format!(
+            "<b>{}</> was last seen {} ago.",
+            name,
+            describe_duration(now().saturating_sub(record.last_seen))
+        ),
+        (_, Some(_)) => format!("<b>{}</> is online now.", name),
+        (_, None) => format!("<b>{}</> hasn't been seen yet.", name),
+    });
+    Ok(())
+}
+
+/// The leaderboard for playtime, as each player's rank, name and playtime.
+fn top(state: &State, count: usize) -> Vec<(usize, String, String)> {
+    state
+        .history
+        .top_playtime(count, now())
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, playtime))| (i + 1, name, describe_playtime(playtime)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{describe_playtime, parse_top, TOP_DEFAULT, TOP_LIMIT};
+
+    #[test]
+    fn playtime_is_described_in_hours_and_minutes() {
+        assert_eq!(describe_playtime(59), "0m");
+        assert_eq!(describe_playtime(600), "10m");
+        assert_eq!(describe_playtime(3600 * 5 + 60 * 12 + 30), "5h 12m");
+    }
+
+    #[test]
+    fn leaderboard_sizes_are_parsed() {
+        assert_eq!(parse_top(""), Ok(TOP_DEFAULT));
+        assert_eq!(parse_top("playtime 3"), Ok(3));
+        assert_eq!(parse_top("playtime 1000"), Ok(TOP_LIMIT));
+        assert!(parse_top("playtime 0").is_err());
+        assert!(parse_top("kills").is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use tokio::sync::{Mutex as AsyncMutex, Notify};
+use twilight_model::{
+    channel::embed::Embed,
+    id::{ChannelId, UserId},
+};
+
+use crate::{
+    discord,
+    format::{Formatter, MESSAGE_LIMIT},
+    State,
+};
+
+/// How many times to try sending a message before giving up on it.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// How long to wait before the first retry, doubling after each one.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The in-game player a message is relayed from.
+#[derive(Debug, Clone)]
+pub struct Author {
+    pub name: String,
+
+    /// The player's UUID, if known.
+    pub id: Option<String>,
+
+    /// The formatters describing the player, for the webhook's username and avatar.
+    pub formatters: Vec<Formatter>,
+}
+
+/// A message waiting to be sent to a Discord channel.
+#[derive(Debug, Clone)]
+pub struct Outgoing {
+    pub channel_id: ChannelId,
+
+    /// The player the message is from, or `None` if it's from the server.
+    pub author: Option<Author>,
+    pub content: String,
+    pub embeds: Vec<Embed>,
+
+    /// The users the message may mention.
+    pub mentions: Vec<UserId>,
+
+    /// Whether the message may be sent together with the ones around it.
+    pub batch: bool,
+}
+
+impl Outgoing {
+    /// Whether another message can be added onto this one.
+    fn can_batch(&self, next: &Outgoing, by_author: bool) -> bool {
+        let same_author = || match (&self.author, &next.author) {
+            (Some(a), Some(b)) => a.name == b.name,
+            (None, None) => true,
+            _ => false,
+        };
+
+        self.batch
+            && next.batch
+            && self.channel_id == next.channel_id
+            && (!by_author || same_author())
+            && self.content.len() + 1 + next.content.len() <= MESSAGE_LIMIT
+    }
+
+    fn append(&mut self, next: Outgoing) {
+        self.content.push('\n');
+        self.content.push_str(&next.content);
+        for user in next.mentions {
+            if !self.mentions.contains(&user) {
+                self.mentions.push(user);
+            }
+        }
+    }
+}
+
+/// What to do with a new message when the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Drop the oldest waiting message to make room.
+    Oldest,
+
+    /// Drop the new message.
+    Newest,
+}
+
+impl DropPolicy {
+    pub fn parse(policy: &str) -> Self {
+        match policy {
+            "newest" => Self::Newest,
+            _ => Self::Oldest,
+        }
+    }
+}
+
+/// Messages waiting to be sent to Discord, in order.
+///
+/// Pushing never waits on Discord, so the RPC loop is never held up by it.
+pub struct Queue {
+    items: Mutex<VecDeque<Outgoing>>,
+    notify: Notify,
+
+    /// Held while sending, so only one task sends at a time and messages stay in order.
+    sending: AsyncMutex<()>,
+    capacity: usize,
+    policy: DropPolicy,
+}
+
+impl Queue {
+    pub fn new(capacity: usize, policy: DropPolicy) -> Self {
+        Self {
+            items: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+            sending: AsyncMutex::new(()),
+            capacity: capacity.max(1),
+            policy,
+        }
+    }
+
+    /// Queue a message to be sent. Returns `false` if a message had to be dropped.
+    pub fn push(&self, message: Outgoing) -> bool {
+        let mut items = self.items.lock().unwrap();
+        let full = items.len() >= self.capacity;
+
+        match (full, self.policy) {
+            (true, DropPolicy::Newest) => return false,
+            (true, DropPolicy::Oldest) => {
+                items.pop_front();
+            }
+            _ => (),
+        }
+
+        items.push_back(message);
+        self.notify.notify_one();
+        !full
+    }
+
+    /// Take the next message to send, along with any waiting messages it can be sent with.
+    ///
+    /// Messages only pile up while Discord is slower than the game, so this only batches
+    /// when traffic is high. With `by_author`, only messages from the same player are batched,
+    /// as they are sent under that player's name.
+    fn pop_batch(&self, by_author: bool) -> Option<Outgoing> {
+        let mut items = self.items.lock().unwrap();
+        let mut message = items.pop_front()?;
+
+        while let Some(next) = items.front() {
+            if !message.can_batch(next, by_author) {
+                break;
+            }
+
+            if let Some(next) = items.pop_front() {
+                message.append(next);
+            }
+        }
+
+        Some(message)
+    }
+}
+
+/// Queue a message to be sent to Discord, warning if one had to be dropped.
+pub fn push(state: &State, message: Outgoing) {
+    if !state.queue.push(message) {
+        state
+            .omegga
+            .warn("The Discord message queue is full, so a message was dropped.");
+    }
+}
+
+/// Send a message, retrying with backoff when it fails for a reason that might go away.
+async fn send(state: &State, message: &Outgoing) {
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let error = match discord::relay(state, message).await {
+            Ok(_) => return,
+            Err(e) => e,
+        };
+
+        // not forwarded to discord, where it would most likely fail again
+        if !error.is_transient() || attempt == MAX_ATTEMPTS {
+            state
+                .omegga
+                .local_error(format!("Error sending message to Discord: {}", error));
+            return;
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+/// Send every waiting message, one at a time so they stay in order.
+///
+/// This waits for any other task that's sending to finish first, like the background sender
+/// when the plugin stops.
+pub async fn send_waiting(state: &State) {
+    let _sending = state.queue.sending.lock().await;
+    while let Some(message) = state.queue.pop_batch(state.webhook.is_some()) {
+        send(state, &message).await;
+    }
+}
+
+/// Send queued messages in the background.
+pub fn spawn(state: State) {
+    tokio::spawn(async move {
+        loop {
+            send_waiting(&state).await;
+            state.queue.notify.notified().await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use twilight_model::id::ChannelId;
+
+    use super::{Author, DropPolicy, Outgoing, Queue};
+
+    fn chat(name: &str, content: &str) -> Outgoing {
+        Outgoing {
+            channel_id: ChannelId(1),
+            author: Some(Author {
+                name: name.into(),
+                id: None,
+                formatters: vec![],
+            }),
+            content: content.into(),
+            embeds: vec![],
+            mentions: vec![],
+            batch: true,
+        }
+    }
+
+    #[test]
+    fn consecutive_messages_are_batched() {
+        let queue = Queue::new(10, DropPolicy::Oldest);
+        queue.push(chat("a", "one"));
+        queue.push(chat("b", "two"));
+        queue.push(Outgoing {
+            batch: false,
+            ..chat("a", "**a joined the game.**")
+        });
+        queue.push(chat("a", "three"));
+
+        assert_eq!(queue.pop_batch(false).unwrap().content, "one\ntwo");
+        assert_eq!(
+            queue.pop_batch(false).unwrap().content,
+            "**a joined the game.**"
+        );
+        assert_eq!(queue.pop_batch(false).unwrap().content, "three");
+        assert!(queue.pop_batch(false).is_none());
+    }
+
+    #[test]
+    fn webhook_batches_are_by_author() {
+        let queue = Queue::new(10, DropPolicy::Oldest);
+        queue.push(chat("a", "one"));
+        queue.push(chat("a", "two"));
+        queue.push(chat("b", "three"));
+
+        assert_eq!(queue.pop_batch(true).unwrap().content, "one\ntwo");
+        assert_eq!(queue.pop_batch(true).unwrap().content, "three");
+    }
+
+    #[test]
+    fn batches_fit_in_a_message() {
+        let queue = Queue::new(10, DropPolicy::Oldest);
+        queue.push(chat("a", &"x".repeat(1500)));
+        queue.push(chat("a", &"y".repeat(1500)));
+
+        assert_eq!(queue.pop_batch(false).unwrap().content.len(), 1500);
+        assert_eq!(queue.pop_batch(false).unwrap().content.len(), 1500);
+    }
+
+    #[test]
+    fn full_queues_drop_by_policy() {
+        let queue = Queue::new(2, DropPolicy::Oldest);
+        assert!(queue.push(chat("a", "one")));
+        assert!(queue.push(chat("b", "two")));
+        assert!(!queue.push(chat("c", "three")));
+        assert_eq!(queue.pop_batch(true).unwrap().content, "two");
+
+        let queue = Queue::new(2, DropPolicy::Newest);
+        queue.push(chat("a", "one"));
+        queue.push(chat("b", "two"));
+        assert!(!queue.push(chat("c", "three")));
+        assert_eq!(queue.pop_batch(true).unwrap().content, "one");
+    }
+}
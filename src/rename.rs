@@ -0,0 +1,176 @@
+use std::{collections::VecDeque, time::Duration};
+
+use tokio::sync::Notify;
+use twilight_model::id::ChannelId;
+
+use crate::{
+    error::{Error, Result},
+    format::format_content,
+    status,
+    verify::now,
+    State,
+};
+
+/// How many times Discord lets a channel be renamed per [`RENAME_WINDOW`] seconds.
+const RENAME_LIMIT: usize = 2;
+const RENAME_WINDOW: u64 = 600;
+
+/// How long to wait after something changes before renaming, so bursts of joins and
+/// leaves are only counted once.
+const DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// How long to wait before trying again after failing to render or rename, on top of
+/// waiting out the rename limit.
+const RETRY: Duration = Duration::from_secs(30);
+
+/// How often to check whether the channel has been cached yet, before the first rename.
+const CACHE_POLL: Duration = Duration::from_secs(1);
+
+/// The times recent renames were made at, to stay within Discord's rename limit.
+#[derive(Debug, Default)]
+struct RenameBudget {
+    renames: VecDeque<u64>,
+}
+
+impl RenameBudget {
+    /// How many seconds until another rename can be made at `now`.
+    fn wait(&self, now: u64) -> u64 {
+        if self.renames.len() < RENAME_LIMIT {
+            return 0;
+        }
+
+        self.renames
+            .front()
+            .map(|t| (t + RENAME_WINDOW).saturating_sub(now))
+            .unwrap_or(0)
+    }
+
+    fn record(&mut self, now: u64) {
+        self.renames.push_back(now);
+        while self.renames.len() > RENAME_LIMIT {
+            self.renames.pop_front();
+        }
+    }
+}
+
+/// The channel renamed by `channel-name-online-format`.
+pub struct ChannelName {
+    channel_id: Option<ChannelId>,
+    notify: Notify,
+}
+
+impl ChannelName {
+    /// A channel name, renaming `channel_id` if given or the bridge channel otherwise.
+    pub fn new(format: &str, channel_id: Option<ChannelId>, bridge: ChannelId) -> Self {
+        Self {
+            channel_id: (!format.is_empty()).then(|| channel_id.unwrap_or(bridge)),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Ask for the channel's name to be brought up to date.
+    pub fn request_update(&self) {
+        if self.channel_id.is_some() {
+            self.notify.notify_one();
+        }
+    }
+}
+
+/// Render the channel name with the current server info.
+async fn render(state: &State) -> Result<String> {
+    let info = status::gather(state).await?;
+    Ok(format_content(
+        state.config().channel_name_online_format.clone(),
+        &info.formatters(),
+    ))
+}
+
+/// Keep the channel's name up to date in the background, if `channel-name-online-format` is set.
+///
+/// Changes are coalesced, and the channel is only renamed when its name would change and
+/// Discord's rename limit allows it, always catching up to the latest name eventually.
+pub fn spawn(state: State) {
+    let channel_id = match state.channel_name.channel_id {
+        Some(id) => id,
+        None => return,
+    };
+
+    tokio::spawn(async move {
+        let mut budget = RenameBudget::default();
+
+        // the channel is only cached once the gateway has sent its guild, and until then its
+        // name is unknown, so renaming would use up the budget even if the name is the same
+        let mut current = loop {
+            match state.cache.guild_channel(channel_id) {
+                Some(channel) => break channel.name().to_owned(),
+                None => tokio::time::sleep(CACHE_POLL).await,
+            }
+        };
+
+        loop {
+            // wait out the rename limit before working out the name, so it's the latest
+            let wait = budget.wait(now());
+            if wait > 0 {
+                tokio::time::sleep(Duration::from_secs(wait)).await;
+            }
+
+            let name = match render(&state).await {
+                Ok(name) => name,
+                Err(e) => {
+                    state
+                        .omegga
+                        .error(format!("Error rendering channel name: {}", e));
+                    tokio::time::sleep(RETRY).await;
+                    continue;
+                }
+            };
+
+            if current != name {
+                match state.discord.rename_channel(channel_id, name.clone()).await {
+                    Ok(_) => {
+                        budget.record(now());
+                        current = name;
+                    }
+                    // the name was rejected before it was sent, so the rename wasn't used up
+                    Err(e @ Error::Request(_)) => state
+                        .omegga
+                        .error(format!("Invalid channel name {:?}: {}", name, e)),
+                    // try again rather than waiting for something else to change
+                    Err(e) => {
+                        budget.record(now());
+                        state
+                            .omegga
+                            .error(format!("Error on updating channel: {}", e));
+                        tokio::time::sleep(RETRY).await;
+                        continue;
+                    }
+                }
+            }
+
+            state.channel_name.notify.notified().await;
+            tokio::time::sleep(DEBOUNCE).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RenameBudget, RENAME_WINDOW};
+
+    #[test]
+    fn budget_allows_two_renames_per_window() {
+        let mut budget = RenameBudget::default();
+        assert_eq!(budget.wait(100), 0);
+
+        budget.record(100);
+        assert_eq!(budget.wait(150), 0);
+
+        budget.record(150);
+        assert_eq!(budget.wait(200), RENAME_WINDOW - 100);
+        assert_eq!(budget.wait(100 + RENAME_WINDOW), 0);
+
+        // the oldest rename falls out of the window first
+        budget.record(100 + RENAME_WINDOW);
+        assert_eq!(budget.wait(100 + RENAME_WINDOW), 50);
+    }
+}
@@ -0,0 +1,296 @@
+use std::time::Duration;
+
+use omegga::resources::Player;
+use twilight_model::id::{RoleId, UserId};
+
+use crate::{
+    admin::{self, AdminEvent, AdminKind},
+    discord,
+    error::Result,
+    format::escape_markdown,
+    link,
+    moderation::quote,
+    State,
+};
+
+/// A Discord role that is kept in sync with a Brickadia role.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoleMapping {
+    pub discord: RoleId,
+    pub game: String,
+}
+
+/// Parse the `role-sync` entries, formatted like `DISCORD ROLE ID:BRICKADIA ROLE`.
+/// Invalid entries are skipped.
+pub fn parse_mappings(entries: &[String]) -> Vec<RoleMapping> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let (id, game) = entry.split_once(':')?;
+            Some(RoleMapping {
+                discord: RoleId(id.trim().parse().ok()?),
+                game: game.trim().to_owned(),
+            })
+        })
+        .filter(|m| !m.game.is_empty())
+        .collect()
+}
+
+/// A change needed to bring someone's roles in sync.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoleChange {
+    GrantGame(String),
+    RevokeGame(String),
+    AddDiscord(RoleId),
+    RemoveDiscord(RoleId),
+}
+
+/// Work out the changes needed to sync a player's roles.
+///
+/// Discord is the source of truth, unless `reverse` is set, in which case the game is.
+pub fn diff(
+    mappings: &[RoleMapping],
+    discord_roles: &[RoleId],
+    game_roles: &[String],
+    reverse: bool,
+) -> Vec<RoleChange> {
+    mappings
+        .iter()
+        .filter_map(|m| {
+            let in_discord = discord_roles.contains(&m.discord);
+            let in_game = game_roles.iter().any(|r| r.eq_ignore_ascii_case(&m.game));
+
+            match (reverse, in_discord, in_game) {
+                (false, true, false) => Some(RoleChange::GrantGame(m.game.clone())),
+                (false, false, true) => Some(RoleChange::RevokeGame(m.game.clone())),
+                (true, false, true) => Some(RoleChange::AddDiscord(m.discord)),
+                (true, true, false) => Some(RoleChange::RemoveDiscord(m.discord)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Get the roles a Discord user has in the bridge channel's guild, checking the cache
+/// before asking Discord.
+async fn member_roles(state: &State, user: UserId) -> Result<Option<Vec<RoleId>>> {
+    let guild_id = match discord::guild_id(state) {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+
+    if let Some(member) = state.cache.member(guild_id, user) {
+        return Ok(Some(member.roles.clone()));
+    }
+
+    Ok(Some(state.discord.member_roles(guild_id, user).await?))
+}
+
+/// Sync the roles of an online player with their linked Discord user, if any.
+///
+/// `member_roles` can be given when the user's Discord roles are already known.
+pub async fn sync_player(
+    state: &State,
+    player: &Player,
+    member_roles: Option<Vec<RoleId>>,
+) -> Result<()> {
+    let mappings = parse_mappings(&state.config().role_sync);
+    if mappings.is_empty() {
+        return Ok(());
+    }
+
+    let user = match link::linked_user(state, &player.id).await? {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+
+    let discord_roles = match member_roles {
+        Some(roles) => roles,
+        None => match self::member_roles(state, user).await? {
+            Some(roles) => roles,
+            None => return Ok(()),
+        },
+    };
+
+    let game_roles = state
+        .omegga
+        .get_player_roles(&player.name)
+        .await?
+        .unwrap_or_default();
+
+    let changes = diff(
+        &mappings,
+        &discord_roles,
+        &game_roles,
+        state.config().role_sync_reverse,
+    );
+
+    for change in changes {
+        apply(state, player, user, change).await?;
+    }
+
+    Ok(())
+}
+
+/// Apply a role change, or log it in dry-run mode.
+async fn apply(state: &State, player: &Player, user: UserId, change: RoleChange) -> Result<()> {
+    if state.config().role_sync_dry_run {
+        state.omegga.log(format!(
+            "[role sync dry run] Would {}",
+            describe(player, user, &change)
+        ));
+        return Ok(());
+    }
+
+    let description = format!("Role sync: {}", describe(player, user, &change));
+    state.omegga.log(&description);
+    admin::log(
+        state,
+        AdminEvent {
+            target: Some(format!("{} (<@{}>)", escape_markdown(&player.name), user)),
+            ..AdminEvent::new(AdminKind::Roles, escape_markdown(&description))
+        },
+    );
+
+    match change {
+        RoleChange::GrantGame(role) => state.omegga.writeln(format!(
+            "Chat.Command /GrantRole {} {}",
+            quote(&role),
+            quote(&player.name)
+        )),
+        RoleChange::RevokeGame(role) => state.omegga.writeln(format!(
+            "Chat.Command /RevokeRole {} {}",
+            quote(&role),
+            quote(&player.name)
+        )),
+        RoleChange::AddDiscord(role) => {
+            if let Some(guild_id) = discord::guild_id(state) {
+                state.discord.add_role(guild_id, user, role).await?;
+            }
+        }
+        RoleChange::RemoveDiscord(role) => {
+            if let Some(guild_id) = discord::guild_id(state) {
+                state.discord.remove_role(guild_id, user, role).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn describe(player: &Player, user: UserId, change: &RoleChange) -> String {
+    match change {
+        RoleChange::GrantGame(role) => format!("grant {} to {} in game", role, player.name),
+        RoleChange::RevokeGame(role) => format!("revoke {} from {} in game", role, player.name),
+        RoleChange::AddDiscord(role) => format!("add role {} to {} in Discord", role, user),
+        RoleChange::RemoveDiscord(role) => {
+            format!("remove role {} from {} in Discord", role, user)
+        }
+    }
+}
+
+/// Sync the roles of a Discord user whose roles changed, if they're linked to an online player.
+pub async fn sync_user(state: &State, user: UserId, member_roles: Vec<RoleId>) -> Result<()> {
+    if state.config().role_sync.is_empty() {
+        return Ok(());
+    }
+
+    let player = match link::linked_player(state, user).await? {
+        Some(player) => player,
+        None => return Ok(()),
+    };
+
+    match state.omegga.get_player(&player).await? {
+        Some(player) => sync_player(state, &player, Some(member_roles)).await,
+        None => Ok(()),
+    }
+}
+
+/// Sync the roles of every online player.
+pub async fn reconcile(state: &State) -> Result<()> {
+    for player in state.omegga.get_players().await? {
+        if let Err(e) = sync_player(state, &player, None).await {
+            state
+                .omegga
+                .error(format!("Error syncing roles for {}: {}", player.name, e));
+        }
+    }
+
+    Ok(())
+}
+
+/// Periodically reconcile roles every `role-sync-interval` seconds, if role sync is enabled.
+pub fn spawn_reconciler(state: State) {
+    if state.config().role_sync.is_empty() || state.config().role_sync_interval == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(state.config().role_sync_interval));
+
+        loop {
+            interval.tick().await;
+            if let Err(e) = reconcile(&state).await {
+                state
+                    .omegga
+                    .error(format!("Error reconciling roles: {}", e));
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use twilight_model::id::RoleId;
+
+    use super::{diff, parse_mappings, RoleChange, RoleMapping};
+
+    fn mappings() -> Vec<RoleMapping> {
+        parse_mappings(&[
+            "1:Staff".into(),
+            "2: Donator ".into(),
+            "nope:Trusted".into(),
+            "3:".into(),
+        ])
+    }
+
+    #[test]
+    fn mappings_skip_invalid_entries() {
+        assert_eq!(
+            mappings(),
+            vec![
+                RoleMapping {
+                    discord: RoleId(1),
+                    game: "Staff".into()
+                },
+                RoleMapping {
+                    discord: RoleId(2),
+                    game: "Donator".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn discord_is_the_source_of_truth() {
+        assert_eq!(
+            diff(&mappings(), &[RoleId(1)], &["donator".into()], false),
+            vec![
+                RoleChange::GrantGame("Staff".into()),
+                RoleChange::RevokeGame("Donator".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn game_is_the_source_of_truth_in_reverse() {
+        assert_eq!(
+            diff(&mappings(), &[RoleId(1)], &["donator".into()], true),
+            vec![
+                RoleChange::RemoveDiscord(RoleId(1)),
+                RoleChange::AddDiscord(RoleId(2))
+            ]
+        );
+    }
+}
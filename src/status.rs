@@ -0,0 +1,504 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Notify;
+use twilight_gateway::Shard;
+use twilight_model::{
+    channel::embed::{Embed, EmbedField},
+    id::{ChannelId, MessageId},
+};
+
+use crate::{
+    backend::NewMessage,
+    channels::{self, EventKind},
+    commands,
+    error::Result,
+    format::{describe_duration, Formatter},
+    verify::now,
+    State,
+};
+
+/// The store key the status message and server info are persisted under.
+const STORE_KEY: &str = "status_message";
+
+/// How long to wait after something changes before updating, so bursts of changes are
+/// only shown once.
+const DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// How often to check the latency of Discord's gateway.
+const LATENCY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often to update even when nothing changes, to keep the uptime current.
+const REFRESH: Duration = Duration::from_secs(300);
+
+/// The longest an embed description can be.
+const DESCRIPTION_LIMIT: usize = 4096;
+
+/// What's persisted about the status message and the server.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Saved {
+    message_id: Option<u64>,
+    map: Option<String>,
+
+    /// The UNIX time in seconds the server last started at.
+    started: Option<u64>,
+}
+
+/// The formatters given by [`ServerInfo::formatters`]. `$n` is the same as `$players`, but is
+/// only documented for `channel-name-online-format`, where it's always been.
+pub const KEYS: &[&str] = &[
+    "players",
+    "n",
+    "max",
+    "map",
+    "uptime",
+    "bricks",
+    "minigames",
+    "latency",
+    "ping",
+];
+
+/// The live status message, kept up to date in `status-channel-id`.
+pub struct Status {
+    channel_id: Option<ChannelId>,
+    saved: Mutex<Saved>,
+    notify: Notify,
+
+    /// The latency of Discord's gateway, as of the last heartbeat.
+    gateway_latency: Mutex<Option<Duration>>,
+}
+
+impl Status {
+    pub fn new(channel_id: Option<ChannelId>) -> Self {
+        Self {
+            channel_id,
+            saved: Mutex::new(Saved::default()),
+            notify: Notify::new(),
+            gateway_latency: Mutex::new(None),
+        }
+    }
+
+    /// Ask for the status message to be updated soon.
+    pub fn request_update(&self) {
+        if self.channel_id.is_some() {
+            self.notify.notify_one();
+        }
+    }
+
+    /// Record that the server started on a map.
+    pub fn server_started(&self, map: String) {
+        let mut saved = self.saved.lock().unwrap();
+        saved.map = Some(map);
+        saved.started = Some(now());
+    }
+
+    /// Record that the server changed maps without restarting.
+    pub fn map_changed(&self, map: String) {
+        self.saved.lock().unwrap().map = Some(map);
+    }
+
+    /// The ID of the status message, if one has been posted.
+    pub fn message_id(&self) -> Option<MessageId> {
+        self.saved.lock().unwrap().message_id.map(MessageId)
+    }
+
+    /// The map the server last started on, if known.
+    pub fn map(&self) -> Option<String> {
+        self.saved.lock().unwrap().map.clone()
+    }
+
+    /// How many seconds the server has been up for.
+    pub fn uptime(&self) -> u64 {
+        let started = self.saved.lock().unwrap().started.unwrap_or_else(now);
+        now().saturating_sub(started)
+    }
+
+    /// Record the latency of Discord's gateway.
+    pub fn set_gateway_latency(&self, latency: Option<Duration>) {
+        *self.gateway_latency.lock().unwrap() = latency;
+    }
+
+    fn snapshot(&self) -> Saved {
+        self.saved.lock().unwrap().clone()
+    }
+}
+
+/// What's known about the server right now.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerInfo {
+    pub map: Option<String>,
+
+    /// The UNIX time in seconds the server last started at.
+    pub started: u64,
+    pub players: usize,
+    pub max_players: u64,
+
+    /// The number of bricks, if the server could say.
+    pub bricks: Option<u64>,
+
+    /// The names of the minigames being played, if the server could say.
+    pub minigames: Option<Vec<String>>,
+
+    /// How long omegga took to answer the plugin.
+    pub latency: Duration,
+
+    /// The latency of Discord's gateway, if a heartbeat has been answered.
+    pub bot_latency: Option<Duration>,
+}
+
+impl ServerInfo {
+    /// How many seconds the server has been up for.
+    pub fn uptime(&self) -> u64 {
+        now().saturating_sub(self.started)
+    }
+
+    /// The number of players online, against the maximum if it's set.
+    pub fn describe_players(&self) -> String {
+        match self.max_players {
+            0 => self.players.to_string(),
+            max => format!("{}/{}", self.players, max),
+        }
+    }
+
+    pub fn describe_map(&self) -> String {
+        self.map.clone().unwrap_or_else(|| "unknown".into())
+    }
+
+    pub fn describe_bricks(&self) -> String {
+        self.bricks
+            .map_or_else(|| "unknown".into(), |b| b.to_string())
+    }
+
+    pub fn describe_minigames(&self) -> String {
+        match &self.minigames {
+            Some(names) if names.is_empty() => "none".into(),
+            Some(names) => names.join(", "),
+            None => "unknown".into(),
+        }
+    }
+
+    pub fn describe_latency(&self) -> String {
+        format!("{} ms", self.latency.as_millis())
+    }
+
+    pub fn describe_bot_latency(&self) -> String {
+        self.bot_latency
+            .map_or_else(|| "unknown".into(), |l| format!("{} ms", l.as_millis()))
+    }
+
+    /// The formatters for templates, named in [`KEYS`].
+    pub fn formatters(&self) -> Vec<Formatter> {
+        vec![
+            Formatter {
+                key: "players",
+                value: self.players.to_string(),
+            },
+            Formatter {
+                key: "n",
+                value: self.players.to_string(),
+            },
+            Formatter {
+                key: "max",
+                value: self.max_players.to_string(),
+            },
+            Formatter {
+                key: "map",
+                value: self.describe_map(),
+            },
+            Formatter {
+                key: "uptime",
+                value: describe_duration(self.uptime()),
+            },
+            Formatter {
+                key: "bricks",
+                value: self.describe_bricks(),
+            },
+            Formatter {
+                key: "minigames",
+                value: self.describe_minigames(),
+            },
+            Formatter {
+                key: "latency",
+                value: self.describe_latency(),
+            },
+            Formatter {
+                key: "ping",
+                value: self.describe_bot_latency(),
+            },
+        ]
+    }
+}
+
+/// Read the names of the minigames being played, leaving out the global one every server has.
+fn parse_minigames(minigames: &Value) -> Option<Vec<String>> {
+    let field = |minigame: &Value, key: &str| {
+        minigame
+            .get(key)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned()
+    };
+
+    Some(
+        minigames
+            .as_array()?
+            .iter()
+            .filter(|m| field(m, "ruleset") != "GLOBAL")
+            .map(|m| match field(m, "name") {
+                name if name.is_empty() => field(m, "ruleset"),
+                name => name,
+            })
+            .collect(),
+    )
+}
+
+/// Find out about the server.
+pub async fn gather(state: &State) -> Result<ServerInfo> {
+    let sent = Instant::now();
+    let players = state.omegga.get_players().await?.len();
+    let latency = sent.elapsed();
+
+    // the server may not be able to answer these while it's starting, which is fine
+    let bricks = state
+        .omegga
+        .get_server_status()
+        .await
+        .ok()
+        .and_then(|s| s.get("bricks").and_then(Value::as_u64));
+    let minigames = match state.omegga.get_minigames().await {
+        Ok(minigames) => parse_minigames(&minigames),
+        Err(_) => None,
+    };
+
+    let saved = state.status.snapshot();
+    Ok(ServerInfo {
+        map: saved.map,
+        started: saved.started.unwrap_or_else(now),
+        players,
+        max_players: state.config().max_players,
+        bricks,
+        minigames,
+        latency,
+        bot_latency: *state.status.gateway_latency.lock().unwrap(),
+    })
+}
+
+/// The server info formatters for `option`, if any channel's template for it uses them.
+///
+/// Finding out about the server takes a few requests, so it's only done when needed.
+pub async fn formatters_for(state: &State, kind: EventKind, option: &str) -> Vec<Formatter> {
+    let config = state.config();
+    let used = channels::bindings(&config, state.channel_id)
+        .iter()
+        .filter(|b| b.receives(kind))
+        .any(|b| uses_keys(&b.format(&config, option)));
+    if !used {
+        return vec![];
+    }
+
+    match gather(state).await {
+        Ok(info) => info.formatters(),
+        Err(e) => {
+            state
+                .omegga
+                .error(format!("Error getting server info: {}", e));
+            vec![]
+        }
+    }
+}
+
+/// Whether a template uses any of the server info formatters.
+fn uses_keys(template: &str) -> bool {
+    template
+        .split('$')
+        .skip(1)
+        .any(|rest| KEYS.iter().any(|k| rest.starts_with(k)))
+}
+
+/// Load the status message and server info from the store.
+pub async fn restore(state: &State) {
+    match state.omegga.store_get(STORE_KEY).await {
+        Ok(Some(value)) => {
+            let mut saved = serde_json::from_value::<Saved>(value).unwrap_or_default();
+
+            // we don't know when the server started, so count from now
+            saved.started.get_or_insert_with(now);
+            *state.status.saved.lock().unwrap() = saved;
+        }
+        Ok(None) => state.status.saved.lock().unwrap().started = Some(now()),
+        Err(e) => state
+            .omegga
+            .error(format!("Error restoring status message: {}", e)),
+    }
+}
+
+/// Save the status message and server info to the store.
+pub fn persist(state: &State) {
+    if let Ok(value) = serde_json::to_value(state.status.snapshot()) {
+        state.omegga.store_set(STORE_KEY, value);
+    }
+}
+
+/// Keep track of the latency of Discord's gateway in the background.
+pub fn watch_gateway(state: State, shard: Arc<Shard>) {
+    tokio::spawn(async move {
+        loop {
+            // the shard has no session while it's reconnecting
+            let latency = shard
+                .info()
+                .ok()
+                .and_then(|info| info.latency().recent().back().copied());
+            state.status.set_gateway_latency(latency);
+
+            tokio::time::sleep(LATENCY_INTERVAL).await;
+        }
+    });
+}
+
+/// Keep the status message updated in the background, if `status-channel-id` is set.
+pub fn spawn(state: State) {
+    if state.status.channel_id.is_none() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = update(&state).await {
+                state
+                    .omegga
+                    .error(format!("Error updating status message: {}", e));
+            }
+
+            tokio::select! {
+                _ = state.status.notify.notified() => (),
+                _ = tokio::time::sleep(REFRESH) => (),
+            }
+            tokio::time::sleep(DEBOUNCE).await;
+        }
+    });
+}
+
+/// Edit the status message, posting a new one if it doesn't exist.
+async fn update(state: &State) -> Result<()> {
+    let channel_id = match state.status.channel_id {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+
+    let embeds = vec![embed(state).await?];
+
+    if let Some(message_id) = state.status.message_id() {
+        let result = state
+            .discord
+            .update_embeds(channel_id, message_id, embeds.clone())
+            .await;
+
+        match result {
+            Ok(_) => return Ok(()),
+            Err(e) if !e.is_status(404) => return Err(e),
+            // the message was deleted, so post a new one
+            Err(_) => (),
+        }
+    }
+
+    let message_id = state
+        .discord
+        .send_message(
+            channel_id,
+            NewMessage {
+                embeds,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+    state.status.saved.lock().unwrap().message_id = Some(message_id.0);
+    persist(state);
+    Ok(())
+}
+
+async fn embed(state: &State) -> Result<Embed> {
+    let info = gather(state).await?;
+    let players = commands::player_lines(state).await?;
+
+    let mut description = String::new();
+    for (i, line) in players.iter().enumerate() {
+        let more = format!("\n…and {} more", players.len() - i);
+        if description.len() + line.len() + 1 + more.len() > DESCRIPTION_LIMIT {
+            description.push_str(&more);
+            break;
+        }
+
+        if !description.is_empty() {
+            description.push('\n');
+        }
+        description.push_str(line);
+    }
+
+    if description.is_empty() {
+        description.push_str("*Nobody is online.*");
+    }
+
+    let field = |name: &str, value: String| EmbedField {
+        inline: true,
+        name: name.into(),
+        value,
+    };
+
+    Ok(Embed {
+        author: None,
+        color: Some(0x43b581),
+        description: Some(description),
+        fields: vec![
+            field("Players", info.describe_players()),
+            field("Map", info.map.clone().unwrap_or_else(|| "Unknown".into())),
+            field("Uptime", describe_duration(info.uptime())),
+            field("Last restart", format!("<t:{}:f>", info.started)),
+            field("Bricks", info.describe_bricks()),
+            field("Minigames", info.describe_minigames()),
+        ],
+        footer: None,
+        image: None,
+        kind: "rich".into(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: Some("Server status".into()),
+        url: None,
+        video: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{parse_minigames, uses_keys};
+
+    #[test]
+    fn minigames_are_parsed_without_the_global_one() {
+        let minigames = json!([
+            { "index": -1, "name": "GLOBAL", "ruleset": "GLOBAL" },
+            { "index": 0, "name": "Race", "ruleset": "Race" },
+            { "index": 1, "ruleset": "Deathmatch" },
+        ]);
+
+        assert_eq!(
+            parse_minigames(&minigames),
+            Some(vec!["Race".into(), "Deathmatch".into()])
+        );
+        assert_eq!(parse_minigames(&json!(null)), None);
+    }
+
+    #[test]
+    fn templates_using_server_info_are_found() {
+        assert!(uses_keys("$user joined ($n/$max)"));
+        assert!(uses_keys("Started with $bricks bricks"));
+        assert!(!uses_keys("**$user joined the game.**"));
+        assert!(!uses_keys("costs 5$"));
+    }
+}
@@ -0,0 +1,318 @@
+use std::{
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use dashmap::DashMap;
+use rand::{distributions, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use twilight_model::id::UserId;
+
+use crate::State;
+
+/// The store key pending verifications are persisted under.
+const STORE_KEY: &str = "verify_pending";
+
+/// The length of generated verification codes.
+const CODE_LENGTH: usize = 6;
+
+/// The current UNIX time in seconds.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// A player's pending verification.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pending {
+    pub code: String,
+
+    /// The UNIX time in seconds the code expires at.
+    pub expires: u64,
+}
+
+impl Pending {
+    pub fn is_expired(&self) -> bool {
+        now() >= self.expires
+    }
+
+    /// The number of seconds until the code expires.
+    pub fn remaining(&self) -> u64 {
+        self.expires.saturating_sub(now())
+    }
+}
+
+/// The failed attempts a Discord user has made at verifying.
+#[derive(Debug, Clone, Default)]
+struct Attempts {
+    failures: u32,
+    locked_until: u64,
+}
+
+/// The outcome of a Discord user trying a verification code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Attempt {
+    /// The code matched the pending verification of the player with this UUID.
+    Success(String),
+
+    /// The code matched the verification of the player with this UUID, but it has expired.
+    Expired(String),
+
+    /// The code doesn't match any pending verification.
+    Invalid { remaining: u32 },
+
+    /// The user has failed too many times, and must wait this many seconds.
+    Locked(u64),
+}
+
+/// Pending verification codes, and the attempts made at them.
+pub struct Verifications {
+    /// Player UUID to their pending verification.
+    pending: DashMap<String, Pending>,
+
+    /// Lowercase verification code to player UUID.
+    codes: DashMap<String, String>,
+
+    /// Discord user to their failed attempts.
+    attempts: DashMap<UserId, Attempts>,
+
+    ttl: AtomicU64,
+    max_attempts: AtomicU32,
+    cooldown: AtomicU64,
+}
+
+impl Verifications {
+    pub fn new(ttl: u64, max_attempts: u32, cooldown: u64) -> Self {
+        Self {
+            pending: DashMap::new(),
+            codes: DashMap::new(),
+            attempts: DashMap::new(),
+            ttl: AtomicU64::new(ttl),
+            max_attempts: AtomicU32::new(max_attempts.max(1)),
+            cooldown: AtomicU64::new(cooldown),
+        }
+    }
+
+    /// Change how long codes last and how many attempts can be made, for codes started and
+    /// attempts made from now on.
+    pub fn set_limits(&self, ttl: u64, max_attempts: u32, cooldown: u64) {
+        self.ttl.store(ttl, Ordering::Relaxed);
+        self.max_attempts
+            .store(max_attempts.max(1), Ordering::Relaxed);
+        self.cooldown.store(cooldown, Ordering::Relaxed);
+    }
+
+    /// Restore pending verifications saved with [`to_value`](Self::to_value), skipping
+    /// any that have expired since.
+    pub fn restore(&self, value: Value) {
+        let pending = serde_json::from_value::<Vec<(String, Pending)>>(value).unwrap_or_default();
+        for (player, pending) in pending.into_iter().filter(|(_, p)| !p.is_expired()) {
+            self.codes
+                .insert(pending.code.to_lowercase(), player.to_owned());
+            self.pending.insert(player, pending);
+        }
+    }
+
+    /// Serialize the pending verifications that haven't expired.
+    pub fn to_value(&self) -> Value {
+        let pending = self
+            .pending
+            .iter()
+            .filter(|r| !r.value().is_expired())
+            .map(|r| (r.key().to_owned(), r.value().clone()))
+            .collect::<Vec<_>>();
+
+        serde_json::to_value(pending).unwrap_or(Value::Null)
+    }
+
+    /// Get a player's pending verification, if any. This includes expired verifications.
+    pub fn pending(&self, player: &str) -> Option<Pending> {
+        self.pending.get(player).map(|r| r.value().clone())
+    }
+
+    /// Start a new verification for a player, replacing any previous one.
+    pub fn start(&self, player: &str) -> Pending {
+        self.remove(player);
+
+        // generate a code that isn't in use
+        let code = loop {
+            let code = rand::thread_rng()
+                .sample_iter(&distributions::Alphanumeric)
+                .take(CODE_LENGTH)
+                .map(|c| char::from(c).to_ascii_uppercase())
+                .collect::<String>();
+
+            if !self.codes.contains_key(&code.to_lowercase()) {
+                break code;
+            }
+        };
+
+        let pending = Pending {
+            code,
+            expires: now() + self.ttl.load(Ordering::Relaxed),
+        };
+
+        self.codes
+            .insert(pending.code.to_lowercase(), player.to_owned());
+        self.pending.insert(player.to_owned(), pending.clone());
+        pending
+    }
+
+    /// Remove a player's pending verification.
+    pub fn remove(&self, player: &str) {
+        if let Some((_, pending)) = self.pending.remove(player) {
+            self.codes.remove(&pending.code.to_lowercase());
+        }
+    }
+
+    /// Try a verification code on behalf of a Discord user.
+    pub fn attempt(&self, user: UserId, code: &str) -> Attempt {
+        let now = now();
+
+        let locked_until = self
+            .attempts
+            .get(&user)
+            .map(|a| a.locked_until)
+            .unwrap_or(0);
+        if locked_until > now {
+            return Attempt::Locked(locked_until - now);
+        }
+
+        let player = self
+            .codes
+            .get(&code.trim().to_lowercase())
+            .map(|r| r.value().to_owned());
+
+        if let Some(player) = player {
+            let expired = self
+                .pending
+                .get(&player)
+                .map(|p| p.is_expired())
+                .unwrap_or(true);
+            self.remove(&player);
+
+            if expired {
+                return Attempt::Expired(player);
+            }
+
+            self.attempts.remove(&user);
+            return Attempt::Success(player);
+        }
+
+        let max_attempts = self.max_attempts.load(Ordering::Relaxed);
+        let cooldown = self.cooldown.load(Ordering::Relaxed);
+
+        let mut attempts = self.attempts.entry(user).or_default();
+        attempts.failures += 1;
+        if attempts.failures >= max_attempts {
+            attempts.failures = 0;
+            attempts.locked_until = now + cooldown;
+            return Attempt::Locked(cooldown);
+        }
+
+        Attempt::Invalid {
+            remaining: max_attempts - attempts.failures,
+        }
+    }
+}
+
+/// Load pending verifications from the store, if they are persisted.
+pub async fn restore(state: &State) {
+    if !state.config().verify_persist {
+        return;
+    }
+
+    match state.omegga.store_get(STORE_KEY).await {
+        Ok(Some(value)) => state.verifications.restore(value),
+        Ok(None) => (),
+        Err(e) => state
+            .omegga
+            .error(format!("Error restoring pending verifications: {}", e)),
+    }
+}
+
+/// Save pending verifications to the store, if they are persisted.
+pub fn persist(state: &State) {
+    if state.config().verify_persist {
+        state
+            .omegga
+            .store_set(STORE_KEY, state.verifications.to_value());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use twilight_model::id::UserId;
+
+    use super::{Attempt, Verifications};
+
+    #[test]
+    fn codes_are_case_insensitive() {
+        let verifications = Verifications::new(600, 3, 60);
+        let code = verifications.start("player").code;
+
+        assert_eq!(
+            verifications.attempt(UserId(1), &code.to_lowercase()),
+            Attempt::Success("player".into())
+        );
+        assert!(verifications.pending("player").is_none());
+    }
+
+    #[test]
+    fn expired_codes_are_rejected() {
+        let verifications = Verifications::new(0, 3, 60);
+        let code = verifications.start("player").code;
+
+        assert_eq!(
+            verifications.attempt(UserId(1), &code),
+            Attempt::Expired("player".into())
+        );
+        assert_eq!(
+            verifications.attempt(UserId(1), &code),
+            Attempt::Invalid { remaining: 2 }
+        );
+    }
+
+    #[test]
+    fn failed_attempts_lock_out() {
+        let verifications = Verifications::new(600, 3, 60);
+        let code = verifications.start("player").code;
+
+        assert_eq!(
+            verifications.attempt(UserId(1), "nope"),
+            Attempt::Invalid { remaining: 2 }
+        );
+        assert_eq!(
+            verifications.attempt(UserId(1), "nope"),
+            Attempt::Invalid { remaining: 1 }
+        );
+        assert_eq!(
+            verifications.attempt(UserId(1), "nope"),
+            Attempt::Locked(60)
+        );
+        assert_eq!(verifications.attempt(UserId(1), &code), Attempt::Locked(60));
+
+        // other users aren't affected
+        assert_eq!(
+            verifications.attempt(UserId(2), &code),
+            Attempt::Success("player".into())
+        );
+    }
+
+    #[test]
+    fn pending_verifications_round_trip() {
+        let verifications = Verifications::new(600, 3, 60);
+        let code = verifications.start("player").code;
+
+        let restored = Verifications::new(600, 3, 60);
+        restored.restore(verifications.to_value());
+
+        assert_eq!(
+            restored.attempt(UserId(1), &code),
+            Attempt::Success("player".into())
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use twilight_http::Client as HttpClient;
+use twilight_model::id::{ChannelId, UserId, WebhookId};
+
+use crate::{
+    error::{Error, Result},
+    format::{format_content, Formatter},
+    link, State,
+};
+
+/// The name given to webhooks created by the plugin.
+const WEBHOOK_NAME: &str = "omegga-discord-lite";
+
+/// A Discord webhook that game messages can be executed through.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub id: WebhookId,
+    pub token: String,
+}
+
+impl Webhook {
+    /// Parse a webhook from its URL, like `https://discord.com/api/webhooks/<id>/<token>`.
+    pub fn parse(url: &str) -> Option<Self> {
+        let mut parts = url.trim_end_matches('/').rsplit('/');
+        let token = parts.next()?;
+        let id = parts.next()?.parse().ok()?;
+
+        match parts.next() {
+            Some("webhooks") if !token.is_empty() => Some(Self {
+                id: WebhookId(id),
+                token: token.to_owned(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Find the plugin's webhook in a channel, or create it if it doesn't exist.
+    pub async fn find_or_create(http: &HttpClient, channel_id: ChannelId) -> Result<Self> {
+        let existing = http
+            .channel_webhooks(channel_id)
+            .exec()
+            .await?
+            .model()
+            .await?
+            .into_iter()
+            .find(|w| w.name.as_deref() == Some(WEBHOOK_NAME) && w.token.is_some());
+
+        let webhook = match existing {
+            Some(w) => w,
+            None => {
+                http.create_webhook(channel_id, WEBHOOK_NAME)
+                    .exec()
+                    .await?
+                    .model()
+                    .await?
+            }
+        };
+
+        Ok(Self {
+            id: webhook.id,
+            token: webhook
+                .token
+                .ok_or_else(|| Error::Request("created webhook has no token".into()))?,
+        })
+    }
+}
+
+/// Pick an avatar URL for a player.
+///
+/// Verified players use the avatar of their linked Discord account. Otherwise, the
+/// `webhook-avatar-format` is used, falling back to one of Discord's default avatars
+/// chosen by the player's UUID.
+pub async fn avatar_url(state: &State, id: &str, formatters: &[Formatter]) -> String {
+    if let Ok(Some(user)) = link::linked_user(state, id).await {
+        if let Some(url) = discord_avatar_url(state, user).await {
+            return url;
+        }
+    }
+
+    if !state.config().webhook_avatar_format.is_empty() {
+        return format_content(
+            state.config().webhook_avatar_format.clone(),
+            &[
+                formatters,
+                &[Formatter {
+                    key: "id",
+                    value: id.to_owned(),
+                }],
+            ]
+            .concat(),
+        );
+    }
+
+    let index = id.bytes().map(u32::from).sum::<u32>() % 5;
+    format!("https://cdn.discordapp.com/embed/avatars/{}.png", index)
+}
+
+/// Get the avatar URL of a Discord user, checking the cache before asking Discord.
+async fn discord_avatar_url(state: &State, id: UserId) -> Option<String> {
+    let avatar = match state.cache.user(id) {
+        Some(user) => user.avatar.clone(),
+        None => state.discord.user_avatar(id).await.ok()?,
+    }?;
+
+    Some(format!(
+        "https://cdn.discordapp.com/avatars/{}/{}.png",
+        id, avatar
+    ))
+}
@@ -0,0 +1,159 @@
+use twilight_model::id::UserId;
+
+use crate::{
+    commands,
+    error::Result,
+    format::{escape_markdown, sanitize},
+    history::{self, Known},
+    link,
+    moderation::Resolved,
+    State,
+};
+
+/// A player's name, if they're online or have been seen before.
+pub async fn player_name(state: &State, id: &str) -> Result<Option<String>> {
+    if let Some(player) = state.omegga.get_player(id).await? {
+        return Ok(Some(player.name));
+    }
+
+    Ok(state.history.get(id).map(|r| r.name))
+}
+
+/// A Discord user's tag, if they're cached.
+fn user_tag(state: &State, user: UserId) -> Option<String> {
+    state
+        .cache
+        .user(user)
+        .map(|u| format!("{}#{}", u.name, u.discriminator))
+}
+
+/// Look up a player or Discord user, describing how they're linked and when they were last
+/// seen.
+pub async fn run(state: &State, query: &str) -> Result<String> {
+    if query.is_empty() {
+        return Ok(String::from(
+            "**Please give a player's name or UUID, or mention a Discord user.**",
+        ));
+    }
+
+    let player = match commands::parse_user(query) {
+        Some(user) => match link::linked_player(state, user).await? {
+            Some(id) => Known {
+                name: player_name(state, &id).await?.unwrap_or_else(|| id.clone()),
+                id,
+            },
+            None => return Ok(format!("**<@{}> isn't linked to a player.**", user)),
+        },
+        None => match history::find(state, query).await? {
+            Resolved::Found(player) => player,
+            Resolved::NotFound => {
+                return Ok(format!(
+                    "**No player has been seen matching {}.**",
+                    escape_markdown(query)
+                ))
+            }
+            Resolved::Ambiguous(names) => {
+                return Ok(format!(
+                    "**More than one player matches {}:** {}",
+                    escape_markdown(query),
+                    names
+                        .iter()
+                        .map(|n| escape_markdown(n))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }
+        },
+    };
+
+    let discord = match link::linked_user(state, &player.id).await? {
+        Some(user) => {
+            let verified = match link::linked_at(state, &player.id).await? {
+                Some(time) => format!("<t:{}:f>", time),
+                None => "Unknown".into(),
+            };
+            let tag = user_tag(state, user)
+                .map(|t| format!(" ({})", escape_markdown(&t)))
+                .unwrap_or_default();
+            format!("<@{}>{}\n**Verified:** {}", user, tag, verified)
+        }
+        None => "Not linked".into(),
+    };
+
+    let roles = state
+        .omegga
+        .get_player_roles(&player.id)
+        .await?
+        .unwrap_or_default();
+    let roles = if roles.is_empty() {
+        "None".into()
+    } else {
+        escape_markdown(&roles.join(", "))
+    };
+
+    let status = match state.omegga.get_player(&player.id).await? {
+        Some(_) => "Online".to_owned(),
+        None => match state.history.get(&player.id) {
+            Some(record) => format!("Offline, last seen <t:{}:R>", record.last_seen),
+            None => "Offline, never seen".to_owned(),
+        },
+    };
+
+    Ok(format!(
+        "**{}** (`{}`)\n**Discord:** {}\n**Roles:** {}\n**Status:** {}",
+        escape_markdown(&player.name),
+        player.id,
+        discord,
+        roles,
+        status
+    ))
+}
+
+/// Look up a player for someone in game, telling them who they're linked to on Discord.
+pub async fn run_in_game(state: &State, user: &str, query: &str) -> Result<()> {
+    if query.is_empty() {
+        state
+            .omegga
+            .whisper(user, "<color=\"a00\">Please give the name of a player.</>");
+        return Ok(());
+    }
+
+    let player = match history::find(state, query).await? {
+        Resolved::Found(player) => player,
+        Resolved::NotFound => {
+            state.omegga.whisper(
+                user,
+                format!(
+                    "<color=\"a00\">No player has been seen matching <b>{}</>.</>",
+                    sanitize(query)
+                ),
+            );
+            return Ok(());
+        }
+        Resolved::Ambiguous(names) => {
+            state.omegga.whisper(
+                user,
+                format!(
+                    "<color=\"a00\">More than one player matches <b>{}</>:</> {}",
+                    sanitize(query),
+                    sanitize(&names.join(", "))
+                ),
+            );
+            return Ok(());
+        }
+    };
+
+    let line = match link::linked_user(state, &player.id).await? {
+        Some(discord) => format!(
+            "<b>{}</> is linked to <b>{}</> on Discord.",
+            sanitize(&player.name),
+            sanitize(&user_tag(state, discord).unwrap_or_else(|| discord.to_string()))
+        ),
+        None => format!(
+            "<b>{}</> isn't linked to a Discord account.",
+            sanitize(&player.name)
+        ),
+    };
+    state.omegga.whisper(user, line);
+    Ok(())
+}